@@ -36,6 +36,30 @@ macro_rules! map_marshalers {
     }}
 }
 
+/// Pairs a type containing the placeholder `T` with its input and return marshalers, which are
+/// the same unless given as `input | return`.
+macro_rules! generic_marshalers {
+    [$($rust:ty => $input:path $(| $ret:path)?,)*] => {{
+        let mut list = Vec::<(Type, syn::Path, syn::Path)>::new();
+        $({
+            let input: syn::Path = syn::parse2(quote!{ $input })
+                .expect(concat!("cannot parse", stringify!($input), "as path"));
+            let ret = None
+                $(.or(Some(syn::parse2::<syn::Path>(quote!{ $ret })
+                    .expect(concat!("cannot parse", stringify!($ret), "as path")))))?
+                .unwrap_or_else(|| input.clone());
+
+            list.push((
+                syn::parse2(quote!{ $rust })
+                    .expect(concat!("cannot parse", stringify!($rust), "as type")),
+                input,
+                ret,
+            ));
+        })*
+        list
+    }}
+}
+
 macro_rules! type_array {
     [$($rust:ty,)*] => {{
         vec![
@@ -49,6 +73,41 @@ macro_rules! type_array {
 fn main() {
     let default_marshalers: HashMap<Type, syn::Path> = map_marshalers![
         bool => ::cffi::BoolMarshaler,
+        char => ::cffi::CharMarshaler,
+        i128 => ::cffi::Int128Marshaler::<i128>,
+        u128 => ::cffi::Int128Marshaler::<u128>,
+        String => ::cffi::StringMarshaler,
+        Result<String, Box<dyn Error>> => ::cffi::StringMarshaler,
+        &str => ::cffi::StrMarshaler,
+        PathBuf => ::cffi::PathBufMarshaler,
+        Result<PathBuf, Box<dyn Error>> => ::cffi::PathBufMarshaler,
+        Url => ::cffi::UrlMarshaler,
+        Result<Url, Box<dyn Error>> => ::cffi::UrlMarshaler,
+        Result<(), Box<dyn Error>> => ::cffi::UnitMarshaler,
+        // Zero from C would be an invalid value, so these are checked unless inside an `Option`.
+        NonZeroU8 => ::cffi::NonZeroMarshaler::<u8>,
+        NonZeroI8 => ::cffi::NonZeroMarshaler::<i8>,
+        NonZeroU16 => ::cffi::NonZeroMarshaler::<u16>,
+        NonZeroI16 => ::cffi::NonZeroMarshaler::<i16>,
+        NonZeroU32 => ::cffi::NonZeroMarshaler::<u32>,
+        NonZeroI32 => ::cffi::NonZeroMarshaler::<i32>,
+        NonZeroU64 => ::cffi::NonZeroMarshaler::<u64>,
+        NonZeroI64 => ::cffi::NonZeroMarshaler::<i64>,
+        NonZeroUsize => ::cffi::NonZeroMarshaler::<usize>,
+        NonZeroIsize => ::cffi::NonZeroMarshaler::<isize>,
+    ];
+
+    // Tried in order after the exact matches above. `T` matches any type other than a trait
+    // object and `dyn T` any trait object, and the matched type is substituted for `T`. `Vec<T>`,
+    // `&[T]` and `Option<T>` depend on whether `T` is passed through, so they are resolved in the
+    // registry.
+    let generic_marshalers = generic_marshalers![
+        Box<T> => ::cffi::BoxMarshaler::<T>,
+        // Arguments borrow the caller's reference, while returns hand over a new one.
+        Arc<T> => ::cffi::ArcRefMarshaler::<T> | ::cffi::ArcMarshaler::<T>,
+        Arc<dyn T> => ::cffi::ArcRefMarshaler::<T> | ::cffi::ArcMarshaler::<T>,
+        NonZero<T> => ::cffi::NonZeroMarshaler::<T>,
+        NonNull<T> => ::cffi::NonNullMarshaler::<T>,
     ];
 
     let path = Path::new(&env::var("OUT_DIR").unwrap()).join("codegen.rs");
@@ -63,11 +122,27 @@ fn main() {
     for (key, value) in default_marshalers.iter() {
         map.entry(
             quote! { #key }.to_string(),
-            &format!("\"{}\"", quote! { #value }.to_string()),
+            &format!("\"{}\"", quote! { #value }),
         );
     }
     write!(&mut file, "{}", map.build()).unwrap();
-    write!(&mut file, ";\n").unwrap();
+    writeln!(&mut file, ";").unwrap();
+
+    writeln!(
+        &mut file,
+        "static GENERIC_MARSHALERS: &[(&str, &str, &str)] = &[{}];",
+        generic_marshalers
+            .iter()
+            .map(|(ty, input, ret)| format!(
+                "({:?}, {:?}, {:?})",
+                quote! { #ty }.to_string(),
+                quote! { #input }.to_string(),
+                quote! { #ret }.to_string()
+            ))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+    .unwrap();
 
     let types: Vec<Type> = type_array![
         (),
@@ -79,19 +154,35 @@ fn main() {
         i32,
         i64,
         u64,
-        i128,
-        u128,
         isize,
         usize,
         f32,
         f64,
         // bool,
-        char,
+        // `char`, `i128` and `u128` have default marshalers instead, as they are not FFI-safe.
+        c_char,
+        c_schar,
+        c_uchar,
+        c_short,
+        c_ushort,
+        c_int,
+        c_uint,
+        c_long,
+        c_ulong,
+        c_longlong,
+        c_ulonglong,
+        c_float,
+        c_double,
+        size_t,
+        ssize_t,
+        intptr_t,
+        uintptr_t,
+        ptrdiff_t,
     ];
 
-    write!(
+    writeln!(
         &mut file,
-        "static PASSTHROUGH_TYPES: &[&str] = &[\"{}\"];\n",
+        "static PASSTHROUGH_TYPES: &[&str] = &[\"{}\"];",
         types
             .into_iter()
             .map(|x| quote! { #x }.to_string())
@@ -1,11 +1,38 @@
 use darling::FromMeta;
 
+/// How a `#[marshal] impl` hands its objects to foreign code.
+#[derive(Debug, FromMeta, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HandleKind {
+    /// Owned through `BoxMarshaler`.
+    #[default]
+    #[darling(rename = "box")]
+    Box,
+    /// Shared and reference counted through `ArcMarshaler`.
+    #[darling(rename = "arc")]
+    Arc,
+}
+
 #[derive(Debug, FromMeta, Default)]
 pub struct InvokeParams {
     #[darling(default)]
     pub return_marshaler: Option<syn::Path>,
     #[darling(default)]
     pub prefix: Option<String>,
+    /// Returns through a callback. On an impl, this applies to the methods returning a marshaled
+    /// value other than a handle.
     #[darling(default)]
     pub callback: bool,
+    /// Writes the return value into a caller-provided buffer. On an impl, this applies to the
+    /// methods returning a marshaled value other than a handle.
+    #[darling(default)]
+    pub buffer: bool,
+    #[darling(default)]
+    pub handle: HandleKind,
+    /// Wraps an impl's objects in a `SyncHandle`, locking them for each method call, and exports
+    /// `<prefix>_release` to free them.
+    #[darling(default)]
+    pub sync: bool,
+    /// Allows returning handles to types that are not `Send`.
+    #[darling(default)]
+    pub thread_confined: bool,
 }
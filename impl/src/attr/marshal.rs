@@ -1,4 +1,7 @@
 use quote::quote;
+use syn::punctuated::Punctuated;
+
+use crate::registry::Direction;
 use std::fmt::{self, Debug};
 
 /// Holds onto the inside of #[marshal(...)]
@@ -10,11 +13,13 @@ use std::fmt::{self, Debug};
 pub struct MarshalAttr {
     pub path: syn::Path,
     pub types: Vec<syn::Type>,
+    /// Whether the foreign value is passed as the two parameters of its `ForeignPair`.
+    pub split: bool,
 }
 
 impl Debug for MarshalAttr {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let MarshalAttr { path, types } = self;
+        let MarshalAttr { path, types, split } = self;
 
         fmt.debug_struct("MarshalAttr")
             .field("path", &quote! { #path }.to_string())
@@ -25,15 +30,27 @@ impl Debug for MarshalAttr {
                     .map(|x| quote! { #x }.to_string())
                     .collect::<Vec<_>>(),
             )
+            .field("split", split)
             .finish()
     }
 }
 
 impl MarshalAttr {
-    pub fn self_type() -> Option<MarshalAttr> {
-        Some(MarshalAttr {
-            path: syn::parse2(quote! { ::cffi::BoxMarshaler }).unwrap(),
+    /// The marshaler for a `self` receiver, or for a parameter of type `#ty` with no explicit
+    /// marshaler: `&T` and `&mut T` borrow from a `BoxMarshaler` pointer, `T` takes ownership.
+    pub fn self_type(ty: &syn::Type) -> Result<MarshalAttr, syn::Error> {
+        let path = match ty {
+            syn::Type::Reference(reference) => {
+                let elem = &reference.elem;
+                quote! { ::cffi::BoxRefMarshaler::<#elem> }
+            }
+            ty => quote! { ::cffi::BoxMarshaler::<#ty> },
+        };
+
+        Ok(MarshalAttr {
+            path: syn::parse2(path)?,
             types: vec![],
+            split: false,
         })
     }
 
@@ -46,21 +63,46 @@ impl MarshalAttr {
             .map(|ty| syn::ReturnType::Type(<syn::Token![->]>::default(), Box::new(ty)))
     }
 
-    pub fn from_defaults_by_type(ty: &syn::Type) -> Option<MarshalAttr> {
-        crate::default_marshaler(&ty).map(|x| MarshalAttr {
-            path: x.clone(),
-            types: vec![],
-        })
+    fn from_defaults(
+        ty: &syn::Type,
+        direction: Direction,
+    ) -> Result<Option<MarshalAttr>, syn::Error> {
+        match crate::registry::default_marshaler(ty, direction)? {
+            Some(path) => Self::from_path(path),
+            None => Ok(None),
+        }
+    }
+
+    /// The default marshaler for values of `ty` passed into Rust.
+    pub fn from_defaults_by_type(ty: &syn::Type) -> Result<Option<MarshalAttr>, syn::Error> {
+        Self::from_defaults(ty, Direction::Input)
     }
 
-    pub fn from_defaults_by_return_type(ty: &syn::ReturnType) -> Option<MarshalAttr> {
+    /// The default marshaler for values of `ty` returned from Rust.
+    pub fn from_return_defaults_by_type(ty: &syn::Type) -> Result<Option<MarshalAttr>, syn::Error> {
+        Self::from_defaults(ty, Direction::Return)
+    }
+
+    pub fn from_defaults_by_return_type(
+        ty: &syn::ReturnType,
+    ) -> Result<Option<MarshalAttr>, syn::Error> {
         match ty {
-            syn::ReturnType::Type(_, ty) => Self::from_defaults_by_type(&*ty),
-            _ => None,
+            syn::ReturnType::Type(_, ty) => Self::from_return_defaults_by_type(ty),
+            _ => Ok(None),
         }
     }
 
+    /// Reads a marshaler path such as `OptionMarshaler<VecOfMarshaler<StringMarshaler>>` or
+    /// `Result<StringMarshaler, MyErrorMarshaler>`, expanding the `Option<M>` and `Result<M, E>`
+    /// shorthands and adding the turbofish needed to call the marshaler.
     pub fn from_path(path: syn::Path) -> Result<Option<MarshalAttr>, syn::Error> {
+        let mut path = desugar(path)?;
+        for segment in path.segments.iter_mut() {
+            if let syn::PathArguments::AngleBracketed(args) = &mut segment.arguments {
+                args.colon2_token = Some(Default::default());
+            }
+        }
+
         let types = path
             .segments
             .iter()
@@ -73,7 +115,11 @@ impl MarshalAttr {
             })
             .collect::<Vec<_>>();
 
-        Ok(Some(MarshalAttr { path, types }))
+        Ok(Some(MarshalAttr {
+            path,
+            types,
+            split: false,
+        }))
     }
 
     fn from_bare_fn(bare_fn: syn::TypeBareFn) -> Result<Option<MarshalAttr>, syn::Error> {
@@ -110,29 +156,342 @@ impl MarshalAttr {
         Ok(None)
     }
 
-    pub fn from_attribute(attr: syn::Attribute) -> Result<Option<MarshalAttr>, syn::Error> {
+    /// Reads `#[marshal(M)]`, `#[marshal(M, split)]`, or `#[marshal(split)]` for the default
+    /// marshaler of `ty`.
+    pub fn from_attribute(
+        attr: syn::Attribute,
+        ty: Option<&syn::Type>,
+    ) -> Result<Option<MarshalAttr>, syn::Error> {
         if !attr.meta.path().is_ident("marshal") {
             return Ok(None);
         }
 
         let list = attr.meta.require_list()?;
 
-        let marshal_ty: syn::Type = match syn::parse2(list.tokens.clone()) {
-            Ok(v) => v,
-            Err(e) => return Err(e),
+        let mut args = list
+            .parse_args_with(Punctuated::<syn::Type, syn::Token![,]>::parse_terminated)?
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let split = match args.last() {
+            Some(syn::Type::Path(path)) if path.path.is_ident("split") => {
+                args.pop();
+                true
+            }
+            _ => false,
         };
 
-        match marshal_ty {
-            syn::Type::Paren(path) => match *path.elem {
+        if split && ty.is_none() {
+            return Err(syn::Error::new_spanned(
+                list,
+                "split is only supported on parameters",
+            ));
+        }
+
+        let marshal_ty = match (args.pop(), args.is_empty(), ty) {
+            (Some(ty), true, _) => ty,
+            (None, _, Some(ty)) if split => {
+                return match Self::from_defaults_by_type(ty)? {
+                    Some(attr) => Ok(Some(MarshalAttr { split, ..attr })),
+                    None => Err(syn::Error::new_spanned(
+                        ty,
+                        "no default marshaler to split for this type",
+                    )),
+                };
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    list,
+                    "expected a marshaler, optionally followed by `split`",
+                ))
+            }
+        };
+
+        let marshal_ty = match validator(&marshal_ty, ty)? {
+            Some(path) => syn::Type::Path(syn::TypePath { qself: None, path }),
+            None => marshal_ty,
+        };
+
+        let attr = match marshal_ty {
+            syn::Type::Paren(paren) => match *paren.elem {
                 syn::Type::Path(path) => Self::from_path(path.path),
                 syn::Type::BareFn(bare_fn) => Self::from_bare_fn(bare_fn),
-                e => {
-                    return Err(syn::Error::new_spanned(e, "Must be a path"));
-                }
+                e => Err(syn::Error::new_spanned(e, "Must be a path")),
             },
-            e => {
-                return Err(syn::Error::new_spanned(e, "Must be a path"));
+            syn::Type::Path(path) => Self::from_path(path.path),
+            syn::Type::BareFn(bare_fn) => Self::from_bare_fn(bare_fn),
+            // `(M1, M2)` is shorthand for `TupleMarshaler<(M1, M2)>`.
+            syn::Type::Tuple(tuple) if !tuple.elems.is_empty() => {
+                Self::from_path(syn::parse2(quote! { ::cffi::TupleMarshaler<#tuple> })?)
             }
+            e => Err(syn::Error::new_spanned(e, "Must be a path")),
+        }?;
+
+        Ok(attr.map(|attr| MarshalAttr { split, ..attr }))
+    }
+}
+
+/// Expands the validating shorthands `Range<MIN, MAX>`, `Finite` and `NonZero` to the matching
+/// marshaler for values of the parameter type `ty`.
+fn validator(
+    marshal_ty: &syn::Type,
+    ty: Option<&syn::Type>,
+) -> Result<Option<syn::Path>, syn::Error> {
+    let segment = match marshal_ty {
+        syn::Type::Path(p)
+            if p.qself.is_none()
+                && p.path.leading_colon.is_none()
+                && p.path.segments.len() == 1 =>
+        {
+            &p.path.segments[0]
+        }
+        _ => return Ok(None),
+    };
+
+    let name = segment.ident.to_string();
+    if !matches!(&*name, "Range" | "Finite" | "NonZero") {
+        return Ok(None);
+    }
+
+    let ty = match ty {
+        Some(v) => v,
+        None => {
+            return Err(syn::Error::new_spanned(
+                marshal_ty,
+                format!(
+                    "{} is only supported on parameters; name its marshaler in full",
+                    name
+                ),
+            ))
         }
+    };
+
+    let args = match &segment.arguments {
+        syn::PathArguments::None => vec![],
+        syn::PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .map(|arg| match arg {
+                syn::GenericArgument::Const(expr @ syn::Expr::Lit(_)) => quote! { #expr },
+                // Other expressions and named constants must be braced to be const arguments.
+                syn::GenericArgument::Const(expr) => quote! { { #expr } },
+                syn::GenericArgument::Type(ty) => quote! { { #ty } },
+                arg => quote! { #arg },
+            })
+            .collect(),
+        arguments => {
+            return Err(syn::Error::new_spanned(
+                arguments,
+                "expected angle-bracketed bounds",
+            ))
+        }
+    };
+
+    match (&*name, &*args) {
+        ("Range", [min, max]) => {
+            syn::parse2(quote! { ::cffi::RangeMarshaler<#ty, #min, #max> }).map(Some)
+        }
+        ("Finite", []) => syn::parse2(quote! { ::cffi::FiniteMarshaler<#ty> }).map(Some),
+        ("NonZero", []) => syn::parse2(quote! { ::cffi::NonZeroMarshaler<#ty> }).map(Some),
+        _ => Err(syn::Error::new_spanned(
+            marshal_ty,
+            "expected Range<MIN, MAX>, Finite or NonZero",
+        )),
+    }
+}
+
+/// Whether `path` names a marshaler rather than a Rust type, going by the `...Marshaler` naming
+/// convention and the `Option<M>` and `Result<M, E>` shorthands.
+fn is_marshaler(path: &syn::Path) -> bool {
+    match path.segments.last() {
+        Some(segment) => segment.ident.to_string().ends_with("Marshaler") || is_shorthand(path),
+        None => false,
+    }
+}
+
+/// The marshaler arguments of `path`, if all of its generic arguments are marshalers.
+fn marshaler_arguments(segment: &syn::PathSegment) -> Option<Vec<&syn::Path>> {
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .map(|arg| match arg {
+                syn::GenericArgument::Type(syn::Type::Path(p))
+                    if p.qself.is_none() && is_marshaler(&p.path) =>
+                {
+                    Some(&p.path)
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Whether `path` is `Option<M>` or `Result<M, E>` over marshalers.
+fn is_shorthand(path: &syn::Path) -> bool {
+    let segment = match path.segments.first() {
+        Some(v) if path.segments.len() == 1 && path.leading_colon.is_none() => v,
+        _ => return false,
+    };
+
+    let arity = match &*segment.ident.to_string() {
+        "Option" => 1,
+        "Result" => 2,
+        _ => return false,
+    };
+
+    marshaler_arguments(segment).is_some_and(|args| args.len() == arity)
+}
+
+/// Expands `Option<M>` to `OptionMarshaler<M>` and `Result<M, E>` to `ResultMarshaler<M, E>`,
+/// including where they are arguments of other marshalers.
+fn desugar(mut path: syn::Path) -> Result<syn::Path, syn::Error> {
+    if is_shorthand(&path) {
+        let segment = &path.segments[0];
+        let args = marshaler_arguments(segment)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|arg| desugar(arg.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        return match &*segment.ident.to_string() {
+            "Option" => syn::parse2(quote! { ::cffi::OptionMarshaler<#(#args),*> }),
+            _ => syn::parse2(quote! { ::cffi::ResultMarshaler<#(#args),*> }),
+        };
+    }
+
+    for segment in path.segments.iter_mut() {
+        if let syn::PathArguments::AngleBracketed(args) = &mut segment.arguments {
+            for arg in args.args.iter_mut() {
+                match arg {
+                    syn::GenericArgument::Type(syn::Type::Path(p)) => desugar_argument(p)?,
+                    // The element marshalers of a `TupleMarshaler`.
+                    syn::GenericArgument::Type(syn::Type::Tuple(tuple)) => {
+                        for elem in tuple.elems.iter_mut() {
+                            if let syn::Type::Path(p) = elem {
+                                desugar_argument(p)?;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(path)
+}
+
+fn desugar_argument(ty: &mut syn::TypePath) -> Result<(), syn::Error> {
+    if ty.qself.is_none() && is_marshaler(&ty.path) {
+        ty.path = desugar(ty.path.clone())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marshaler(path: &str) -> String {
+        let attr = MarshalAttr::from_path(syn::parse_str(path).unwrap())
+            .unwrap()
+            .unwrap();
+        let path = &attr.path;
+        quote! { #path }.to_string().replace(' ', "")
+    }
+
+    #[test]
+    fn nested() {
+        assert_eq!(
+            marshaler("OptionMarshaler<VecOfMarshaler<StringMarshaler>>"),
+            "OptionMarshaler::<VecOfMarshaler<StringMarshaler>>"
+        );
+        assert_eq!(
+            marshaler("Result<StringMarshaler, MyErrMarshaler>"),
+            "::cffi::ResultMarshaler::<StringMarshaler,MyErrMarshaler>"
+        );
+        assert_eq!(
+            marshaler("VecOfMarshaler<Option<StringMarshaler>>"),
+            "VecOfMarshaler::<::cffi::OptionMarshaler<StringMarshaler>>"
+        );
+        assert_eq!(
+            marshaler("BoxMarshaler<Option<u32>>"),
+            "BoxMarshaler::<Option<u32>>"
+        );
+    }
+
+    #[test]
+    fn split() {
+        let ty: syn::Type = syn::parse_quote! { &str };
+
+        let attr = MarshalAttr::from_attribute(syn::parse_quote! { #[marshal(split)] }, Some(&ty))
+            .unwrap()
+            .unwrap();
+        assert!(attr.split);
+        assert!(attr.path.segments.last().unwrap().ident == "StrMarshaler");
+
+        let attr = MarshalAttr::from_attribute(
+            syn::parse_quote! { #[marshal(VecRefMarshaler<u8>, split)] },
+            Some(&ty),
+        )
+        .unwrap()
+        .unwrap();
+        assert!(attr.split);
+
+        assert!(
+            MarshalAttr::from_attribute(syn::parse_quote! { #[marshal(split)] }, None).is_err()
+        );
+    }
+
+    #[test]
+    fn validators() {
+        let validator = |attr: syn::Attribute, ty: syn::Type| {
+            let attr = MarshalAttr::from_attribute(attr, Some(&ty))
+                .unwrap()
+                .unwrap();
+            let path = &attr.path;
+            quote! { #path }.to_string().replace(' ', "")
+        };
+
+        assert_eq!(
+            validator(
+                syn::parse_quote! { #[marshal(Range<0, 100>)] },
+                syn::parse_quote! { u8 }
+            ),
+            "::cffi::RangeMarshaler::<u8,0,100>"
+        );
+        assert_eq!(
+            validator(
+                syn::parse_quote! { #[marshal(Range<-1, MAX>)] },
+                syn::parse_quote! { i32 }
+            ),
+            "::cffi::RangeMarshaler::<i32,-1,{MAX}>"
+        );
+        assert_eq!(
+            validator(
+                syn::parse_quote! { #[marshal(Finite)] },
+                syn::parse_quote! { f64 }
+            ),
+            "::cffi::FiniteMarshaler::<f64>"
+        );
+        assert_eq!(
+            validator(
+                syn::parse_quote! { #[marshal(NonZero)] },
+                syn::parse_quote! { u32 }
+            ),
+            "::cffi::NonZeroMarshaler::<u32>"
+        );
+
+        assert!(
+            MarshalAttr::from_attribute(syn::parse_quote! { #[marshal(Finite)] }, None).is_err()
+        );
+        let ty: syn::Type = syn::parse_quote! { u8 };
+        assert!(
+            MarshalAttr::from_attribute(syn::parse_quote! { #[marshal(Range<0>)] }, Some(&ty))
+                .is_err()
+        );
     }
 }
@@ -2,11 +2,15 @@ pub mod invoke;
 pub mod marshal;
 
 use marshal::MarshalAttr;
+use quote::quote;
 
 #[derive(Debug)]
 pub struct Mapping {
     pub output_type: syn::Type,
     pub marshaler: Option<MarshalAttr>,
+    /// The lock guard the marshaler produces instead of `output_type`, which is then borrowed
+    /// from the guard.
+    pub guard_type: Option<syn::Type>,
 }
 
 impl Mapping {
@@ -14,14 +18,43 @@ impl Mapping {
         let syn::Receiver {
             reference,
             mutability,
+            colon_token,
             ..
         } = receiver.clone();
 
+        if colon_token.is_some() {
+            return Err(syn::Error::new_spanned(
+                receiver,
+                "only self, &self and &mut self receivers are supported",
+            ));
+        }
+
+        // Trait objects are only ever borrowed from their opaque `TraitObject` handle.
+        if let syn::Type::TraitObject(_) = parent {
+            if reference.is_none() || mutability.is_some() {
+                return Err(syn::Error::new_spanned(
+                    receiver,
+                    "trait object methods must take &self",
+                ));
+            }
+
+            return Ok(Mapping {
+                output_type: syn::parse2(quote! { &#parent })?,
+                marshaler: Some(MarshalAttr {
+                    path: syn::parse2(quote! { ::cffi::ArcRefMarshaler::<#parent> })?,
+                    types: vec![parent.clone()],
+                    split: false,
+                }),
+                guard_type: None,
+            });
+        }
+
         let path = match parent {
             syn::Type::Path(path) => path,
-            e => return Err(syn::Error::new_spanned(&e, "not a valid self type path")),
+            e => return Err(syn::Error::new_spanned(e, "not a valid self type path")),
         };
 
+        // A by-value receiver consumes the handle, freeing it once the method returns.
         let output_type = match (reference, mutability) {
             (None, _) => syn::Type::Path(path.clone()),
             (Some((and_token, lifetime)), mutability) => syn::Type::Reference(syn::TypeReference {
@@ -32,9 +65,63 @@ impl Mapping {
             }),
         };
 
+        Ok(Mapping {
+            marshaler: Some(MarshalAttr::self_type(&output_type)?),
+            output_type,
+            guard_type: None,
+        })
+    }
+
+    /// Maps a receiver onto a shared `Arc` handle, which may only be borrowed immutably.
+    pub fn arc_self_type(
+        receiver: &syn::Receiver,
+        parent: &syn::Type,
+    ) -> Result<Mapping, syn::Error> {
+        if receiver.reference.is_none() || receiver.mutability.is_some() {
+            return Err(syn::Error::new_spanned(
+                receiver,
+                "arc handles are shared, so methods must take &self",
+            ));
+        }
+
+        let Mapping { output_type, .. } = Mapping::self_type(receiver, parent)?;
+
+        Ok(Mapping {
+            output_type,
+            marshaler: Some(MarshalAttr {
+                path: syn::parse2(quote! { ::cffi::ArcRefMarshaler::<#parent> })?,
+                types: vec![],
+                split: false,
+            }),
+            guard_type: None,
+        })
+    }
+
+    /// Maps a receiver onto a `SyncHandle`: `&self` holds a read lock and `&mut self` a write
+    /// lock for the duration of the call, while `self` takes the object out of the handle, which
+    /// must still be freed.
+    pub fn sync_self_type(
+        receiver: &syn::Receiver,
+        parent: &syn::Type,
+    ) -> Result<Mapping, syn::Error> {
+        let Mapping { output_type, .. } = Mapping::self_type(receiver, parent)?;
+
+        let guard_type = match &output_type {
+            syn::Type::Reference(reference) if reference.mutability.is_some() => {
+                Some(syn::parse2(quote! { ::cffi::SyncMut<'_, #parent> })?)
+            }
+            syn::Type::Reference(_) => Some(syn::parse2(quote! { ::cffi::SyncRef<'_, #parent> })?),
+            _ => None,
+        };
+
         Ok(Mapping {
             output_type,
-            marshaler: MarshalAttr::self_type(),
+            marshaler: Some(MarshalAttr {
+                path: syn::parse2(quote! { ::cffi::SyncHandleMarshaler::<#parent> })?,
+                types: vec![],
+                split: false,
+            }),
+            guard_type,
         })
     }
 }
@@ -44,41 +131,47 @@ pub(crate) trait AttrExt {
 }
 
 #[inline]
-fn drain_marshal_attrs(attrs: &mut Vec<syn::Attribute>) -> Result<Option<MarshalAttr>, syn::Error> {
+fn drain_marshal_attrs(
+    attrs: &mut Vec<syn::Attribute>,
+    ty: Option<&syn::Type>,
+) -> Result<Option<MarshalAttr>, syn::Error> {
     let mut unhandled_attrs = vec![];
     std::mem::swap(attrs, &mut unhandled_attrs);
 
     let idents = unhandled_attrs
         .into_iter()
-        .filter_map(|item| match MarshalAttr::from_attribute(item.clone()) {
+        .filter_map(|item| match MarshalAttr::from_attribute(item.clone(), ty) {
             Ok(None) => {
                 attrs.push(item);
-                return None;
+                None
             }
             Ok(Some(v)) => Some(Ok(v)),
-            Err(e) => return Some(Err(e)),
+            Err(e) => Some(Err(e)),
         })
         .collect::<Result<Vec<_>, _>>();
 
-    let mut idents = match idents {
-        Ok(v) => v,
-        Err(e) => return Err(e),
-    };
+    let mut idents = idents?;
 
     Ok(idents.pop())
 }
 
 impl AttrExt for syn::PatType {
     fn drain_marshal_attrs(&mut self) -> Result<Option<MarshalAttr>, syn::Error> {
-        drain_marshal_attrs(&mut self.attrs)
+        drain_marshal_attrs(&mut self.attrs, Some(&self.ty))
+    }
+}
+
+impl AttrExt for syn::TraitItemFn {
+    fn drain_marshal_attrs(&mut self) -> Result<Option<MarshalAttr>, syn::Error> {
+        drain_marshal_attrs(&mut self.attrs, None)
     }
 }
 
 impl AttrExt for syn::FnArg {
     fn drain_marshal_attrs(&mut self) -> Result<Option<MarshalAttr>, syn::Error> {
         match self {
-            syn::FnArg::Receiver(receiver) => drain_marshal_attrs(&mut receiver.attrs),
-            syn::FnArg::Typed(typed) => drain_marshal_attrs(&mut typed.attrs),
+            syn::FnArg::Receiver(receiver) => drain_marshal_attrs(&mut receiver.attrs, None),
+            syn::FnArg::Typed(typed) => drain_marshal_attrs(&mut typed.attrs, Some(&typed.ty)),
         }
     }
 }
@@ -97,32 +190,33 @@ impl SignatureExt for syn::Signature {
     ) -> Result<Vec<Mapping>, syn::Error> {
         self.inputs
             .iter_mut()
-            .filter_map(|mut input| {
+            .map(|mut input| {
                 // Check if we're a self-type, and short-circuit
                 // TODO: this should use marshal_attr like normal typed fields
                 let input = match &mut input {
                     syn::FnArg::Receiver(receiver) => {
                         if let Some(parent_type) = parent_type {
-                            return Some(Mapping::self_type(receiver, parent_type));
+                            return Mapping::self_type(receiver, parent_type);
                         } else {
-                            return Some(Err(syn::Error::new_spanned(
+                            return Err(syn::Error::new_spanned(
                                 &receiver,
                                 "no self type found; using invoke wrong?",
-                            )));
+                            ));
                         }
                     }
                     syn::FnArg::Typed(t) => t,
                 };
 
-                let marshaler = match input.drain_marshal_attrs() {
-                    Ok(v) => v.or_else(|| MarshalAttr::from_defaults_by_type(&input.ty)),
-                    Err(e) => return Some(Err(e)),
+                let marshaler = match input.drain_marshal_attrs()? {
+                    Some(v) => Some(v),
+                    None => MarshalAttr::from_defaults_by_type(&input.ty)?,
                 };
 
-                Some(Ok(Mapping {
+                Ok(Mapping {
                     output_type: *input.ty.clone(),
                     marshaler,
-                }))
+                    guard_type: None,
+                })
             })
             .collect::<Result<Vec<_>, _>>()
     }
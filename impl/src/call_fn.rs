@@ -3,13 +3,15 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use syn::token::Paren;
 
-use super::{function::Function, function::InnerFn, return_type::ReturnType};
+use super::function::{gen_send_check, Function, InnerFn, ReturnMode};
+use super::return_type::ReturnType;
 use crate::attr::marshal::MarshalAttr;
 use crate::attr::SignatureExt;
 
 pub fn call_with_function(
     return_marshaler: Option<syn::Path>,
-    callback: bool,
+    return_mode: ReturnMode,
+    thread_confined: bool,
     mut fn_item: syn::ItemFn,
     parent_type: Option<&syn::Type>,
 ) -> Result<TokenStream, syn::Error> {
@@ -34,14 +36,19 @@ pub fn call_with_function(
         meta: syn::Meta::List(syn::MetaList {
             path: syn::parse2(quote! { inline }).unwrap(),
             delimiter: syn::MacroDelimiter::Paren(Paren::default()),
-            tokens: quote! { (always) },
+            tokens: quote! { always },
         }),
     };
     fn_item.attrs.push(attr);
 
     let fn_marshal_attr = match return_marshaler {
         Some(p) => MarshalAttr::from_path(p)?,
-        None => MarshalAttr::from_defaults_by_return_type(&fn_item.sig.output),
+        None => MarshalAttr::from_defaults_by_return_type(&fn_item.sig.output)?,
+    };
+
+    let send_check = match thread_confined {
+        true => None,
+        false => gen_send_check(fn_marshal_attr.as_ref()),
     };
 
     let return_type = ReturnType::new(fn_marshal_attr.as_ref(), fn_item.sig.output.clone())?;
@@ -50,10 +57,15 @@ pub fn call_with_function(
         fn_item.sig.inputs.clone(),
         &mappings,
         return_type,
-        InnerFn::FunctionBody(fn_item),
+        InnerFn::FunctionBody(Box::new(fn_item)),
         fn_marshal_attr,
-        callback,
+        return_mode,
     )?;
 
-    function.to_token_stream()
+    let function = function.to_token_stream()?;
+
+    Ok(quote! {
+        #function
+        #send_check
+    })
 }
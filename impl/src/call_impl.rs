@@ -1,14 +1,142 @@
 use heck::ToSnakeCase as _;
 use log::debug;
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
 
-use super::{function::Function, function::InnerFn, return_type::ReturnType};
+use super::function::{gen_send_check, Function, InnerFn, ReturnMode};
+use super::return_type::ReturnType;
+use crate::attr::invoke::HandleKind;
 use crate::attr::marshal::MarshalAttr;
-use crate::attr::SignatureExt;
+use crate::attr::{Mapping, SignatureExt};
+
+/// Whether `ty` names the impl's type, either directly or as `Self`.
+fn is_self_type(ty: &syn::Type, self_ty: &syn::Type) -> bool {
+    let ty = quote! { #ty }.to_string();
+    ty == "Self" || ty == quote! { #self_ty }.to_string()
+}
+
+/// How the impl's objects are handed to foreign code.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ImplHandle {
+    Box,
+    Sync,
+    Arc,
+}
+
+impl ImplHandle {
+    /// The marshaler for handles to `self_ty`, if it is not the default `BoxMarshaler`.
+    fn marshaler(self, self_ty: &syn::Type) -> Option<TokenStream> {
+        match self {
+            ImplHandle::Box => None,
+            ImplHandle::Sync => Some(quote! { ::cffi::SyncHandleMarshaler::<#self_ty> }),
+            ImplHandle::Arc => Some(quote! { ::cffi::ArcMarshaler::<#self_ty> }),
+        }
+    }
+}
+
+/// Whether `ty` is `Arc<T>` for the impl's type `T`.
+fn is_arc_of_self_type(ty: &syn::Type, self_ty: &syn::Type) -> bool {
+    let segment = match ty {
+        syn::Type::Path(path) => path.path.segments.last(),
+        _ => None,
+    };
+
+    match segment {
+        Some(segment) if segment.ident == "Arc" => match &segment.arguments {
+            syn::PathArguments::AngleBracketed(args) => args.args.iter().any(
+                |arg| matches!(arg, syn::GenericArgument::Type(ty) if is_self_type(ty, self_ty)),
+            ),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Routes the receiver, and any other parameters of the impl's type without a marshaler, through
+/// the marshaler for `handle`.
+fn handle_mappings(
+    handle: ImplHandle,
+    sig: &syn::Signature,
+    self_ty: &syn::Type,
+    mappings: &mut [Mapping],
+) -> Result<(), syn::Error> {
+    for (input, mapping) in sig.inputs.iter().zip(mappings.iter_mut()) {
+        let typed = match input {
+            syn::FnArg::Receiver(receiver) => {
+                match handle {
+                    ImplHandle::Box => {}
+                    ImplHandle::Sync => *mapping = Mapping::sync_self_type(receiver, self_ty)?,
+                    ImplHandle::Arc => *mapping = Mapping::arc_self_type(receiver, self_ty)?,
+                }
+                continue;
+            }
+            syn::FnArg::Typed(typed) => typed,
+        };
+
+        if mapping.marshaler.is_some() {
+            continue;
+        }
+
+        let (output_type, path) = match (handle, &*typed.ty) {
+            (ImplHandle::Box, _) => continue,
+            (ImplHandle::Sync, syn::Type::Reference(reference))
+                if is_self_type(&reference.elem, self_ty) =>
+            {
+                return Err(syn::Error::new_spanned(
+                    typed,
+                    "sync handles may only be borrowed through self, as locking two could deadlock",
+                ));
+            }
+            (ImplHandle::Sync, ty) if is_self_type(ty, self_ty) => (
+                quote! { #self_ty },
+                quote! { ::cffi::SyncHandleMarshaler::<#self_ty> },
+            ),
+            (ImplHandle::Arc, syn::Type::Reference(reference))
+                if is_self_type(&reference.elem, self_ty) && reference.mutability.is_none() =>
+            {
+                (
+                    quote! { &#self_ty },
+                    quote! { ::cffi::ArcRefMarshaler::<#self_ty> },
+                )
+            }
+            (ImplHandle::Arc, ty) if is_arc_of_self_type(ty, self_ty) => (
+                quote! { ::std::sync::Arc<#self_ty> },
+                quote! { ::cffi::ArcRefMarshaler::<#self_ty> },
+            ),
+            (ImplHandle::Arc, syn::Type::Reference(reference))
+                if is_self_type(&reference.elem, self_ty) =>
+            {
+                return Err(syn::Error::new_spanned(
+                    typed,
+                    "arc handles are shared, so may only be borrowed immutably",
+                ));
+            }
+            (ImplHandle::Arc, ty) if is_self_type(ty, self_ty) => {
+                return Err(syn::Error::new_spanned(
+                    typed,
+                    "arc handles are shared, so cannot be taken by value; use Arc<Self>",
+                ));
+            }
+            _ => continue,
+        };
+
+        mapping.output_type = syn::parse2(output_type)?;
+        mapping.marshaler = Some(MarshalAttr {
+            path: syn::parse2(path)?,
+            types: vec![],
+            split: false,
+        });
+    }
+
+    Ok(())
+}
 
 pub(crate) fn call_with_impl(
     prefix: Option<String>,
+    handle: HandleKind,
+    sync: bool,
+    return_mode: ReturnMode,
+    thread_confined: bool,
     mut item: syn::ItemImpl,
 ) -> Result<TokenStream, syn::Error> {
     debug!("{}", {
@@ -19,14 +147,14 @@ pub(crate) fn call_with_impl(
 
     if let Some(defaultness) = item.defaultness {
         return Err(syn::Error::new_spanned(
-            &defaultness,
+            defaultness,
             "Does not support specialised impls",
         ));
     }
 
     if let Some(unsafety) = item.unsafety {
         return Err(syn::Error::new_spanned(
-            &unsafety,
+            unsafety,
             "Does not support unsafe impls",
         ));
     }
@@ -45,6 +173,25 @@ pub(crate) fn call_with_impl(
         ));
     }
 
+    let handle = match (handle, sync) {
+        (HandleKind::Box, false) => ImplHandle::Box,
+        (HandleKind::Box, true) => ImplHandle::Sync,
+        (HandleKind::Arc, false) => ImplHandle::Arc,
+        (HandleKind::Arc, true) => {
+            return Err(syn::Error::new_spanned(
+                &item.self_ty,
+                "sync handles cannot also be arc handles",
+            ))
+        }
+    };
+
+    if handle == ImplHandle::Sync && thread_confined {
+        return Err(syn::Error::new_spanned(
+            &item.self_ty,
+            "sync handles are shared between threads, so they cannot be thread_confined",
+        ));
+    }
+
     let self_ty = &*item.self_ty;
     let invoke_prefix = prefix.unwrap_or_else(|| "".into());
     let prefix = format!("{}_{}", invoke_prefix, quote! { #self_ty }).to_snake_case();
@@ -72,7 +219,8 @@ pub(crate) fn call_with_impl(
             let c_ident: syn::Ident =
                 syn::parse_str(&format!("{}_{}", prefix, &ident).to_snake_case()).unwrap();
 
-            let mappings = x.sig.drain_mappings(Some(&*self_ty))?;
+            let mut mappings = x.sig.drain_mappings(Some(self_ty))?;
+            handle_mappings(handle, &x.sig, self_ty, &mut mappings)?;
 
             debug!("impl fn {}", quote! { #fn_path });
             debug!("impl fn def: {}", quote! { #x });
@@ -84,16 +232,16 @@ pub(crate) fn call_with_impl(
                 .into_iter()
                 .filter_map(|item| {
                     debug!("attr {}", quote! { #item });
-                    match MarshalAttr::from_attribute(item.clone()) {
+                    match MarshalAttr::from_attribute(item.clone(), None) {
                         Ok(None) => {
                             x.attrs.push(item);
-                            return None;
+                            None
                         }
                         Ok(Some(v)) => Some(Ok(v)),
-                        Err(e) => return Some(Err(e)),
+                        Err(e) => Some(Err(e)),
                     }
                 })
-                .collect::<Result<Vec<_>, _>>()?;
+                .collect::<Result<Vec<_>, syn::Error>>()?;
 
             let attr = idents.pop();
 
@@ -103,9 +251,35 @@ pub(crate) fn call_with_impl(
                 ..
             } = x.sig.clone();
 
-            let fn_marshal_attr = match attr.map(|x| x.path) {
-                Some(p) => MarshalAttr::from_path(p)?,
-                None => MarshalAttr::from_defaults_by_return_type(&local_return_type),
+            let returns_handle = matches!(
+                &local_return_type,
+                syn::ReturnType::Type(_, ty) if is_self_type(ty, self_ty)
+                    || (handle == ImplHandle::Arc && is_arc_of_self_type(ty, self_ty))
+            );
+            let handle_marshaler = match returns_handle {
+                true => handle.marshaler(self_ty),
+                false => None,
+            };
+
+            // Handles are always returned directly, as are values without a marshaler.
+            let return_mode = match &local_return_type {
+                syn::ReturnType::Type(_, ty)
+                    if !returns_handle && (attr.is_some() || !crate::is_passthrough_type(ty)) =>
+                {
+                    return_mode
+                }
+                _ => ReturnMode::Direct,
+            };
+
+            let fn_marshal_attr = match (attr.map(|x| x.path), handle_marshaler) {
+                (Some(p), _) => MarshalAttr::from_path(p)?,
+                (None, Some(p)) => MarshalAttr::from_path(syn::parse2(p)?)?,
+                (None, None) => MarshalAttr::from_defaults_by_return_type(&local_return_type)?,
+            };
+
+            let send_check = match thread_confined {
+                true => None,
+                false => gen_send_check(fn_marshal_attr.as_ref()),
             };
 
             let return_type = ReturnType::new(fn_marshal_attr.as_ref(), local_return_type)?;
@@ -116,18 +290,60 @@ pub(crate) fn call_with_impl(
                 return_type,
                 InnerFn::FunctionCall(fn_path),
                 fn_marshal_attr,
-                false,
+                return_mode,
             )?;
 
             debug!("{:#?}", &function);
 
-            function.to_token_stream()
+            let function = function.to_token_stream()?;
+
+            Ok(quote! {
+                #function
+                #send_check
+            })
         })
-        .collect::<Result<Vec<_>, _>>()?;
+        .collect::<Result<Vec<_>, syn::Error>>()?;
+
+    let handle_fns = match handle {
+        ImplHandle::Arc => {
+            let retain_ident = format_ident!("{}_retain", prefix);
+            let release_ident = format_ident!("{}_release", prefix);
+
+            Some(quote! {
+                #[no_mangle]
+                pub extern "C" fn #retain_ident(__handle: *const #self_ty) -> *const #self_ty {
+                    unsafe { ::cffi::ArcMarshaler::<#self_ty>::retain(__handle) }
+                }
+
+                #[no_mangle]
+                pub extern "C" fn #release_ident(__handle: *const #self_ty) {
+                    unsafe { ::cffi::ArcMarshaler::<#self_ty>::release(__handle) }
+                }
+            })
+        }
+        // Consuming methods leave the handle empty, so it is always freed separately.
+        ImplHandle::Sync => {
+            let release_ident = format_ident!("{}_release", prefix);
+
+            Some(quote! {
+                #[no_mangle]
+                pub extern "C" fn #release_ident(__handle: *const ::cffi::SyncHandle<#self_ty>) {
+                    unsafe {
+                        <::cffi::SyncHandleMarshaler<#self_ty> as ::cffi::DropForeign<_>>::drop_foreign(
+                            __handle,
+                        )
+                    }
+                }
+            })
+        }
+        ImplHandle::Box => None,
+    };
 
     Ok(quote! {
         #item
 
         #(#foreign_methods)*
+
+        #handle_fns
     })
 }
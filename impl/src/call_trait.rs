@@ -0,0 +1,195 @@
+use heck::ToSnakeCase as _;
+use log::debug;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+
+use super::function::{gen_send_check, Function, InnerFn, ReturnMode};
+use super::return_type::ReturnType;
+use crate::attr::marshal::MarshalAttr;
+use crate::attr::{AttrExt, SignatureExt};
+
+pub(crate) fn call_with_trait(
+    prefix: Option<String>,
+    thread_confined: bool,
+    mut item: syn::ItemTrait,
+) -> Result<TokenStream, syn::Error> {
+    debug!("trait {}", &item.ident);
+
+    if let Some(unsafety) = item.unsafety {
+        return Err(syn::Error::new_spanned(
+            unsafety,
+            "Does not support unsafe traits",
+        ));
+    }
+
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &item.generics,
+            "Does not support generic traits",
+        ));
+    }
+
+    let trait_ident = &item.ident;
+    let dyn_ty: syn::Type = syn::parse2(quote! { dyn #trait_ident })?;
+    let invoke_prefix = prefix.unwrap_or_else(|| "".into());
+    let prefix = format!("{}_{}", invoke_prefix, trait_ident).to_snake_case();
+
+    let mut methods = vec![];
+    for trait_item in item.items.iter_mut() {
+        let method = match trait_item {
+            syn::TraitItem::Fn(method) => method,
+            _ => continue,
+        };
+
+        if take_skip(&mut method.attrs) {
+            continue;
+        }
+
+        unsupported_method(&method.sig)?;
+        methods.push(method);
+    }
+
+    let foreign_methods = methods
+        .into_iter()
+        .map(|x| {
+            let ident = &x.sig.ident;
+            let fn_path: syn::Path = syn::parse2(quote! { #trait_ident::#ident })?;
+            let c_ident = format_ident!("{}", format!("{}_{}", prefix, ident).to_snake_case());
+
+            let mappings = x.sig.drain_mappings(Some(&dyn_ty))?;
+
+            let syn::Signature {
+                inputs: params,
+                output: local_return_type,
+                ..
+            } = x.sig.clone();
+
+            let fn_marshal_attr = match x.drain_marshal_attrs()?.map(|x| x.path) {
+                Some(p) => MarshalAttr::from_path(p)?,
+                None => MarshalAttr::from_defaults_by_return_type(&local_return_type)?,
+            };
+
+            let send_check = match thread_confined {
+                true => None,
+                false => gen_send_check(fn_marshal_attr.as_ref()),
+            };
+
+            let return_type = ReturnType::new(fn_marshal_attr.as_ref(), local_return_type)?;
+            let function = Function::new(
+                c_ident,
+                params,
+                &mappings,
+                return_type,
+                InnerFn::FunctionCall(fn_path),
+                fn_marshal_attr,
+                ReturnMode::Direct,
+            )?;
+
+            debug!("{:#?}", &function);
+
+            let function = function.to_token_stream()?;
+
+            Ok(quote! {
+                #function
+                #send_check
+            })
+        })
+        .collect::<Result<Vec<_>, syn::Error>>()?;
+
+    let free_ident = format_ident!("{}_free", prefix);
+
+    Ok(quote! {
+        #item
+
+        #(#foreign_methods)*
+
+        #[no_mangle]
+        pub extern "C" fn #free_ident(__handle: *const ::cffi::TraitObject<#dyn_ty>) {
+            unsafe { ::cffi::TraitObject::free(__handle) }
+        }
+    })
+}
+
+/// Removes a `#[marshal(skip)]` attribute, returning whether there was one.
+fn take_skip(attrs: &mut Vec<syn::Attribute>) -> bool {
+    let len = attrs.len();
+    attrs.retain(|attr| {
+        !(attr.path().is_ident("marshal")
+            && attr
+                .parse_args::<syn::Ident>()
+                .is_ok_and(|ident| ident == "skip"))
+    });
+    attrs.len() != len
+}
+
+/// Rejects methods that cannot be exported, which must be skipped explicitly instead.
+fn unsupported_method(sig: &syn::Signature) -> Result<(), syn::Error> {
+    let reason = match sig.receiver() {
+        None => "methods without a self receiver cannot be called through a trait object handle",
+        Some(receiver) if receiver.reference.is_none() => {
+            "methods taking self by value cannot be called through a shared trait object handle"
+        }
+        Some(receiver) if receiver.mutability.is_some() => {
+            "methods taking &mut self cannot be called through a shared trait object handle"
+        }
+        Some(_) if sig.asyncness.is_some() => "async methods are not supported",
+        Some(_) if sig.unsafety.is_some() => "unsafe methods are not supported",
+        Some(_) if !sig.generics.params.is_empty() => "generic methods are not supported",
+        Some(_) => return Ok(()),
+    };
+
+    Err(syn::Error::new_spanned(
+        &sig.ident,
+        format!("{}; add #[marshal(skip)] to leave this method out", reason),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(item: syn::ItemTrait) -> Result<TokenStream, syn::Error> {
+        call_with_trait(None, false, item)
+    }
+
+    #[test]
+    fn unsupported_methods() {
+        let err = expand(syn::parse_quote! {
+            trait Counter {
+                fn get(&self) -> u32;
+                fn increment(&mut self);
+            }
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("&mut self"), "{}", err);
+
+        let err = expand(syn::parse_quote! {
+            trait Counter {
+                fn new() -> Self where Self: Sized;
+            }
+        })
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("without a self receiver"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn skipped_methods() {
+        let tokens = expand(syn::parse_quote! {
+            trait Counter {
+                fn get(&self) -> u32;
+                #[marshal(skip)]
+                fn increment(&mut self);
+            }
+        })
+        .unwrap()
+        .to_string();
+
+        assert!(tokens.contains("counter_get"), "{}", tokens);
+        assert!(!tokens.contains("counter_increment"), "{}", tokens);
+        assert!(!tokens.contains("skip"), "{}", tokens);
+    }
+}
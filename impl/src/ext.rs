@@ -13,8 +13,7 @@ impl ForeignArgExt for syn::PatType {
         Ok(syn::PatType {
             ty: Box::new(self.ty.to_foreign_type()?),
             ..self.clone()
-        }
-        .into())
+        })
     }
 
     fn to_foreign_arg(&self) -> Result<syn::Pat, syn::Error> {
@@ -74,6 +73,7 @@ impl ForeignTypeExt for syn::Type {
     fn to_foreign_type(&self) -> Result<syn::Type, syn::Error> {
         match &self {
             syn::Type::Path(..) | syn::Type::Reference(..) => {}
+            syn::Type::Ptr(..) => return Ok(self.clone()),
             syn::Type::BareFn(bare_fn) => {
                 if bare_fn.abi.is_some() {
                     // This one is safe to pass through.
@@ -85,12 +85,16 @@ impl ForeignTypeExt for syn::Type {
                     "Non-extern-C fn parameters not supported",
                 ));
             }
-            syn::Type::Tuple(..) => {
+            // Other tuples are marshaled by a `TupleMarshaler`.
+            syn::Type::Tuple(tuple) if tuple.elems.is_empty() => {
                 return Err(syn::Error::new_spanned(
                     self,
-                    "Tuple parameters not supported",
+                    "Unit parameters not supported",
                 ))
             }
+            syn::Type::Tuple(..) => {}
+            // Arrays are marshaled by an `ArrayMarshaler`.
+            syn::Type::Array(..) => {}
             _ => {
                 return Err(syn::Error::new_spanned(
                     self,
@@ -100,8 +104,7 @@ impl ForeignTypeExt for syn::Type {
         }
 
         // Special case for bool
-        let bool_ty: syn::Type = syn::parse_str("bool").unwrap();
-        if self == &bool_ty {
+        if crate::normalize::key(self) == "bool" {
             return Ok(syn::parse2(quote! { /* bool */ u8 }).unwrap());
         }
 
@@ -122,10 +125,7 @@ pub trait ErrorExt<T> {
 impl<T> ErrorExt<T> for Result<T, syn::Error> {
     fn context(self, msg: impl Display) -> Self {
         match self {
-            Err(err) => Err(syn::Error::new(
-                err.span(),
-                format!("{}: {}", msg, err.to_string()),
-            )),
+            Err(err) => Err(syn::Error::new(err.span(), format!("{}: {}", msg, err))),
             x => x,
         }
     }
@@ -0,0 +1,574 @@
+use heck::ToShoutySnakeCase;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+
+use crate::attr::{marshal::MarshalAttr, AttrExt};
+use crate::header;
+use crate::registry::Direction;
+
+/// How a value crosses the vtable boundary.
+enum Conversion {
+    /// Passed as-is.
+    Passthrough(syn::Type),
+    /// Converted with the given marshaler.
+    Marshaled(syn::Path),
+}
+
+impl Conversion {
+    fn new(
+        ty: &syn::Type,
+        marshal_attr: Option<MarshalAttr>,
+        direction: Direction,
+    ) -> Result<Conversion, syn::Error> {
+        if marshal_attr.as_ref().is_some_and(|attr| attr.split) {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "split parameters are not supported by foreign traits",
+            ));
+        }
+
+        let marshal_attr = match (marshal_attr, direction) {
+            (Some(attr), _) => Some(attr),
+            (None, Direction::Input) => MarshalAttr::from_defaults_by_type(ty)?,
+            (None, Direction::Return) => MarshalAttr::from_return_defaults_by_type(ty)?,
+        };
+
+        if let Some(attr) = marshal_attr {
+            return Ok(Conversion::Marshaled(attr.path));
+        }
+
+        if crate::is_passthrough_type(ty) || matches!(ty, syn::Type::Ptr(_)) {
+            return Ok(Conversion::Passthrough(ty.clone()));
+        }
+
+        Err(syn::Error::new_spanned(
+            ty,
+            "no marshaler found for this type; add a #[marshal(...)] attribute",
+        ))
+    }
+
+    /// The foreign type for values Rust hands to the vtable.
+    fn param_type(&self) -> TokenStream {
+        match self {
+            Conversion::Passthrough(ty) => quote! { #ty },
+            Conversion::Marshaled(path) => quote! { <#path as ::cffi::ReturnType>::Foreign },
+        }
+    }
+
+    /// The foreign type for values the vtable hands back to Rust.
+    fn return_type(&self) -> TokenStream {
+        match self {
+            Conversion::Passthrough(ty) => quote! { #ty },
+            Conversion::Marshaled(path) => quote! { <#path as ::cffi::InputType>::Foreign },
+        }
+    }
+
+    fn c_type(&self) -> String {
+        match self {
+            Conversion::Passthrough(ty) => header::c_type_name(ty),
+            Conversion::Marshaled(path) => header::c_marshaler_name(path),
+        }
+    }
+}
+
+struct Param {
+    name: syn::Ident,
+    ty: syn::Type,
+    conversion: Conversion,
+}
+
+struct Method {
+    sig: syn::Signature,
+    params: Vec<Param>,
+    /// The value the vtable returns: the return type, or `T` of a `Result<T, E>` return.
+    output: Option<(syn::Type, Conversion)>,
+    /// Whether the method returns a `Result`, so that marshaling failures can be returned as
+    /// errors rather than panicking.
+    fallible: bool,
+}
+
+/// `T` if `ty` is a `Result<T, E>` or a `Result<T>` alias.
+fn result_ok_type(ty: &syn::Type) -> Option<&syn::Type> {
+    let segment = match ty {
+        syn::Type::Path(p) if p.qself.is_none() => p.path.segments.last()?,
+        _ => return None,
+    };
+
+    if segment.ident != "Result" {
+        return None;
+    }
+
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if matches!(args.args.len(), 1 | 2) => {
+            match args.args.first()? {
+                syn::GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+impl Method {
+    fn new(item: &mut syn::TraitItemFn) -> Result<Method, syn::Error> {
+        let sig = &mut item.sig;
+
+        if !sig.generics.params.is_empty() {
+            return Err(syn::Error::new_spanned(
+                &sig.generics,
+                "foreign trait methods may not be generic",
+            ));
+        }
+
+        match sig.receiver() {
+            Some(receiver) if receiver.reference.is_some() => {}
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &sig.ident,
+                    "foreign trait methods must take &self or &mut self",
+                ))
+            }
+        }
+
+        let mut params = vec![];
+        for input in sig.inputs.iter_mut() {
+            let arg = match input {
+                syn::FnArg::Typed(arg) => arg,
+                syn::FnArg::Receiver(_) => continue,
+            };
+
+            let marshal_attr = arg.drain_marshal_attrs()?;
+            let name = match &*arg.pat {
+                syn::Pat::Ident(pat) => pat.ident.clone(),
+                pat => {
+                    return Err(syn::Error::new_spanned(
+                        pat,
+                        "foreign trait parameters must be plain identifiers",
+                    ))
+                }
+            };
+
+            params.push(Param {
+                name,
+                ty: (*arg.ty).clone(),
+                conversion: Conversion::new(&arg.ty, marshal_attr, Direction::Return)?,
+            });
+        }
+
+        let marshal_attr = item.drain_marshal_attrs()?;
+        let (output, fallible) = match &item.sig.output {
+            syn::ReturnType::Default => (None, false),
+            syn::ReturnType::Type(_, ty) => {
+                let (value, fallible) = match result_ok_type(ty) {
+                    Some(value) => (value, true),
+                    None => (&**ty, false),
+                };
+                let conversion = Conversion::new(value, marshal_attr, Direction::Input)?;
+
+                if !fallible && matches!(conversion, Conversion::Marshaled(_)) {
+                    return Err(syn::Error::new_spanned(
+                        ty,
+                        "foreign trait methods returning a marshaled value must return \
+                        `Result<_, E>` where `E: From<Box<dyn Error>>`, to report marshaling \
+                        failures",
+                    ));
+                }
+
+                (Some((value.clone(), conversion)), fallible)
+            }
+        };
+
+        Ok(Method {
+            sig: item.sig.clone(),
+            params,
+            output,
+            fallible,
+        })
+    }
+
+    fn fn_ptr_type(&self) -> TokenStream {
+        let params = self.params.iter().map(|p| p.conversion.param_type());
+        let output = self.output.as_ref().map(|(_, c)| {
+            let ty = c.return_type();
+            quote! { -> #ty }
+        });
+
+        quote! {
+            unsafe extern "C" fn(*mut ::std::ffi::c_void #(, #params)*) #output
+        }
+    }
+
+    fn adapter_fn(&self, trait_name: &syn::Ident) -> TokenStream {
+        let sig = &self.sig;
+        let ident = &sig.ident;
+
+        let mut to_foreigns = TokenStream::new();
+        let mut args = vec![];
+        let mut drops = TokenStream::new();
+
+        // Converts a marshaling error `e` into the method's error type.
+        let error = quote! {
+            ::std::convert::From::from(::std::convert::Into::<Box<dyn ::std::error::Error>>::into(e))
+        };
+
+        for Param {
+            name,
+            ty,
+            conversion,
+        } in &self.params
+        {
+            match conversion {
+                Conversion::Passthrough(_) => args.push(quote! { #name }),
+                Conversion::Marshaled(path) => {
+                    let failed = match self.fallible {
+                        // Release the arguments already marshaled before returning the error.
+                        true if !drops.is_empty() => quote! {
+                            unsafe { #drops }
+                            return Err(#error);
+                        },
+                        true => quote! { return Err(#error); },
+                        false => {
+                            let msg =
+                                format!("{}::{}: failed to marshal `{}`", trait_name, ident, name);
+                            quote! { panic!("{}: {:?}", #msg, e) }
+                        }
+                    };
+                    to_foreigns.extend(quote! {
+                        let #name = match <#path as ::cffi::ToForeign<#ty, _>>::to_foreign(#name) {
+                            Ok(v) => v,
+                            Err(e) => { #failed }
+                        };
+                    });
+                    // The callee only borrows its arguments for the duration of the call.
+                    args.push(quote! { ::std::ptr::read(&#name) });
+                    drops.extend(quote! {
+                        <#path as ::cffi::DropForeign<_>>::drop_foreign(#name);
+                    });
+                }
+            }
+        }
+
+        let ret = match (&self.output, self.fallible) {
+            (Some((ty, Conversion::Marshaled(path))), _) => quote! {
+                match <#path as ::cffi::FromForeign<_, #ty>>::from_foreign(__ret) {
+                    Ok(v) => Ok(v),
+                    Err(e) => Err(#error),
+                }
+            },
+            (_, true) => quote! { Ok(__ret) },
+            (_, false) => quote! { __ret },
+        };
+
+        quote! {
+            #sig {
+                #to_foreigns
+                unsafe {
+                    let __ret = (self.#ident)(self.vtable.userdata #(, #args)*);
+                    #drops
+                    #ret
+                }
+            }
+        }
+    }
+
+    fn conversions(&self) -> impl Iterator<Item = &Conversion> {
+        self.params
+            .iter()
+            .map(|p| &p.conversion)
+            .chain(self.output.as_ref().map(|(_, c)| c))
+    }
+
+    fn c_decl(&self) -> String {
+        let ret = self
+            .output
+            .as_ref()
+            .map(|(_, c)| c.c_type())
+            .unwrap_or_else(|| "void".into());
+        let params = std::iter::once("void* userdata".to_string())
+            .chain(
+                self.params
+                    .iter()
+                    .map(|p| format!("{} {}", p.conversion.c_type(), p.name)),
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!("    {} (*{})({});\n", ret, self.sig.ident, params)
+    }
+}
+
+pub fn call_with_trait(mut item: syn::ItemTrait) -> Result<TokenStream, syn::Error> {
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &item.generics,
+            "foreign traits may not be generic",
+        ));
+    }
+
+    if !item.supertraits.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &item.supertraits,
+            "foreign traits may not have supertraits",
+        ));
+    }
+
+    let mut methods = vec![];
+    for trait_item in item.items.iter_mut() {
+        match trait_item {
+            syn::TraitItem::Fn(item) => methods.push(Method::new(item)?),
+            other => {
+                return Err(syn::Error::new_spanned(
+                    other,
+                    "foreign traits may only contain methods",
+                ))
+            }
+        }
+    }
+
+    let vis = &item.vis;
+    let trait_name = &item.ident;
+    let vtable_name = format_ident!("{}Vtable", trait_name);
+    let adapter_name = format_ident!("Foreign{}", trait_name);
+    let marshaler_name = format_ident!("{}Marshaler", trait_name);
+    let header_name = format_ident!("{}_HEADER", trait_name.to_string().to_shouty_snake_case());
+
+    let method_idents = methods.iter().map(|m| &m.sig.ident).collect::<Vec<_>>();
+    let fn_ptr_types = methods.iter().map(Method::fn_ptr_type).collect::<Vec<_>>();
+    let adapter_fns = methods.iter().map(|m| m.adapter_fn(trait_name));
+
+    let mut header = String::new();
+    for method in &methods {
+        for conversion in method.conversions() {
+            if let Conversion::Marshaled(path) = conversion {
+                header::c_declarations(path, &mut header);
+            }
+        }
+    }
+    header.push_str(&format!(
+        "typedef struct {} {{\n    void* userdata;\n",
+        vtable_name
+    ));
+    for method in &methods {
+        header.push_str(&method.c_decl());
+    }
+    header.push_str(&format!(
+        "    void (*release)(void* userdata);\n}} {};\n",
+        vtable_name
+    ));
+
+    let vtable_doc = format!(
+        "The foreign implementation of [`{}`]: a function pointer per method, each taking \
+        `userdata` first. `release`, if set, is called with `userdata` once Rust is done with it.",
+        trait_name
+    );
+    let adapter_doc = format!(
+        "Implements [`{}`] by calling through a [`{}`].",
+        trait_name, vtable_name
+    );
+    let marshaler_doc = format!(
+        "Marshals a [`{}`] into a [`{}`] or `Box<dyn {}>`.",
+        vtable_name, adapter_name, trait_name
+    );
+    let header_doc = format!("The C declaration of [`{}`].", vtable_name);
+
+    Ok(quote! {
+        #item
+
+        #[doc = #vtable_doc]
+        #[repr(C)]
+        #vis struct #vtable_name {
+            pub userdata: *mut ::std::ffi::c_void,
+            #(pub #method_idents: Option<#fn_ptr_types>,)*
+            pub release: Option<unsafe extern "C" fn(*mut ::std::ffi::c_void)>,
+        }
+
+        #[doc = #adapter_doc]
+        #vis struct #adapter_name {
+            vtable: #vtable_name,
+            #(#method_idents: #fn_ptr_types,)*
+        }
+
+        impl #trait_name for #adapter_name {
+            #(#adapter_fns)*
+        }
+
+        impl Drop for #adapter_name {
+            fn drop(&mut self) {
+                if let Some(release) = self.vtable.release {
+                    unsafe { release(self.vtable.userdata) };
+                }
+            }
+        }
+
+        #[doc = #marshaler_doc]
+        #vis struct #marshaler_name;
+
+        impl ::cffi::InputType for #marshaler_name {
+            type Foreign = #vtable_name;
+            type ForeignTraitObject = ();
+        }
+
+        impl ::cffi::FromForeign<#vtable_name, #adapter_name> for #marshaler_name {
+            type Error = Box<dyn ::std::error::Error>;
+
+            #[inline(always)]
+            unsafe fn from_foreign(vtable: #vtable_name) -> Result<#adapter_name, Self::Error> {
+                #(
+                    let #method_idents = match vtable.#method_idents {
+                        Some(v) => v,
+                        None => {
+                            // The vtable is rejected, so Rust is done with `userdata`.
+                            if let Some(release) = vtable.release {
+                                release(vtable.userdata);
+                            }
+                            return Err(::cffi::null_ptr_error());
+                        }
+                    };
+                )*
+
+                Ok(#adapter_name { vtable, #(#method_idents,)* })
+            }
+        }
+
+        impl ::cffi::FromForeign<#vtable_name, Box<dyn #trait_name>> for #marshaler_name {
+            type Error = Box<dyn ::std::error::Error>;
+
+            #[inline(always)]
+            unsafe fn from_foreign(vtable: #vtable_name) -> Result<Box<dyn #trait_name>, Self::Error> {
+                let adapter: #adapter_name = #marshaler_name::from_foreign(vtable)?;
+                Ok(Box::new(adapter))
+            }
+        }
+
+        #[doc = #header_doc]
+        #vis const #header_name: &str = #header;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(item: syn::ItemTrait) -> syn::File {
+        syn::parse2(call_with_trait(item).unwrap()).unwrap()
+    }
+
+    fn store() -> syn::File {
+        expand(syn::parse_quote! {
+            pub trait Store {
+                fn get(&self, key: &str) -> Result<String, Box<dyn Error>>;
+                fn count(&mut self) -> u32;
+            }
+        })
+    }
+
+    #[test]
+    fn vtable() {
+        let vtable = store()
+            .items
+            .into_iter()
+            .find_map(|item| match item {
+                syn::Item::Struct(item) if item.ident == "StoreVtable" => Some(item),
+                _ => None,
+            })
+            .unwrap();
+
+        let fields = vtable
+            .fields
+            .iter()
+            .map(|field| {
+                let (name, ty) = (field.ident.as_ref().unwrap(), &field.ty);
+                format!("{}: {}", name, quote! { #ty }).replace(' ', "")
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            fields,
+            [
+                "userdata:*mut::std::ffi::c_void",
+                "get:Option<unsafeextern\"C\"fn(*mut::std::ffi::c_void,<::cffi::StrMarshaleras::cffi::ReturnType>::Foreign)-><::cffi::StringMarshaleras::cffi::InputType>::Foreign>",
+                "count:Option<unsafeextern\"C\"fn(*mut::std::ffi::c_void)->u32>",
+                "release:Option<unsafeextern\"C\"fn(*mut::std::ffi::c_void)>",
+            ]
+        );
+    }
+
+    #[test]
+    fn adapter() {
+        let adapter = store()
+            .items
+            .into_iter()
+            .find_map(|item| match item {
+                syn::Item::Impl(item)
+                    if item
+                        .trait_
+                        .as_ref()
+                        .is_some_and(|(_, path, _)| path.is_ident("Store")) =>
+                {
+                    Some(item)
+                }
+                _ => None,
+            })
+            .unwrap();
+
+        let self_ty = &adapter.self_ty;
+        assert_eq!(quote! { #self_ty }.to_string(), "ForeignStore");
+
+        let get = quote! { #adapter }.to_string().replace(' ', "");
+        assert!(
+            get.contains("Err(e)=>{returnErr(::std::convert::From::from("),
+            "{}",
+            get
+        );
+        assert!(!get.contains("panic!"), "{}", get);
+    }
+
+    #[test]
+    fn header() {
+        let header = store()
+            .items
+            .into_iter()
+            .find_map(|item| match item {
+                syn::Item::Const(item) if item.ident == "STORE_HEADER" => Some(item),
+                _ => None,
+            })
+            .unwrap();
+
+        let header = match &*header.expr {
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(s),
+                ..
+            }) => s.value(),
+            expr => panic!("unexpected header {}", quote! { #expr }),
+        };
+
+        assert_eq!(
+            header,
+            format!(
+                "{}typedef struct StoreVtable {{
+    void* userdata;
+    cffi_slice_t (*get)(void* userdata, cffi_slice_t key);
+    uint32_t (*count)(void* userdata);
+    void (*release)(void* userdata);
+}} StoreVtable;
+",
+                header::C_SLICE_DECL
+            )
+        );
+    }
+
+    #[test]
+    fn marshaled_return_must_be_fallible() {
+        let err = call_with_trait(syn::parse_quote! {
+            trait Named {
+                fn name(&self) -> String;
+            }
+        })
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("must return `Result<_, E>`"),
+            "{}",
+            err
+        );
+    }
+}
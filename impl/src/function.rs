@@ -1,7 +1,9 @@
+use heck::ToShoutySnakeCase;
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote, quote_spanned, ToTokens};
 use std::fmt::{self, Debug};
 use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
 
 use crate::attr::{marshal::MarshalAttr, Mapping};
 use crate::ext::*;
@@ -48,22 +50,14 @@ fn gen_foreign(
     out_ty: &syn::Type,
     out_marshaler: Option<&syn::Path>,
     ret_ty: Option<&syn::Type>,
-    has_callback: bool,
+    return_mode: ReturnMode,
 ) -> TokenStream {
     let marshaler_path = &marshaler.path;
-    let marshal_ty = marshaler.first_type();
 
     let block = gen_try_not_null(
-        if marshal_ty.as_ref().map(is_trait_object).unwrap_or(false) {
-            quote! { unsafe {
-                let #name = std::mem::transmute::<_, *const (#marshal_ty)>(#name);
-                #marshaler_path::from_foreign(#name)
-            } }
-        } else {
-            quote! { unsafe { #marshaler_path::from_foreign(#name) } }
-        },
-        ret_ty.filter(|_| !has_callback).map(|ty| {
-            if crate::is_passthrough_type(ty) {
+        quote! { unsafe { #marshaler_path::from_foreign(#name) } },
+        ret_ty.filter(|_| return_mode == ReturnMode::Direct).map(|ty| {
+            if out_marshaler.is_none() {
                 quote! { <#ty>::default() }
             } else if is_trait_object(ty) {
                 quote! { <#out_marshaler as ::cffi::ReturnType>::foreign_default_trait_object() }
@@ -77,8 +71,72 @@ fn gen_foreign(
     quote! { let #name: #out_ty = #block; }
 }
 
+/// Marshalers whose foreign value is a handle to a Rust object that foreign code may share
+/// between threads. `HandleMarshaler` is left out as it already requires `Send + Sync`.
+const HANDLE_MARSHALERS: &[&str] = &["BoxMarshaler", "ArcMarshaler", "SyncHandleMarshaler"];
+
+/// Asserts at compile time that the object behind a returned handle is `Send`, as nothing stops
+/// foreign code from using the handle on another thread.
+pub fn gen_send_check(marshaler: Option<&MarshalAttr>) -> Option<TokenStream> {
+    let mut types = vec![];
+    handle_types(&marshaler?.path, &mut types);
+    if types.is_empty() {
+        return None;
+    }
+
+    Some(
+        types
+            .into_iter()
+            .map(|ty| {
+                quote_spanned! { ty.span() =>
+                    const _: () = {
+                        fn __assert_send<T: ?Sized + ::std::marker::Send>() {}
+                        let _ = __assert_send::<#ty>;
+                    };
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Collects the object types of the handle marshalers in `path`, including those nested within
+/// marshalers such as `OptionMarshaler<BoxMarshaler<T>>`.
+fn handle_types(path: &syn::Path, types: &mut Vec<syn::Type>) {
+    let segment = match path.segments.last() {
+        Some(v) => v,
+        None => return,
+    };
+    let args = match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => args,
+        _ => return,
+    };
+    let is_handle = HANDLE_MARSHALERS.contains(&&*segment.ident.to_string());
+
+    for arg in args.args.iter() {
+        match arg {
+            syn::GenericArgument::Type(ty) if is_handle => {
+                types.push(ty.clone());
+                return;
+            }
+            syn::GenericArgument::Type(syn::Type::Path(p)) => handle_types(&p.path, types),
+            _ => {}
+        }
+    }
+}
+
+/// How the generated function hands its return value back to the foreign caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReturnMode {
+    /// Returned directly from the function.
+    Direct,
+    /// Passed to a `__return` callback parameter.
+    Callback,
+    /// Written into caller-provided `__out_buf`, `__out_cap` and `__out_len` parameters.
+    Buffer,
+}
+
 pub enum InnerFn {
-    FunctionBody(syn::ItemFn),
+    FunctionBody(Box<syn::ItemFn>),
     FunctionCall(syn::Path),
 }
 
@@ -104,7 +162,9 @@ pub struct Function {
     inner_fn: InnerFn,
     fn_marshal_attr: Option<MarshalAttr>,
     has_exceptions: bool,
-    has_callback: bool,
+    return_mode: ReturnMode,
+    /// The C declarations of the tuple structs in the signature, if there are any.
+    tuple_header: Option<String>,
 }
 
 impl std::fmt::Debug for Function {
@@ -142,7 +202,7 @@ impl TypeMarshalExt for syn::ReturnType {
     fn pointer_type(&self) -> Option<PtrType> {
         match self {
             syn::ReturnType::Default => None,
-            syn::ReturnType::Type(_, ty) if crate::is_passthrough_type(&ty) => None,
+            syn::ReturnType::Type(_, ty) if crate::is_passthrough_type(ty) => None,
             syn::ReturnType::Type(_, ty) => ty.pointer_type(),
         }
     }
@@ -153,7 +213,7 @@ impl TypeMarshalExt for syn::ReturnType {
     ) -> Option<&'a syn::Path> {
         match &self {
             syn::ReturnType::Default => None,
-            syn::ReturnType::Type(_, ty) if crate::is_passthrough_type(&ty) => None,
+            syn::ReturnType::Type(_, ty) if crate::is_passthrough_type(ty) => None,
             syn::ReturnType::Type(_, ty) => ty.resolve_marshaler(marshaler_attr),
         }
     }
@@ -178,6 +238,23 @@ impl TypeMarshalExt for syn::Type {
     }
 }
 
+/// The name a foreign parameter is bound to: the identifier of a plain binding, without `mut`
+/// or `ref`, or a fresh name for any other pattern. The value is then matched against the
+/// parameter's pattern when it is passed to the Rust function.
+fn foreign_arg_name(index: usize, pat: syn::Pat) -> syn::Pat {
+    match pat {
+        syn::Pat::Ident(pat) if pat.subpat.is_none() => syn::Pat::Ident(syn::PatIdent {
+            attrs: vec![],
+            by_ref: None,
+            mutability: None,
+            ident: pat.ident,
+            subpat: None,
+        }),
+        syn::Pat::Verbatim(_) => pat,
+        _ => syn::Pat::Verbatim(format_ident!("__arg{}", index).into_token_stream()),
+    }
+}
+
 trait PatExt {
     fn ident(&self) -> Option<syn::Ident>;
 }
@@ -186,7 +263,7 @@ impl PatExt for syn::Pat {
     fn ident(&self) -> Option<syn::Ident> {
         match self {
             syn::Pat::Ident(ident) => Some(ident.ident.clone()),
-            syn::Pat::Verbatim(ident) => Some(syn::parse2(ident.clone()).unwrap()),
+            syn::Pat::Verbatim(ident) => syn::parse2(ident.clone()).ok(),
             _ => None,
         }
     }
@@ -220,7 +297,13 @@ mod c {
             .foreign_params
             .iter()
             .map(|fn_arg| {
-                let name = fn_arg.pat.ident().map(|x| x.to_string()).unwrap();
+                let name = match fn_arg.pat.ident() {
+                    Some(ident) => ident.to_string(),
+                    None => {
+                        let pat = &fn_arg.pat;
+                        quote! { #pat }.to_string()
+                    }
+                };
 
                 if name == "__exception" {
                     "void (*exception)(const char*)".into()
@@ -238,9 +321,43 @@ mod c {
     }
 }
 
+fn is_tuple_marshaler(path: &syn::Path) -> bool {
+    path.segments
+        .last()
+        .is_some_and(|segment| segment.ident == "TupleMarshaler")
+}
+
 fn is_trait_object(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::TraitObject(_))
+}
+
+/// Matches `impl Iterator<..>`, `dyn Iterator<..>` and `Box<dyn Iterator<..>>`.
+fn is_iterator(ty: &syn::Type) -> bool {
+    fn has_iterator_bound(bounds: &Punctuated<syn::TypeParamBound, syn::Token![+]>) -> bool {
+        bounds.iter().any(|bound| match bound {
+            syn::TypeParamBound::Trait(bound) => bound
+                .path
+                .segments
+                .last()
+                .map(|x| x.ident == "Iterator")
+                .unwrap_or(false),
+            _ => false,
+        })
+    }
+
     match ty {
-        syn::Type::TraitObject(_) => true,
+        syn::Type::ImplTrait(ty) => has_iterator_bound(&ty.bounds),
+        syn::Type::TraitObject(ty) => has_iterator_bound(&ty.bounds),
+        syn::Type::Path(ty) => match ty.path.segments.last() {
+            Some(segment) if segment.ident == "Box" => match &segment.arguments {
+                syn::PathArguments::AngleBracketed(args) => args
+                    .args
+                    .iter()
+                    .any(|arg| matches!(arg, syn::GenericArgument::Type(ty) if is_iterator(ty))),
+                _ => false,
+            },
+            _ => false,
+        },
         _ => false,
     }
 }
@@ -253,7 +370,7 @@ impl Function {
         return_type: ReturnType,
         inner_fn: InnerFn,
         fn_marshal_attr: Option<MarshalAttr>,
-        has_callback: bool,
+        return_mode: ReturnMode,
     ) -> Result<Function, syn::Error> {
         let mut from_foreigns = TokenStream::new();
         let mut foreign_params: Punctuated<syn::PatType, syn::Token![,]> = Punctuated::new();
@@ -264,22 +381,45 @@ impl Function {
 
         let mut has_exceptions = false;
 
+        let tuple_header = mappings
+            .iter()
+            .filter_map(|m| m.marshaler.as_ref().map(|m| &m.path))
+            .chain(return_marshaler)
+            .filter(|path| is_tuple_marshaler(path))
+            .fold(None, |header: Option<String>, path| {
+                let mut header = header.unwrap_or_default();
+                crate::header::c_declarations(path, &mut header);
+                Some(header)
+            });
+
+        let names = params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| {
+                param
+                    .to_foreign_arg()
+                    .context("failed to convert Rust type to FFI type")
+                    .map(|arg| foreign_arg_name(i, arg))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
         for (i, param) in params.iter().enumerate() {
             let mapping = &mappings[i];
             let out_type = &mapping.output_type;
             let _marshaler = &mapping.marshaler;
 
-            let name = param
-                .to_foreign_arg()
-                .context("failed to convert Rust type to FFI type")?;
+            let name = names[i].clone();
             let mut in_type = param
                 .to_foreign_param()
                 .context("failed to convert Rust type to FFI type")?;
+            in_type.pat = Box::new(name.clone());
 
             // if let Some(in_ty_override) = marshaler.as_ref().and_then(|m| m.types.first().cloned())
             // {
             //     in_type.ty = Box::new(in_ty_override);
             // }
+            let mut split_param = None;
+
             if let Some(marshaler) = mapping.marshaler.as_ref() {
                 let path = &marshaler.path;
                 let is_trait_object = marshaler
@@ -287,7 +427,43 @@ impl Function {
                     .map(|x| is_trait_object(&x))
                     .unwrap_or(false);
 
-                in_type.ty = if is_trait_object {
+                in_type.ty = if marshaler.split {
+                    // Take the two halves of the foreign value and join them before marshaling.
+                    let ident = match name.ident() {
+                        Some(v) => v,
+                        None => {
+                            return Err(syn::Error::new_spanned(
+                                &name,
+                                "split parameters must be plain identifiers",
+                            ))
+                        }
+                    };
+                    let len_ident = format_ident!("{}_len", ident);
+                    if names.iter().any(|n| n.ident().as_ref() == Some(&len_ident)) {
+                        return Err(syn::Error::new_spanned(
+                            &name,
+                            format!(
+                                "split parameter `{}` needs a `{}` parameter for its length, which is already taken; rename one of the parameters",
+                                ident, len_ident
+                            ),
+                        ));
+                    }
+                    let pair = quote! {
+                        <<#path as ::cffi::InputType>::Foreign as ::cffi::ForeignPair>
+                    };
+
+                    from_foreigns.extend(quote! {
+                        let #ident = #pair::join(#ident, #len_ident);
+                    });
+                    split_param = Some(syn::PatType {
+                        attrs: vec![],
+                        pat: Box::new(syn::Pat::Verbatim(quote! { #len_ident })),
+                        colon_token: <syn::Token![:]>::default(),
+                        ty: Box::new(syn::Type::Verbatim(quote! { #pair::Second })),
+                    });
+
+                    Box::new(syn::Type::Verbatim(quote! { #pair::First }))
+                } else if is_trait_object {
                     Box::new(syn::Type::Verbatim(
                         quote! { <#path as ::cffi::InputType>::ForeignTraitObject },
                     ))
@@ -298,38 +474,44 @@ impl Function {
                 };
 
                 let foreign = gen_foreign(
-                    &marshaler,
+                    marshaler,
                     &name,
-                    &out_type,
+                    mapping.guard_type.as_ref().unwrap_or(out_type),
                     return_marshaler,
                     return_type.foreign_type().as_ref(),
-                    has_callback,
+                    return_mode,
                 );
                 from_foreigns.extend(foreign);
                 has_exceptions = true;
-            } else if !crate::is_passthrough_type(&out_type) {
+            } else if !crate::is_passthrough_type(out_type) {
+                let box_marshaler = MarshalAttr::self_type(out_type)?;
+                let path = &box_marshaler.path;
                 in_type.ty = Box::new(syn::Type::Verbatim(quote! {
-                    <::cffi::BoxMarshaler::<#out_type> as ::cffi::InputType>::Foreign
+                    <#path as ::cffi::InputType>::Foreign
                 }));
 
-                let box_marshaler = MarshalAttr {
-                    path: syn::parse2(quote! { ::cffi::BoxMarshaler::<#out_type> })?,
-                    types: vec![],
-                };
                 let foreign = gen_foreign(
                     &box_marshaler,
                     &name,
-                    &out_type,
+                    out_type,
                     return_marshaler,
                     return_type.foreign_type().as_ref(),
-                    has_callback,
+                    return_mode,
                 );
                 from_foreigns.extend(foreign);
                 has_exceptions = true;
             }
 
             foreign_params.push(in_type);
-            foreign_args.push(name);
+            foreign_params.extend(split_param);
+            foreign_args.push(match (&mapping.guard_type, out_type) {
+                (Some(_), syn::Type::Reference(r)) if r.mutability.is_some() => {
+                    from_foreigns.extend(quote! { let mut #name = #name; });
+                    syn::Pat::Verbatim(quote! { &mut *#name })
+                }
+                (Some(_), _) => syn::Pat::Verbatim(quote! { &*#name }),
+                (None, _) => name,
+            });
         }
 
         let passthrough_return = return_type
@@ -337,6 +519,13 @@ impl Function {
             .map(|ty| crate::is_passthrough_type(&ty))
             .unwrap_or(true);
 
+        if return_mode == ReturnMode::Buffer && (passthrough_return || return_marshaler.is_none()) {
+            return Err(syn::Error::new_spanned(
+                &name,
+                "buffer mode requires a return type with a marshaler",
+            ));
+        }
+
         if has_exceptions || !passthrough_return {
             foreign_params.push(syn::PatType {
                 attrs: vec![],
@@ -351,12 +540,13 @@ impl Function {
             foreign_params,
             foreign_args,
             return_type,
-            return_marshaler: return_marshaler.map(|x| x.clone()),
+            return_marshaler: return_marshaler.cloned(),
             from_foreigns,
             inner_fn,
             fn_marshal_attr,
             has_exceptions,
-            has_callback,
+            return_mode,
+            tuple_header,
         };
 
         c::to_string(&function);
@@ -385,13 +575,15 @@ impl Function {
             ..
         } = self;
 
+        // Handles and out-parameters arrive as raw pointers, which C callers are trusted with.
         let mut sig = quote! {
             #[no_mangle]
+            #[allow(clippy::not_unsafe_ptr_arg_deref)]
             pub extern "C" fn #name
         };
 
         let ty = if let syn::ReturnType::Type(_, ty) = &self.return_type.local {
-            Some(if crate::is_passthrough_type(&ty) {
+            Some(if crate::is_passthrough_type(ty) {
                 quote! { #ty }
             } else {
                 let return_marshaler = match ty.resolve_marshaler(self.fn_marshal_attr.as_ref()) {
@@ -399,10 +591,7 @@ impl Function {
                     None => {
                         return Err(syn::Error::new_spanned(
                             ty,
-                            format!(
-                                "no marshaler found for return type {}",
-                                quote! { #ty }.to_string()
-                            ),
+                            format!("no marshaler found for return type {}", quote! { #ty }),
                         ))
                     }
                 };
@@ -425,14 +614,24 @@ impl Function {
             None
         };
 
-        sig.extend(if let Some(ty) = ty {
-            if self.has_callback {
+        sig.extend(match (ty, self.return_mode) {
+            (Some(ty), ReturnMode::Direct) => quote! { (#foreign_params) -> #ty },
+            (Some(ty), ReturnMode::Callback) => {
                 quote! { (#foreign_params, __return: ::cffi::RetCallback<#ty>) }
-            } else {
-                quote! { (#foreign_params) -> #ty }
             }
-        } else {
-            quote! { (#foreign_params) }
+            (Some(_), ReturnMode::Buffer) => {
+                let return_marshaler = &self.return_marshaler;
+                let local_ty = self.return_type.local_type();
+                quote! {
+                    (
+                        #foreign_params,
+                        __out_buf: *mut <#return_marshaler as ::cffi::ToForeignBuffer<#local_ty>>::Element,
+                        __out_cap: usize,
+                        __out_len: *mut usize
+                    )
+                }
+            }
+            (None, _) => quote! { (#foreign_params) },
         });
 
         Ok(sig)
@@ -466,8 +665,8 @@ impl Function {
             syn::ReturnType::Default => {
                 inner_block.extend(quote! { #call_name(#foreign_args); });
             }
-            syn::ReturnType::Type(_, ty) if crate::is_passthrough_type(&ty) => {
-                if self.has_callback {
+            syn::ReturnType::Type(_, ty) if crate::is_passthrough_type(ty) => {
+                if self.return_mode == ReturnMode::Callback {
                     inner_block.extend(quote! {
                         if let Some(__return) = __return {
                             __return(#call_name(#foreign_args));
@@ -483,10 +682,7 @@ impl Function {
                     None => {
                         return Err(syn::Error::new_spanned(
                             ty,
-                            format!(
-                                "no marshaler found for return type {}",
-                                quote! { #ty }.to_string()
-                            ),
+                            format!("no marshaler found for return type {}", quote! { #ty }),
                         ))
                     }
                 };
@@ -508,10 +704,16 @@ impl Function {
                             <#return_marshaler as ::cffi::ReturnType>::foreign_default()
                         }
                     }),
-                    self.has_callback,
+                    self.return_mode != ReturnMode::Direct,
                 );
 
-                if self.has_callback {
+                if self.return_mode == ReturnMode::Buffer {
+                    inner_block.extend(quote! {
+                        let result = #call_name(#foreign_args);
+                        let mut __buffer = unsafe { ::cffi::OutBuffer::new(__out_buf, __out_cap, __out_len) };
+                        if let Err(e) = <#return_marshaler as ::cffi::ToForeignBuffer<#ty>>::to_foreign_buffer(result, &mut __buffer) #throw
+                    });
+                } else if self.return_mode == ReturnMode::Callback {
                     inner_block.extend(quote! {
                         let result = #call_name(#foreign_args);
                         if let Some(__return) = __return {
@@ -522,16 +724,10 @@ impl Function {
                         }
                     });
                 } else if is_trait_object {
-                    let dyn_ty = self
-                        .fn_marshal_attr
-                        .as_ref()
-                        .and_then(|x| x.first_type())
-                        .unwrap();
-
                     inner_block.extend(quote! {
                         let result = #call_name(#foreign_args);
-                        match #return_marshaler::to_foreign(result) {
-                            Ok(v) => unsafe { cffi::trait_object!(v: (#dyn_ty)) },
+                        match <#return_marshaler as ::cffi::ToForeignTraitObject<_, _>>::to_foreign_trait_object(result) {
+                            Ok(v) => v,
                             Err(e) => #throw
                         }
                     });
@@ -550,14 +746,129 @@ impl Function {
         Ok(inner_block)
     }
 
+    /// Builds the `_next` and `_free` functions for functions returning an iterator.
+    fn build_cursor_fns(&self) -> Option<TokenStream> {
+        let return_marshaler = self.return_marshaler.as_ref()?;
+        if !self
+            .return_type
+            .local_type()
+            .is_some_and(|ty| is_iterator(&ty))
+        {
+            return None;
+        }
+
+        let next_name = format_ident!("{}_next", self.name);
+        let free_name = format_ident!("{}_free", self.name);
+        let item = quote! { <#return_marshaler as ::cffi::CursorType>::Item };
+        let throw = gen_throw(Some(quote! { 0 }), false);
+
+        Some(quote! {
+            #[no_mangle]
+            #[allow(clippy::not_unsafe_ptr_arg_deref)]
+            pub extern "C" fn #next_name(
+                __cursor: *mut ::cffi::Cursor<#item>,
+                __out: *mut #item,
+                __exception: ::cffi::ErrCallback,
+            ) -> u8 {
+                match unsafe { ::cffi::Cursor::next(__cursor, __out) } {
+                    Ok(v) => v as u8,
+                    Err(e) => #throw
+                }
+            }
+
+            #[no_mangle]
+            #[allow(clippy::not_unsafe_ptr_arg_deref)]
+            pub extern "C" fn #free_name(__cursor: *mut ::cffi::Cursor<#item>) {
+                unsafe { ::cffi::Cursor::free(__cursor) }
+            }
+        })
+    }
+
+    /// Builds the `_free` function for functions returning a tuple, and the C declarations of
+    /// the tuple structs used by the function.
+    fn build_tuple_items(&self) -> Option<TokenStream> {
+        let mut header = self.tuple_header.clone()?;
+
+        let free_fn = match &self.return_marshaler {
+            Some(path) if is_tuple_marshaler(path) && self.return_mode != ReturnMode::Buffer => {
+                let free_name = format_ident!("{}_free", self.name);
+                header.push_str(&format!(
+                    "void {}({} value);\n",
+                    free_name,
+                    crate::header::c_marshaler_name(path)
+                ));
+
+                Some(quote! {
+                    #[no_mangle]
+                    pub extern "C" fn #free_name(value: <#path as ::cffi::ReturnType>::Foreign) {
+                        unsafe { <#path as ::cffi::DropForeign<_>>::drop_foreign(value) }
+                    }
+                })
+            }
+            _ => None,
+        };
+
+        let header_name = format_ident!("{}_HEADER", self.name.to_string().to_shouty_snake_case());
+        let header_doc = format!(
+            "The C declarations of the tuple structs used by `{}`.",
+            self.name
+        );
+
+        Some(quote! {
+            #free_fn
+
+            #[doc = #header_doc]
+            pub const #header_name: &str = #header;
+        })
+    }
+
     pub fn to_token_stream(&self) -> Result<TokenStream, syn::Error> {
         let sig = self.build_signature()?;
         let inner_block = self.build_inner_block()?;
+        let cursor_fns = self.build_cursor_fns();
+        let tuple_items = self.build_tuple_items();
 
         Ok(quote! {
             #sig {
                 #inner_block
             }
+
+            #cursor_fns
+            #tuple_items
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arg_name(pat: syn::Pat) -> String {
+        let name = foreign_arg_name(1, pat);
+        quote! { #name }.to_string()
+    }
+
+    #[test]
+    fn pattern_params() {
+        assert_eq!(arg_name(syn::parse_quote! { mut x }), "x");
+        assert_eq!(arg_name(syn::parse_quote! { ref x }), "x");
+        assert_eq!(arg_name(syn::parse_quote! { _ }), "__arg1");
+        assert_eq!(arg_name(syn::parse_quote! { (a, b) }), "__arg1");
+        assert_eq!(arg_name(syn::parse_quote! { x @ 1..=2 }), "__arg1");
+    }
+
+    #[test]
+    fn split_len_collision() {
+        let err = crate::call_fn::call_with_function(
+            None,
+            ReturnMode::Direct,
+            false,
+            syn::parse_quote! {
+                fn find(#[marshal(split)] name: &str, name_len: u32) {}
+            },
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("`name_len`"), "{}", err);
+    }
+}
@@ -0,0 +1,334 @@
+//! C names and declarations for the foreign types of marshalers, used in generated headers.
+
+use heck::ToShoutySnakeCase;
+
+use crate::passthrough::type_argument;
+
+pub const C_SLICE_DECL: &str = "#ifndef CFFI_SLICE_T
+#define CFFI_SLICE_T
+typedef struct cffi_slice_t {
+    void* data;
+    size_t len;
+} cffi_slice_t;
+#endif
+
+";
+
+pub const C_INT128_DECL: &str = "#ifndef CFFI_INT128_T
+#define CFFI_INT128_T
+typedef struct cffi_int128_t {
+    uint64_t lo;
+    uint64_t hi;
+} cffi_int128_t;
+#endif
+
+";
+
+pub fn c_type_name(ty: &syn::Type) -> String {
+    let ty = crate::normalize::normalize_shallow(ty);
+
+    // `Option<NonNull<T>>` and the like are represented as their inner type.
+    if let Some(inner) = type_argument(&ty, "Option").or_else(|| type_argument(&ty, "NonZero")) {
+        return c_type_name(inner);
+    }
+
+    match &ty {
+        syn::Type::Ptr(ptr) if ptr.const_token.is_some() => "const void*".into(),
+        syn::Type::Ptr(_) | syn::Type::BareFn(_) => "void*".into(),
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => "void".into(),
+        _ if type_argument(&ty, "NonNull").is_some() => "void*".into(),
+        _ => match &*crate::normalize::key(&ty) {
+            "u8" | "NonZeroU8" => "uint8_t",
+            "i8" | "NonZeroI8" => "int8_t",
+            "u16" | "NonZeroU16" => "uint16_t",
+            "i16" | "NonZeroI16" => "int16_t",
+            "u32" | "NonZeroU32" => "uint32_t",
+            "i32" | "NonZeroI32" => "int32_t",
+            "u64" | "NonZeroU64" => "uint64_t",
+            "i64" | "NonZeroI64" => "int64_t",
+            "usize" | "NonZeroUsize" | "uintptr_t" => "uintptr_t",
+            "isize" | "NonZeroIsize" | "intptr_t" => "intptr_t",
+            "f32" | "c_float" => "float",
+            "f64" | "c_double" => "double",
+            "c_char" => "char",
+            "c_schar" => "signed char",
+            "c_uchar" => "unsigned char",
+            "c_short" => "short",
+            "c_ushort" => "unsigned short",
+            "c_int" => "int",
+            "c_uint" => "unsigned int",
+            "c_long" => "long",
+            "c_ulong" => "unsigned long",
+            "c_longlong" => "long long",
+            "c_ulonglong" => "unsigned long long",
+            "size_t" => "size_t",
+            "ssize_t" => "ssize_t",
+            "ptrdiff_t" => "ptrdiff_t",
+            other if crate::passthrough::is_declared(other) => return other.into(),
+            other => return format!("/* {} */", other),
+        }
+        .into(),
+    }
+}
+
+pub fn c_marshaler_name(path: &syn::Path) -> String {
+    let segment = match path.segments.last() {
+        Some(v) => v,
+        None => return "void*".into(),
+    };
+
+    match &*segment.ident.to_string() {
+        "BoolMarshaler" => "uint8_t".into(),
+        "CharMarshaler" => "uint32_t".into(),
+        "Int128Marshaler" => "cffi_int128_t".into(),
+        "StringMarshaler" | "StrMarshaler" | "PathBufMarshaler" | "UrlMarshaler"
+        | "VecMarshaler" | "VecRefMarshaler" | "VecOfMarshaler" | "ArrayRefMarshaler" => {
+            "cffi_slice_t".into()
+        }
+        "BoxMarshaler" | "BoxRefMarshaler" | "ArcMarshaler" | "ArcRefMarshaler"
+        | "NonNullMarshaler" | "FnPtrMarshaler" => "void*".into(),
+        "CopyMarshaler" | "RangeMarshaler" | "FiniteMarshaler" | "NonZeroMarshaler" => {
+            match first_type(segment) {
+                Some(ty) => c_type_name(ty),
+                None => "void*".into(),
+            }
+        }
+        // These pass the inner marshaler's value as-is.
+        "OptionMarshaler" | "ResultMarshaler" => match first_type(segment) {
+            Some(syn::Type::Path(inner)) => c_marshaler_name(&inner.path),
+            _ => "void*".into(),
+        },
+        "TupleMarshaler" => match tuple_elements(path) {
+            Some(elements) => c_tuple_name(&elements),
+            None => "void*".into(),
+        },
+        "ArrayMarshaler" => match array_parts(path) {
+            Some((elem, len)) => c_array_name(elem, &len),
+            None => "/* ArrayMarshaler: length must be a literal or a constant */".into(),
+        },
+        other => format!("/* {} */", other),
+    }
+}
+
+fn first_type(segment: &syn::PathSegment) -> Option<&syn::Type> {
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.first() {
+            Some(syn::GenericArgument::Type(ty)) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The element marshalers of a `TupleMarshaler<(M1, M2, ..)>`.
+fn tuple_elements(path: &syn::Path) -> Option<Vec<&syn::Path>> {
+    let segment = path.segments.last()?;
+    if segment.ident != "TupleMarshaler" {
+        return None;
+    }
+
+    match first_type(segment)? {
+        syn::Type::Tuple(tuple) => tuple
+            .elems
+            .iter()
+            .map(|ty| match ty {
+                syn::Type::Path(p) => Some(&p.path),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// The element type and length of an `ArrayMarshaler<T, N>`. A length given by a constant, such
+/// as `{LEN}`, is emitted by name, so C must define a constant of the same name and value.
+fn array_parts(path: &syn::Path) -> Option<(&syn::Type, String)> {
+    let segment = path.segments.last()?;
+    if segment.ident != "ArrayMarshaler" {
+        return None;
+    }
+
+    let args = match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => &args.args,
+        _ => return None,
+    };
+
+    let elem = match args.first()? {
+        syn::GenericArgument::Type(ty) => ty,
+        _ => return None,
+    };
+
+    let len = match args.iter().nth(1)? {
+        syn::GenericArgument::Const(syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(len),
+            ..
+        })) => len.base10_digits().to_string(),
+        syn::GenericArgument::Const(syn::Expr::Block(block)) => match &*block.block.stmts {
+            [syn::Stmt::Expr(syn::Expr::Path(len), None)] => constant_name(&len.path)?,
+            _ => return None,
+        },
+        syn::GenericArgument::Const(syn::Expr::Path(len)) => constant_name(&len.path)?,
+        // Unbraced constants parse as types.
+        syn::GenericArgument::Type(syn::Type::Path(len)) => constant_name(&len.path)?,
+        _ => return None,
+    };
+
+    Some((elem, len))
+}
+
+/// The name of a constant array length, without the module it was declared in.
+fn constant_name(path: &syn::Path) -> Option<String> {
+    let segment = path.segments.last()?;
+    match segment.arguments {
+        syn::PathArguments::None => Some(segment.ident.to_string()),
+        _ => None,
+    }
+}
+
+/// Names the struct for an array after its element's C type and length: `[u8; 32]` is
+/// `cffi_array_uint8_t_32`.
+fn c_array_name(elem: &syn::Type, len: &str) -> String {
+    format!(
+        "cffi_array_{}_{}",
+        c_type_name(elem).replace('*', "_ptr"),
+        len
+    )
+    .replace(' ', "_")
+}
+
+/// Names the struct for a tuple after its elements' C types, so that each tuple shape has one
+/// struct: `(String, u32)` is `cffi_tuple_cffi_slice_t_uint32_t`.
+fn c_tuple_name(elements: &[&syn::Path]) -> String {
+    let mut name = String::from("cffi_tuple");
+
+    for element in elements {
+        name.push('_');
+        for c in c_marshaler_name(element).chars() {
+            match c {
+                '*' => name.push_str("_ptr"),
+                ' ' => name.push('_'),
+                c if c.is_ascii_alphanumeric() || c == '_' => name.push(c),
+                _ => {}
+            }
+        }
+    }
+
+    name
+}
+
+/// Appends the declarations the C type of the marshaler `path` depends on, which are each
+/// guarded so that headers declaring them may be combined.
+pub fn c_declarations(path: &syn::Path, header: &mut String) {
+    match &*c_marshaler_name(path) {
+        "cffi_slice_t" if !header.contains(C_SLICE_DECL) => header.push_str(C_SLICE_DECL),
+        "cffi_int128_t" if !header.contains(C_INT128_DECL) => header.push_str(C_INT128_DECL),
+        _ => {}
+    }
+
+    if let Some((elem, len)) = array_parts(path) {
+        let name = c_array_name(elem, &len);
+        let guard = name.to_shouty_snake_case();
+        if !header.contains(&format!("#ifndef {}\n", guard)) {
+            header.push_str(&format!(
+                "#ifndef {guard}\n#define {guard}\ntypedef struct {name} {{\n    {} data[{len}];\n}} {name};\n#endif\n\n",
+                c_type_name(elem),
+            ));
+        }
+        return;
+    }
+
+    let elements = match tuple_elements(path) {
+        Some(v) => v,
+        None => return,
+    };
+
+    for element in elements.iter() {
+        c_declarations(element, header);
+    }
+
+    let name = c_tuple_name(&elements);
+    let guard = name.to_shouty_snake_case();
+    if header.contains(&format!("#ifndef {}\n", guard)) {
+        return;
+    }
+
+    header.push_str(&format!(
+        "#ifndef {guard}\n#define {guard}\ntypedef struct {name} {{\n"
+    ));
+    for (i, element) in elements.iter().enumerate() {
+        header.push_str(&format!("    {} _{};\n", c_marshaler_name(element), i));
+    }
+    header.push_str(&format!("}} {name};\n#endif\n\n"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tuple_declarations() {
+        let path: syn::Path = syn::parse_quote! {
+            ::cffi::TupleMarshaler::<(::cffi::StringMarshaler, ::cffi::CopyMarshaler<u32>)>
+        };
+
+        let mut header = String::new();
+        c_declarations(&path, &mut header);
+        c_declarations(&path, &mut header);
+
+        assert_eq!(
+            header,
+            format!(
+                "{}{}",
+                C_SLICE_DECL,
+                "#ifndef CFFI_TUPLE_CFFI_SLICE_T_UINT32_T
+#define CFFI_TUPLE_CFFI_SLICE_T_UINT32_T
+typedef struct cffi_tuple_cffi_slice_t_uint32_t {
+    cffi_slice_t _0;
+    uint32_t _1;
+} cffi_tuple_cffi_slice_t_uint32_t;
+#endif
+
+"
+            )
+        );
+    }
+
+    #[test]
+    fn array_declarations() {
+        let path: syn::Path = syn::parse_quote! { ::cffi::ArrayMarshaler::<u8, 32> };
+
+        let mut header = String::new();
+        c_declarations(&path, &mut header);
+        c_declarations(&path, &mut header);
+
+        assert_eq!(c_marshaler_name(&path), "cffi_array_uint8_t_32");
+        assert_eq!(
+            header,
+            "#ifndef CFFI_ARRAY_UINT8_T_32
+#define CFFI_ARRAY_UINT8_T_32
+typedef struct cffi_array_uint8_t_32 {
+    uint8_t data[32];
+} cffi_array_uint8_t_32;
+#endif
+
+"
+        );
+    }
+
+    #[test]
+    fn constant_array_length() {
+        let path: syn::Path = syn::parse_quote! { ::cffi::ArrayMarshaler::<u8, { crate::LEN }> };
+
+        let mut header = String::new();
+        c_declarations(&path, &mut header);
+
+        assert_eq!(c_marshaler_name(&path), "cffi_array_uint8_t_LEN");
+        assert!(header.contains("    uint8_t data[LEN];\n"));
+
+        let path: syn::Path = syn::parse_quote! { ::cffi::ArrayMarshaler::<u8, { 4 * 8 }> };
+        assert_eq!(
+            c_marshaler_name(&path),
+            "/* ArrayMarshaler: length must be a literal or a constant */"
+        );
+    }
+}
@@ -8,13 +8,20 @@ use quote::quote;
 mod attr;
 mod call_fn;
 mod call_impl;
+mod call_trait;
 mod ext;
+mod foreign_trait;
 mod function;
+mod header;
+mod normalize;
+mod passthrough;
 mod ptr_type;
+mod registry;
 mod return_type;
 
-use attr::invoke::InvokeParams;
+use attr::invoke::{HandleKind, InvokeParams};
 use ext::*;
+use function::ReturnMode;
 
 #[proc_macro_attribute]
 pub fn marshal(
@@ -24,7 +31,7 @@ pub fn marshal(
     let params = match NestedMeta::parse_meta_list(params.into()) {
         Ok(v) => v,
         Err(e) => {
-            return TokenStream::from(Error::from(e).write_errors()).into();
+            return Error::from(e).write_errors().into();
         }
     };
 
@@ -41,6 +48,105 @@ pub fn marshal(
     }
 }
 
+/// Lets foreign code implement a trait. For `trait Foo`, this generates:
+///
+///   - `FooVtable`: a `#[repr(C)]` struct of `userdata`, a function pointer per method and an
+///     optional `release`
+///   - `ForeignFoo`: implements `Foo` by calling through a `FooVtable`
+///   - `FooMarshaler`: converts a `FooVtable` into a `ForeignFoo` or `Box<dyn Foo>`
+///   - `FOO_HEADER`: the C declaration of `FooVtable`
+///
+/// Arguments are marshaled like those of `#[marshal]` functions, and are only borrowed by the
+/// foreign implementation for the duration of the call. Methods returning `Result<T, E>` where
+/// `E: From<Box<dyn Error>>` return marshaling failures as errors, with the vtable returning `T`.
+/// Other methods may only return passthrough types, and panic if an argument fails to marshal.
+///
+/// Rust takes ownership of marshaled return values, such as the `Slice` of a `String` or `Vec`,
+/// and frees them with the cffi allocator, so the foreign implementation must allocate them with
+/// `cffi_alloc`.
+#[proc_macro_attribute]
+pub fn foreign_trait(
+    params: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    if !params.is_empty() {
+        return syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "foreign_trait takes no parameters",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let result = syn::parse2(item.into())
+        .context("error parsing trait")
+        .and_then(foreign_trait::call_with_trait);
+
+    match result {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Declares a type alias that `#[marshal]` sees through when looking up passthrough types and
+/// default marshalers, so that after `#[cffi::alias] type Meters = u32;` a `Meters` parameter is
+/// passed through like a `u32`.
+///
+/// Macros cannot resolve names, so the alias is recorded as the compiler expands it and is only
+/// known to `#[marshal]` items that come after it in the crate. Aliases are matched by name, so
+/// a name may only be aliased once in a crate. Generic aliases are not supported.
+#[proc_macro_attribute]
+pub fn alias(
+    params: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    if !params.is_empty() {
+        return syn::Error::new(proc_macro2::Span::call_site(), "alias takes no parameters")
+            .to_compile_error()
+            .into();
+    }
+
+    let result = syn::parse2(item.into())
+        .context("alias is only supported on type aliases")
+        .and_then(normalize::call_with_alias);
+
+    match result {
+        Ok(item) => quote! { #item }.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Lets a `#[repr(C)]` or `#[repr(transparent)]` struct or union be passed to and from C as it
+/// is, like the primitive types, rather than through a `BoxMarshaler`.
+///
+/// The type must be valid for any value the foreign side may pass, so types with invariants are
+/// better marshaled. Enums are rejected, as C may pass a discriminant that matches no variant. Like `#[cffi::alias]`, it is only known to `#[marshal]` items
+/// that come after it in the crate, generic types are not supported, and as types are matched by
+/// name, two passthrough types in a crate may not share a name.
+#[proc_macro_attribute]
+pub fn passthrough(
+    params: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    if !params.is_empty() {
+        return syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "passthrough takes no parameters",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let result = syn::parse2(item.into())
+        .context("error parsing item")
+        .and_then(passthrough::call_with_passthrough);
+
+    match result {
+        Ok(item) => quote! { #item }.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
 #[ctor]
 fn init() {
     pretty_env_logger::init();
@@ -85,6 +191,21 @@ fn init() {
 //     }
 // }
 
+fn return_mode(
+    invoke_params: &InvokeParams,
+    span: impl quote::ToTokens,
+) -> Result<ReturnMode, syn::Error> {
+    match (invoke_params.callback, invoke_params.buffer) {
+        (false, false) => Ok(ReturnMode::Direct),
+        (true, false) => Ok(ReturnMode::Callback),
+        (false, true) => Ok(ReturnMode::Buffer),
+        (true, true) => Err(syn::Error::new_spanned(
+            span,
+            "callback and buffer modes are mutually exclusive",
+        )),
+    }
+}
+
 fn call_with(invoke_params: InvokeParams, item: TokenStream) -> Result<TokenStream, syn::Error> {
     // if let Some(value) = invoke_params.send_help.as_ref() {
     //     log::debug!("HELP REQUESTED: {}", value);
@@ -95,18 +216,47 @@ fn call_with(invoke_params: InvokeParams, item: TokenStream) -> Result<TokenStre
 
     let item: syn::Item = syn::parse2(item.clone()).context("error parsing function body")?;
     let result = match item {
-        syn::Item::Fn(item) => call_fn::call_with_function(
-            invoke_params.return_marshaler,
-            invoke_params.callback,
-            item,
-            None,
-        ),
-        syn::Item::Impl(item) => call_impl::call_with_impl(invoke_params.prefix, item),
+        syn::Item::Fn(item) => {
+            let return_mode = return_mode(&invoke_params, &item.sig.ident)?;
+            if invoke_params.sync || invoke_params.handle != HandleKind::Box {
+                return Err(syn::Error::new_spanned(
+                    &item.sig.ident,
+                    "sync and handle are only supported on impls",
+                ));
+            }
+            call_fn::call_with_function(
+                invoke_params.return_marshaler,
+                return_mode,
+                invoke_params.thread_confined,
+                item,
+                None,
+            )
+        }
+        syn::Item::Impl(item) => {
+            let return_mode = return_mode(&invoke_params, &item.self_ty)?;
+            call_impl::call_with_impl(
+                invoke_params.prefix,
+                invoke_params.handle,
+                invoke_params.sync,
+                return_mode,
+                invoke_params.thread_confined,
+                item,
+            )
+        }
+        syn::Item::Trait(item) => {
+            if invoke_params.sync || invoke_params.handle != HandleKind::Box {
+                return Err(syn::Error::new_spanned(
+                    &item.ident,
+                    "sync and handle are only supported on impls",
+                ));
+            }
+            call_trait::call_with_trait(invoke_params.prefix, invoke_params.thread_confined, item)
+        }
         item => {
             log::error!("{:?}", &item);
             Err(syn::Error::new_spanned(
                 &item,
-                "Only supported on functions and impls",
+                "Only supported on functions, impls and traits",
             ))
         }
     };
@@ -117,20 +267,25 @@ fn call_with(invoke_params: InvokeParams, item: TokenStream) -> Result<TokenStre
         log::debug!("macro finished successfully");
     }
 
-    result
+    // Rebuild when the crate's default marshalers change.
+    let registry = registry::registry_path().map(|path| {
+        let path = path.to_string_lossy();
+        quote! { const _: &[u8] = include_bytes!(#path); }
+    });
+
+    result.map(|tokens| quote! { #tokens #registry })
 }
 
 include!(concat!(env!("OUT_DIR"), "/codegen.rs"));
 
-pub(crate) fn default_marshaler(ty: &syn::Type) -> Option<syn::Path> {
-    DEFAULT_MARSHALERS
-        .get(&*quote! { #ty }.to_string())
-        .and_then(|x| syn::parse_str(x).ok())
-}
-
 pub(crate) fn is_passthrough_type(ty: &syn::Type) -> bool {
-    match ty {
-        syn::Type::BareFn(bare_fn) => bare_fn.abi.is_some(),
-        _ => PASSTHROUGH_TYPES.contains(&&*quote! { #ty }.to_string()),
+    match normalize::normalize_shallow(ty) {
+        syn::Type::Ptr(_) => true,
+        _ => {
+            let key = normalize::key(ty);
+            PASSTHROUGH_TYPES.contains(&&*key)
+                || passthrough::is_declared(&key)
+                || passthrough::is_passthrough_wrapper(ty)
+        }
     }
 }
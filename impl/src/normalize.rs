@@ -0,0 +1,286 @@
+//! Puts types into a canonical form before they are compared against the passthrough and default
+//! marshaler tables, so that `std::string::String`, `::core::primitive::u32` and aliases declared
+//! with `#[cffi::alias]` are found under the names the tables use.
+//!
+//! Normalized types are only ever compared, never emitted, as the shortened paths may not be in
+//! scope where the type was written.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Modules whose items are found by their last segment, such as `std::string` for `String`.
+const CANONICAL_MODULES: &[&[&str]] = &[
+    &["std", "string"],
+    &["alloc", "string"],
+    &["std", "vec"],
+    &["alloc", "vec"],
+    &["std", "boxed"],
+    &["alloc", "boxed"],
+    &["std", "option"],
+    &["core", "option"],
+    &["std", "result"],
+    &["core", "result"],
+    &["std", "sync"],
+    &["alloc", "sync"],
+    &["std", "rc"],
+    &["alloc", "rc"],
+    &["std", "primitive"],
+    &["core", "primitive"],
+    &["std", "error"],
+    &["core", "error"],
+    &["std", "ffi"],
+    &["core", "ffi"],
+    &["std", "path"],
+    &["std", "num"],
+    &["core", "num"],
+    &["std", "ptr"],
+    &["core", "ptr"],
+    &["std", "os", "raw"],
+    &["libc"],
+    &["url"],
+];
+
+/// How deeply aliases may refer to other aliases before expansion gives up.
+const MAX_ALIAS_DEPTH: usize = 16;
+
+/// The targets of aliases declared with `#[cffi::alias]`.
+static ALIASES: CrateRegistry = CrateRegistry::new();
+
+fn crate_key() -> String {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let crate_name = std::env::var("CARGO_CRATE_NAME").unwrap_or_default();
+    format!("{}#{}", manifest_dir, crate_name)
+}
+
+/// Items declared by attribute macros for later items to refer to, keyed by the crate that
+/// declared them and the item name.
+///
+/// Declarations are kept as strings, as tokens cannot outlive the macro invocation that created
+/// them. They are looked up by name alone, so a different declaration of the same name in another
+/// module of the crate would change what later items see, and is rejected. Repeating the same
+/// declaration, as when an item is expanded again, is harmless.
+pub(crate) struct CrateRegistry(Mutex<Option<HashMap<(String, String), String>>>);
+
+impl CrateRegistry {
+    pub const fn new() -> CrateRegistry {
+        CrateRegistry(Mutex::new(None))
+    }
+
+    /// Records `declaration` under `name` for this crate, or returns the different declaration
+    /// already recorded under it.
+    pub fn declare(&self, name: &str, declaration: String) -> Result<(), String> {
+        let mut declared = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let declared = declared.get_or_insert_with(HashMap::new);
+
+        let key = (crate_key(), name.to_string());
+        match declared.get(&key) {
+            Some(existing) if *existing != declaration => Err(existing.clone()),
+            _ => {
+                declared.insert(key, declaration);
+                Ok(())
+            }
+        }
+    }
+
+    /// The declaration recorded under `name` for this crate.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let declared = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        declared
+            .as_ref()?
+            .get(&(crate_key(), name.to_string()))
+            .cloned()
+    }
+}
+
+/// Handles `#[cffi::alias] type Name = Target;`, recording that `Name` should be looked up as
+/// `Target` from here on. The item itself is left as it is.
+pub fn call_with_alias(item: syn::ItemType) -> Result<syn::ItemType, syn::Error> {
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &item.generics,
+            "generic aliases are not supported",
+        ));
+    }
+
+    let target = &item.ty;
+    let target = quote::quote! { #target }.to_string();
+
+    if let Err(existing) = ALIASES.declare(&item.ident.to_string(), target) {
+        return Err(syn::Error::new_spanned(
+            &item.ident,
+            format!(
+                "`{}` is already an alias of `{}` in this crate; alias names must be unique",
+                item.ident, existing
+            ),
+        ));
+    }
+
+    Ok(item)
+}
+
+fn alias_target(ident: &syn::Ident) -> Option<syn::Type> {
+    let target = ALIASES.get(&ident.to_string())?;
+    syn::parse_str(&target).ok()
+}
+
+/// `path` without a leading `std::string::` or the like, or `None` if it has no such prefix.
+fn strip_module(path: &syn::Path) -> Option<syn::Path> {
+    let len = path.segments.len();
+
+    let is_module = CANONICAL_MODULES.iter().any(|module| {
+        module.len() + 1 == len
+            && path
+                .segments
+                .iter()
+                .zip(module.iter())
+                .all(|(segment, name)| segment.ident == name && segment.arguments.is_none())
+    });
+
+    is_module.then(|| syn::Path {
+        leading_colon: None,
+        segments: path.segments.iter().skip(len - 1).cloned().collect(),
+    })
+}
+
+/// The alias a single-segment path names, if any.
+fn expand_alias(path: &syn::Path) -> Option<syn::Type> {
+    match path.segments.first() {
+        Some(segment) if path.segments.len() == 1 && segment.arguments.is_none() => {
+            alias_target(&segment.ident)
+        }
+        _ => None,
+    }
+}
+
+/// Normalizes the outermost layer of `ty`, expanding aliases and shortening canonical paths, but
+/// leaves its generic arguments and element types as written.
+pub fn normalize_shallow(ty: &syn::Type) -> syn::Type {
+    let mut ty = ty.clone();
+
+    for _ in 0..MAX_ALIAS_DEPTH {
+        let path = match &ty {
+            syn::Type::Path(p) if p.qself.is_none() => &p.path,
+            syn::Type::Group(group) => {
+                ty = (*group.elem).clone();
+                continue;
+            }
+            syn::Type::Paren(paren) => {
+                ty = (*paren.elem).clone();
+                continue;
+            }
+            _ => return ty,
+        };
+
+        let path = strip_module(path).unwrap_or_else(|| path.clone());
+        match expand_alias(&path) {
+            Some(target) => ty = target,
+            None => {
+                return syn::Type::Path(syn::TypePath { qself: None, path });
+            }
+        }
+    }
+
+    ty
+}
+
+/// Normalizes `ty` and every type within it.
+pub fn normalize(ty: &syn::Type) -> syn::Type {
+    match normalize_shallow(ty) {
+        syn::Type::Path(mut p) => {
+            for segment in p.path.segments.iter_mut() {
+                if let syn::PathArguments::AngleBracketed(args) = &mut segment.arguments {
+                    for arg in args.args.iter_mut() {
+                        if let syn::GenericArgument::Type(ty) = arg {
+                            *ty = normalize(ty);
+                        }
+                    }
+                }
+            }
+            syn::Type::Path(p)
+        }
+        syn::Type::Reference(mut r) => {
+            *r.elem = normalize(&r.elem);
+            syn::Type::Reference(r)
+        }
+        syn::Type::Ptr(mut p) => {
+            *p.elem = normalize(&p.elem);
+            syn::Type::Ptr(p)
+        }
+        syn::Type::Slice(mut s) => {
+            *s.elem = normalize(&s.elem);
+            syn::Type::Slice(s)
+        }
+        syn::Type::Array(mut a) => {
+            *a.elem = normalize(&a.elem);
+            syn::Type::Array(a)
+        }
+        syn::Type::Tuple(mut t) => {
+            for elem in t.elems.iter_mut() {
+                *elem = normalize(elem);
+            }
+            syn::Type::Tuple(t)
+        }
+        syn::Type::TraitObject(mut t) => {
+            for bound in t.bounds.iter_mut() {
+                if let syn::TypeParamBound::Trait(bound) = bound {
+                    bound.path = strip_module(&bound.path).unwrap_or_else(|| bound.path.clone());
+                }
+            }
+            syn::Type::TraitObject(t)
+        }
+        ty => ty,
+    }
+}
+
+/// The string `ty` is looked up under in the tables generated by `build.rs`.
+pub fn key(ty: &syn::Type) -> String {
+    let ty = normalize(ty);
+    quote::quote! { #ty }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_of(ty: &str) -> String {
+        key(&syn::parse_str(ty).unwrap())
+    }
+
+    #[test]
+    fn canonical_paths() {
+        assert_eq!(key_of("std::string::String"), key_of("String"));
+        assert_eq!(key_of("::core::primitive::u32"), key_of("u32"));
+        assert_eq!(
+            key_of("Option<::std::string::String>"),
+            key_of("Option<String>")
+        );
+        assert_eq!(
+            key_of("Result<(), Box<dyn std::error::Error>>"),
+            key_of("Result<(), Box<dyn Error>>")
+        );
+        assert_eq!(key_of("&[core::primitive::u8]"), key_of("&[u8]"));
+        assert_ne!(key_of("my::string::String"), key_of("String"));
+    }
+
+    #[test]
+    fn aliases() {
+        call_with_alias(syn::parse_quote! { type Meters = ::core::primitive::u32; }).unwrap();
+        call_with_alias(syn::parse_quote! { type Distance = Meters; }).unwrap();
+        call_with_alias(syn::parse_quote! { type Loop = Loop; }).unwrap();
+
+        assert_eq!(key_of("Distance"), key_of("u32"));
+        assert_eq!(key_of("Vec<Meters>"), key_of("Vec<u32>"));
+        assert_eq!(key_of("Loop"), key_of("Loop"));
+        assert!(call_with_alias(syn::parse_quote! { type Id<T> = Vec<T>; }).is_err());
+    }
+
+    #[test]
+    fn duplicate_aliases() {
+        call_with_alias(syn::parse_quote! { type Seconds = u64; }).unwrap();
+        call_with_alias(syn::parse_quote! { type Seconds = u64; }).unwrap();
+
+        let err = call_with_alias(syn::parse_quote! { type Seconds = f64; }).unwrap_err();
+        assert!(err.to_string().contains("already an alias"), "{}", err);
+        assert_eq!(key_of("Seconds"), key_of("u64"));
+    }
+}
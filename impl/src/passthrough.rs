@@ -0,0 +1,210 @@
+//! Decides which types are passed to and from C as they are, without a marshaler: primitives and
+//! their libc names from the table generated by `build.rs`, raw and non-null pointers, `extern`
+//! function pointers, and user types declared with `#[cffi::passthrough]`.
+
+use syn::punctuated::Punctuated;
+
+use crate::normalize::{self, CrateRegistry};
+
+/// Types declared with `#[cffi::passthrough]`, with the declaring item to tell a repeated
+/// expansion from another type of the same name.
+static DECLARED: CrateRegistry = CrateRegistry::new();
+
+/// The integer types `NonZero<T>` may be used with.
+const INTEGERS: &[&str] = &[
+    "u8", "i8", "u16", "i16", "u32", "i32", "u64", "i64", "usize", "isize",
+];
+
+/// The `NonZero<T>` aliases for each of `INTEGERS`.
+const NON_ZERO_INTEGERS: &[&str] = &[
+    "NonZeroU8",
+    "NonZeroI8",
+    "NonZeroU16",
+    "NonZeroI16",
+    "NonZeroU32",
+    "NonZeroI32",
+    "NonZeroU64",
+    "NonZeroI64",
+    "NonZeroUsize",
+    "NonZeroIsize",
+];
+
+/// Handles `#[cffi::passthrough]` on a struct or union, recording that it may be passed
+/// through from here on. The item itself is left as it is.
+pub fn call_with_passthrough(item: syn::Item) -> Result<syn::Item, syn::Error> {
+    let (ident, attrs, generics) = match &item {
+        syn::Item::Struct(item) => (&item.ident, &item.attrs, &item.generics),
+        syn::Item::Enum(item) => {
+            return Err(syn::Error::new_spanned(
+                &item.ident,
+                "enums may not be passed through, as a discriminant from C that matches no variant would be an invalid value; use a marshaler that checks the discriminant instead",
+            ))
+        }
+        syn::Item::Union(item) => (&item.ident, &item.attrs, &item.generics),
+        item => {
+            return Err(syn::Error::new_spanned(
+                item,
+                "passthrough is only supported on structs and unions",
+            ))
+        }
+    };
+
+    if !generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            generics,
+            "generic passthrough types are not supported",
+        ));
+    }
+
+    if !has_c_repr(attrs)? {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "passthrough types must be #[repr(C)] or #[repr(transparent)]",
+        ));
+    }
+
+    let tokens = quote::quote! { #item }.to_string();
+    if DECLARED.declare(&ident.to_string(), tokens).is_err() {
+        return Err(syn::Error::new_spanned(
+            ident,
+            format!(
+                "a passthrough type named `{}` is already declared in this crate; passthrough type names must be unique",
+                ident
+            ),
+        ));
+    }
+
+    Ok(item)
+}
+
+fn has_c_repr(attrs: &[syn::Attribute]) -> Result<bool, syn::Error> {
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("repr")) {
+        let reprs =
+            attr.parse_args_with(Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated)?;
+        if reprs
+            .iter()
+            .any(|repr| repr.path().is_ident("C") || repr.path().is_ident("transparent"))
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Whether `key` names a type declared with `#[cffi::passthrough]` in this crate.
+pub fn is_declared(key: &str) -> bool {
+    DECLARED.get(key).is_some()
+}
+
+/// The single type argument of `ty` if its last segment is `name`, as `T` for `NonNull<T>`.
+pub fn type_argument<'a>(ty: &'a syn::Type, name: &str) -> Option<&'a syn::Type> {
+    let segment = match ty {
+        syn::Type::Path(p) if p.qself.is_none() => p.path.segments.last()?,
+        _ => return None,
+    };
+
+    if segment.ident != name {
+        return None;
+    }
+
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => {
+            match args.args.first()? {
+                syn::GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Whether `ty` can never be zero, so that `Option<ty>` has the same representation with `None`
+/// as zero: `NonNull<T>`, `NonZeroU32`, `NonZero<u32>` and `extern "C" fn`s.
+fn is_non_zero(ty: &syn::Type) -> bool {
+    let ty = normalize::normalize_shallow(ty);
+
+    if let syn::Type::BareFn(bare_fn) = &ty {
+        return bare_fn.abi.is_some();
+    }
+
+    if type_argument(&ty, "NonNull").is_some() {
+        return true;
+    }
+
+    match type_argument(&ty, "NonZero") {
+        Some(int) => INTEGERS.contains(&&*normalize::key(int)),
+        None => NON_ZERO_INTEGERS.contains(&&*normalize::key(&ty)),
+    }
+}
+
+/// Whether `ty` is an `Option` of a pointer-like or integer type which uses zero for `None`, and
+/// so may be passed through. The types themselves are marshaled, as zero from C would be an
+/// invalid value.
+pub fn is_passthrough_wrapper(ty: &syn::Type) -> bool {
+    type_argument(&normalize::normalize_shallow(ty), "Option").is_some_and(is_non_zero)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passthrough(ty: &str) -> bool {
+        crate::is_passthrough_type(&syn::parse_str(ty).unwrap())
+    }
+
+    #[test]
+    fn passthrough_types() {
+        assert!(passthrough("libc::c_int"));
+        assert!(passthrough("std::os::raw::c_ulong"));
+        assert!(passthrough("core::ffi::c_char"));
+        assert!(passthrough("*const std::ffi::c_void"));
+        assert!(passthrough("*mut Foo"));
+        assert!(passthrough("Option<NonNull<u8>>"));
+        assert!(passthrough("Option<extern \"C\" fn(u32)>"));
+        assert!(passthrough("Option<std::num::NonZeroU64>"));
+        assert!(passthrough("Option<NonZero<u32>>"));
+        assert!(!passthrough("std::ptr::NonNull<Foo>"));
+        assert!(!passthrough("extern \"C\" fn(u32)"));
+        assert!(!passthrough("std::num::NonZeroU64"));
+        assert!(!passthrough("NonZero<u32>"));
+        assert!(!passthrough("Option<fn(u32)>"));
+        assert!(!passthrough("Option<u32>"));
+        assert!(!passthrough("NonZero<String>"));
+        assert!(!passthrough("char"));
+        assert!(!passthrough("u128"));
+
+        assert!(!passthrough("Point"));
+        call_with_passthrough(syn::parse_quote! {
+            #[repr(C)]
+            struct Point { x: f32, y: f32 }
+        })
+        .unwrap();
+        assert!(passthrough("Point"));
+
+        call_with_passthrough(syn::parse_quote! {
+            #[repr(C)]
+            struct Point { x: f32, y: f32 }
+        })
+        .unwrap();
+        let err = call_with_passthrough(syn::parse_quote! {
+            #[repr(C)]
+            struct Point { x: f64, y: f64 }
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("already declared"), "{}", err);
+
+        assert!(call_with_passthrough(syn::parse_quote! { struct Opaque(u32); }).is_err());
+        let err = call_with_passthrough(syn::parse_quote! {
+            #[repr(C)]
+            enum Color { Red, Green }
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("marshaler"), "{}", err);
+        assert!(call_with_passthrough(syn::parse_quote! {
+            #[repr(transparent)]
+            struct Wrapper<T>(T);
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,659 @@
+//! Default marshalers for types used without a `#[marshal(...)]` attribute.
+//!
+//! The built-in defaults are generated by `build.rs`. A crate may register its own, which take
+//! precedence over the built-ins, in a `cffi.toml` next to its `Cargo.toml`:
+//!
+//! ```toml
+//! [marshalers]
+//! "Uuid" = "crate::UuidMarshaler"
+//! "Tree<T>" = "crate::TreeMarshaler::<T>"
+//! ```
+//!
+//! `T` matches any type other than a trait object, `dyn T` matches any trait object, and the
+//! matched type is substituted for `T` in the marshaler. Both sides are normalized first, so an
+//! entry for `String` also covers `std::string::String` and aliases declared with
+//! `#[cffi::alias]`.
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use proc_macro2::{Group, Span, TokenStream, TokenTree};
+use quote::quote;
+use syn::punctuated::Punctuated;
+
+use crate::normalize::{self, normalize, normalize_shallow};
+
+/// Whether a value is passed to Rust or returned from it, as `Arc<T>` is borrowed by the former
+/// but handed over by the latter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Input,
+    Return,
+}
+
+struct Entry {
+    pattern: syn::Type,
+    input: TokenStream,
+    ret: TokenStream,
+}
+
+impl Entry {
+    fn parse(ty: &str, input: &str, ret: &str) -> Result<Entry, syn::Error> {
+        Ok(Entry {
+            pattern: normalize(&syn::parse_str(ty)?),
+            input: syn::parse_str(input)?,
+            ret: syn::parse_str(ret)?,
+        })
+    }
+
+    fn resolve(
+        &self,
+        ty: &syn::Type,
+        direction: Direction,
+    ) -> Result<Option<syn::Path>, syn::Error> {
+        let mut binding = None;
+        if !unify(&self.pattern, ty, &mut binding) {
+            return Ok(None);
+        }
+
+        let marshaler = match direction {
+            Direction::Input => self.input.clone(),
+            Direction::Return => self.ret.clone(),
+        };
+
+        let marshaler = match binding {
+            Some(binding) => substitute(marshaler, &binding),
+            None => marshaler,
+        };
+
+        syn::parse2(marshaler).map(Some)
+    }
+}
+
+/// Whether `ty` has the shape of `pattern`, binding the placeholder `T` to the type it matches.
+///
+/// The pattern must already be normalized, while `ty` is normalized as it is walked so that `T`
+/// is bound to the type as written.
+fn unify(pattern: &syn::Type, ty: &syn::Type, binding: &mut Option<syn::Type>) -> bool {
+    let normalized = normalize_shallow(ty);
+
+    match (pattern, &normalized) {
+        (syn::Type::Path(p), _) if p.qself.is_none() && p.path.is_ident("T") => {
+            if matches!(
+                normalized,
+                syn::Type::TraitObject(_) | syn::Type::ImplTrait(_)
+            ) {
+                return false;
+            }
+
+            bind(binding, ty)
+        }
+        (syn::Type::TraitObject(p), syn::Type::TraitObject(_)) if is_placeholder(&p.bounds) => {
+            bind(binding, ty)
+        }
+        (syn::Type::Path(p), syn::Type::Path(t)) => {
+            p.qself.is_none()
+                && t.qself.is_none()
+                && p.path.leading_colon.is_some() == t.path.leading_colon.is_some()
+                && p.path.segments.len() == t.path.segments.len()
+                && p.path
+                    .segments
+                    .iter()
+                    .zip(t.path.segments.iter())
+                    .all(|(p, t)| {
+                        p.ident == t.ident && unify_arguments(&p.arguments, &t.arguments, binding)
+                    })
+        }
+        (syn::Type::Reference(p), syn::Type::Reference(t)) => {
+            p.mutability.is_some() == t.mutability.is_some()
+                && tokens_eq(&p.lifetime, &t.lifetime)
+                && unify(&p.elem, &t.elem, binding)
+        }
+        (syn::Type::Slice(p), syn::Type::Slice(t)) => unify(&p.elem, &t.elem, binding),
+        _ => tokens_eq(pattern, normalize(ty)),
+    }
+}
+
+/// Binds the placeholder to `ty`, or checks that it is already bound to the same type.
+fn bind(binding: &mut Option<syn::Type>, ty: &syn::Type) -> bool {
+    match binding {
+        Some(bound) => normalize::key(bound) == normalize::key(ty),
+        None => {
+            *binding = Some(ty.clone());
+            true
+        }
+    }
+}
+
+/// Whether the bounds of `dyn T` are just the placeholder.
+fn is_placeholder(bounds: &Punctuated<syn::TypeParamBound, syn::Token![+]>) -> bool {
+    match bounds.first() {
+        Some(syn::TypeParamBound::Trait(bound)) => bounds.len() == 1 && bound.path.is_ident("T"),
+        _ => false,
+    }
+}
+
+fn unify_arguments(
+    pattern: &syn::PathArguments,
+    args: &syn::PathArguments,
+    binding: &mut Option<syn::Type>,
+) -> bool {
+    match (pattern, args) {
+        (syn::PathArguments::AngleBracketed(p), syn::PathArguments::AngleBracketed(a)) => {
+            p.args.len() == a.args.len()
+                && p.args.iter().zip(a.args.iter()).all(|pair| match pair {
+                    (syn::GenericArgument::Type(p), syn::GenericArgument::Type(a)) => {
+                        unify(p, a, binding)
+                    }
+                    (p, a) => tokens_eq(p, a),
+                })
+        }
+        (p, a) => tokens_eq(p, a),
+    }
+}
+
+fn tokens_eq(a: impl quote::ToTokens, b: impl quote::ToTokens) -> bool {
+    quote! { #a }.to_string() == quote! { #b }.to_string()
+}
+
+/// Replaces the placeholder `T` in `tokens` with `ty`.
+fn substitute(tokens: TokenStream, ty: &syn::Type) -> TokenStream {
+    tokens
+        .into_iter()
+        .map(|tt| match tt {
+            TokenTree::Ident(ident) if ident == "T" => quote! { #ty },
+            TokenTree::Group(group) => {
+                let mut substituted = Group::new(group.delimiter(), substitute(group.stream(), ty));
+                substituted.set_span(group.span());
+                TokenTree::Group(substituted).into()
+            }
+            tt => tt.into(),
+        })
+        .collect()
+}
+
+/// The `cffi.toml` of the crate being compiled, if it has one.
+pub fn registry_path() -> Option<PathBuf> {
+    let path = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR")?).join("cffi.toml");
+    path.is_file().then_some(path)
+}
+
+/// Reads a key or value: a bare key, or a basic or literal string.
+fn parse_string(s: &str) -> Option<(String, &str)> {
+    let mut chars = s.char_indices();
+
+    match chars.next()?.1 {
+        '\'' => {
+            let end = s[1..].find('\'')? + 1;
+            Some((s[1..end].to_string(), &s[end + 1..]))
+        }
+        '"' => {
+            let mut out = String::new();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => return Some((out, &s[i + 1..])),
+                    '\\' => match chars.next()?.1 {
+                        '"' => out.push('"'),
+                        '\\' => out.push('\\'),
+                        _ => return None,
+                    },
+                    c => out.push(c),
+                }
+            }
+            None
+        }
+        _ => {
+            let end = s
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(s.len());
+            (end > 0).then(|| (s[..end].to_string(), &s[end..]))
+        }
+    }
+}
+
+/// Reads the `[marshalers]` table of a `cffi.toml`. Only the subset of TOML it needs is
+/// supported: table headers, comments, and string keys and values.
+fn parse_registry(source: &str) -> Result<Vec<(String, String)>, String> {
+    let mut in_marshalers = false;
+    let mut entries = vec![];
+
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            in_marshalers = line.split('#').next().map(str::trim) == Some("[marshalers]");
+            continue;
+        }
+
+        if !in_marshalers {
+            continue;
+        }
+
+        let err = || format!("line {}: expected \"type\" = \"marshaler\"", i + 1);
+        let (key, rest) = parse_string(line).ok_or_else(err)?;
+        let rest = rest.trim_start().strip_prefix('=').ok_or_else(err)?;
+        let (value, rest) = parse_string(rest.trim_start()).ok_or_else(err)?;
+        let rest = rest.trim_start();
+        if !(rest.is_empty() || rest.starts_with('#')) {
+            return Err(err());
+        }
+
+        entries.push((key, value));
+    }
+
+    Ok(entries)
+}
+
+type Registry = (PathBuf, Option<SystemTime>, Rc<Vec<(String, String)>>);
+
+thread_local! {
+    // The entries of `cffi.toml`, re-read if it changes while the compiler is running. They are
+    // kept as strings for the same reason as the declarations in a `CrateRegistry`.
+    static REGISTRY: RefCell<Option<Registry>> = const { RefCell::new(None) };
+}
+
+fn user_entries() -> Result<Vec<Entry>, syn::Error> {
+    let path = match registry_path() {
+        Some(v) => v,
+        None => return Ok(vec![]),
+    };
+    let modified = std::fs::metadata(&path).and_then(|x| x.modified()).ok();
+    let error =
+        |msg: String| syn::Error::new(Span::call_site(), format!("{}: {}", path.display(), msg));
+
+    let cached = REGISTRY.with(|registry| match &*registry.borrow() {
+        Some((p, m, entries)) if *p == path && *m == modified => Some(entries.clone()),
+        _ => None,
+    });
+
+    let entries = match cached {
+        Some(entries) => entries,
+        None => {
+            let source = std::fs::read_to_string(&path).map_err(|e| error(e.to_string()))?;
+            let entries = Rc::new(parse_registry(&source).map_err(error)?);
+            REGISTRY.with(|registry| {
+                *registry.borrow_mut() = Some((path.clone(), modified, entries.clone()))
+            });
+            entries
+        }
+    };
+
+    entries
+        .iter()
+        .map(|(ty, marshaler)| {
+            Entry::parse(ty, marshaler, marshaler)
+                .map_err(|e| error(format!("invalid entry \"{}\": {}", ty, e)))
+        })
+        .collect()
+}
+
+/// Finds the default marshaler for `ty`, trying the crate's `cffi.toml` first, then the built-in
+/// exact matches, then the built-in generic ones.
+pub fn default_marshaler(
+    ty: &syn::Type,
+    direction: Direction,
+) -> Result<Option<syn::Path>, syn::Error> {
+    for entry in user_entries()?.iter() {
+        if let Some(path) = entry.resolve(ty, direction)? {
+            return Ok(Some(path));
+        }
+    }
+
+    match normalize_shallow(ty) {
+        syn::Type::Tuple(tuple) => return tuple_marshaler(&tuple, direction),
+        syn::Type::Array(array) => return array_marshaler(&array, false),
+        syn::Type::Reference(r) if r.mutability.is_none() && direction == Direction::Input => {
+            match normalize_shallow(&r.elem) {
+                syn::Type::Array(array) => return array_marshaler(&array, true),
+                syn::Type::Slice(slice) => return slice_marshaler(&slice),
+                _ => {}
+            }
+        }
+        syn::Type::ImplTrait(ty) if direction == Direction::Return => {
+            return iter_marshaler(&ty.bounds);
+        }
+        // A null function pointer from C would be an invalid value.
+        syn::Type::BareFn(bare_fn) if bare_fn.abi.is_some() => {
+            return syn::parse2(quote! { ::cffi::FnPtrMarshaler::<#ty> }).map(Some);
+        }
+        ty => {
+            if let Some(inner) = crate::passthrough::type_argument(&ty, "Option") {
+                return option_marshaler(&ty, inner, direction);
+            }
+
+            if let Some(elem) = crate::passthrough::type_argument(&ty, "Vec") {
+                return vec_marshaler(elem, direction);
+            }
+
+            if let Some(syn::Type::TraitObject(object)) =
+                crate::passthrough::type_argument(&ty, "Box")
+            {
+                if direction == Direction::Return && iterator_item(&object.bounds).is_some() {
+                    return iter_marshaler(&object.bounds);
+                }
+            }
+        }
+    }
+
+    if let Some(path) = crate::DEFAULT_MARSHALERS.get(&*normalize::key(ty)) {
+        return syn::parse_str(path).map(Some);
+    }
+
+    for (pattern, input, ret) in crate::GENERIC_MARSHALERS {
+        if let Some(path) = Entry::parse(pattern, input, ret)?.resolve(ty, direction)? {
+            return Ok(Some(path));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Marshals an `Option` with an `OptionMarshaler` of its inner type's default marshaler, unless
+/// it is passed through as it is, as `Option<NonNull<T>>` is.
+fn option_marshaler(
+    ty: &syn::Type,
+    inner: &syn::Type,
+    direction: Direction,
+) -> Result<Option<syn::Path>, syn::Error> {
+    if crate::passthrough::is_passthrough_wrapper(ty) {
+        return Ok(None);
+    }
+
+    match default_marshaler(inner, direction)? {
+        Some(path) => syn::parse2(quote! { ::cffi::OptionMarshaler::<#path> }).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Marshals a tuple with a `TupleMarshaler` of its elements' default marshalers, passing
+/// passthrough elements through a `CopyMarshaler`.
+fn tuple_marshaler(
+    tuple: &syn::TypeTuple,
+    direction: Direction,
+) -> Result<Option<syn::Path>, syn::Error> {
+    match tuple.elems.len() {
+        0 | 1 => return Ok(None),
+        2..=6 => {}
+        _ => {
+            return Err(syn::Error::new_spanned(
+                tuple,
+                "tuples of more than six elements are not supported",
+            ))
+        }
+    }
+
+    let mut marshalers = vec![];
+    for elem in tuple.elems.iter() {
+        let marshaler = match default_marshaler(elem, direction)? {
+            Some(path) => quote! { #path },
+            None if crate::is_passthrough_type(elem) => quote! { ::cffi::CopyMarshaler::<#elem> },
+            None => return Ok(None),
+        };
+        marshalers.push(marshaler);
+    }
+
+    syn::parse2(quote! { ::cffi::TupleMarshaler::<(#(#marshalers,)*)> }).map(Some)
+}
+
+/// The `T` of an `Iterator<Item = T>` bound.
+fn iterator_item(bounds: &Punctuated<syn::TypeParamBound, syn::Token![+]>) -> Option<&syn::Type> {
+    bounds.iter().find_map(|bound| {
+        let segment = match bound {
+            syn::TypeParamBound::Trait(bound) => bound.path.segments.last()?,
+            _ => return None,
+        };
+
+        match &segment.arguments {
+            syn::PathArguments::AngleBracketed(args) if segment.ident == "Iterator" => {
+                args.args.iter().find_map(|arg| match arg {
+                    syn::GenericArgument::AssocType(assoc) if assoc.ident == "Item" => {
+                        Some(&assoc.ty)
+                    }
+                    _ => None,
+                })
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Returns an iterator as a cursor with an `IterMarshaler` of its items' default marshaler,
+/// passing passthrough items through a `CopyMarshaler`.
+fn iter_marshaler(
+    bounds: &Punctuated<syn::TypeParamBound, syn::Token![+]>,
+) -> Result<Option<syn::Path>, syn::Error> {
+    let item = match iterator_item(bounds) {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+
+    // The cursor outlives the call, so the iterator may not borrow `self` or the arguments.
+    let borrowed = bounds.iter().find_map(|bound| match bound {
+        syn::TypeParamBound::Lifetime(lifetime) if lifetime.ident != "static" => Some(lifetime),
+        _ => None,
+    });
+    if let Some(lifetime) = borrowed {
+        return Err(syn::Error::new_spanned(
+            lifetime,
+            "iterators returned to C must be 'static, as the cursor outlives the call; clone or collect what the iterator borrows",
+        ));
+    }
+
+    let marshaler = match default_marshaler(item, Direction::Return)? {
+        Some(path) => quote! { #path },
+        None if crate::is_passthrough_type(item) => quote! { ::cffi::CopyMarshaler::<#item> },
+        None => return Ok(None),
+    };
+
+    syn::parse2(quote! { ::cffi::IterMarshaler::<#marshaler> }).map(Some)
+}
+
+/// Hands over the buffer of a `Vec` of a passthrough type with a `VecMarshaler`, and converts
+/// other elements one by one with a `VecOfMarshaler` of their default marshaler.
+fn vec_marshaler(elem: &syn::Type, direction: Direction) -> Result<Option<syn::Path>, syn::Error> {
+    if crate::is_passthrough_type(elem) {
+        return syn::parse2(quote! { ::cffi::VecMarshaler::<#elem> }).map(Some);
+    }
+
+    match default_marshaler(elem, direction)? {
+        Some(path) => syn::parse2(quote! { ::cffi::VecOfMarshaler::<#path> }).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Borrows a slice of a passthrough type with a `VecRefMarshaler`. Slices of other types cannot
+/// be borrowed from foreign memory.
+fn slice_marshaler(slice: &syn::TypeSlice) -> Result<Option<syn::Path>, syn::Error> {
+    let elem = &slice.elem;
+    if !crate::is_passthrough_type(elem) {
+        return Ok(None);
+    }
+
+    syn::parse2(quote! { ::cffi::VecRefMarshaler::<#elem> }).map(Some)
+}
+
+/// Marshals an array of a passthrough type with an `ArrayMarshaler`, or a reference to one with
+/// an `ArrayRefMarshaler`.
+fn array_marshaler(array: &syn::TypeArray, by_ref: bool) -> Result<Option<syn::Path>, syn::Error> {
+    let elem = &array.elem;
+    if !crate::is_passthrough_type(elem) {
+        return Ok(None);
+    }
+
+    // Lengths other than literals must be braced to be parsed as const arguments.
+    let len = match &array.len {
+        len @ syn::Expr::Lit(_) => quote! { #len },
+        len => quote! { { #len } },
+    };
+
+    match by_ref {
+        true => syn::parse2(quote! { ::cffi::ArrayRefMarshaler::<#elem, #len> }).map(Some),
+        false => syn::parse2(quote! { ::cffi::ArrayMarshaler::<#elem, #len> }).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(ty: &str, direction: Direction) -> Option<String> {
+        let ty: syn::Type = syn::parse_str(ty).unwrap();
+        default_marshaler(&ty, direction)
+            .unwrap()
+            .map(|path| quote! { #path }.to_string().replace(' ', ""))
+    }
+
+    #[test]
+    fn builtins() {
+        assert_eq!(
+            resolve("String", Direction::Input).as_deref(),
+            Some("::cffi::StringMarshaler")
+        );
+        assert_eq!(
+            resolve("Vec<u8>", Direction::Input).as_deref(),
+            Some("::cffi::VecMarshaler::<u8>")
+        );
+        assert_eq!(
+            resolve("&[f64]", Direction::Input).as_deref(),
+            Some("::cffi::VecRefMarshaler::<f64>")
+        );
+        assert_eq!(resolve("&[Vec<u8>]", Direction::Input), None);
+        assert_eq!(
+            resolve("Vec<Vec<u8>>", Direction::Return).as_deref(),
+            Some("::cffi::VecOfMarshaler::<::cffi::VecMarshaler::<u8>>")
+        );
+        assert_eq!(resolve("Vec<Foo>", Direction::Input), None);
+        assert_eq!(
+            resolve("Arc<Foo>", Direction::Input).as_deref(),
+            Some("::cffi::ArcRefMarshaler::<Foo>")
+        );
+        assert_eq!(
+            resolve("Arc<Foo>", Direction::Return).as_deref(),
+            Some("::cffi::ArcMarshaler::<Foo>")
+        );
+        assert_eq!(
+            resolve("Arc<dyn Foo + Send>", Direction::Return).as_deref(),
+            Some("::cffi::ArcMarshaler::<dynFoo+Send>")
+        );
+        assert_eq!(
+            resolve(
+                "::std::option::Option<std::string::String>",
+                Direction::Input
+            )
+            .as_deref(),
+            Some("::cffi::OptionMarshaler::<::cffi::StringMarshaler>")
+        );
+        assert_eq!(
+            resolve("Option<&str>", Direction::Input).as_deref(),
+            Some("::cffi::OptionMarshaler::<::cffi::StrMarshaler>")
+        );
+        assert_eq!(
+            resolve("Option<PathBuf>", Direction::Input).as_deref(),
+            Some("::cffi::OptionMarshaler::<::cffi::PathBufMarshaler>")
+        );
+        assert_eq!(
+            resolve("Option<Vec<u8>>", Direction::Return).as_deref(),
+            Some("::cffi::OptionMarshaler::<::cffi::VecMarshaler::<u8>>")
+        );
+        assert_eq!(resolve("Option<u32>", Direction::Input), None);
+        assert_eq!(resolve("Option<NonZeroU32>", Direction::Input), None);
+        assert_eq!(
+            resolve("std::vec::Vec<std::path::PathBuf>", Direction::Input).as_deref(),
+            Some("::cffi::VecOfMarshaler::<::cffi::PathBufMarshaler>")
+        );
+        assert_eq!(
+            resolve("(String, u32)", Direction::Input).as_deref(),
+            Some(
+                "::cffi::TupleMarshaler::<(::cffi::StringMarshaler,::cffi::CopyMarshaler::<u32>,)>"
+            )
+        );
+        assert_eq!(resolve("(String, Foo)", Direction::Input), None);
+        assert_eq!(
+            resolve("[u8; 32]", Direction::Return).as_deref(),
+            Some("::cffi::ArrayMarshaler::<u8,32>")
+        );
+        assert_eq!(
+            resolve("&[f32; LEN]", Direction::Input).as_deref(),
+            Some("::cffi::ArrayRefMarshaler::<f32,{LEN}>")
+        );
+        assert_eq!(resolve("&[f32; 4]", Direction::Return), None);
+        assert_eq!(resolve("[Foo; 4]", Direction::Input), None);
+        assert_eq!(
+            resolve("char", Direction::Input).as_deref(),
+            Some("::cffi::CharMarshaler")
+        );
+        assert_eq!(
+            resolve("core::primitive::u128", Direction::Return).as_deref(),
+            Some("::cffi::Int128Marshaler::<u128>")
+        );
+        assert_eq!(resolve("Box<dyn Foo>", Direction::Input), None);
+        assert_eq!(
+            resolve("impl Iterator<Item = String>", Direction::Return).as_deref(),
+            Some("::cffi::IterMarshaler::<::cffi::StringMarshaler>")
+        );
+        assert_eq!(
+            resolve("Box<dyn Iterator<Item = u32> + Send>", Direction::Return).as_deref(),
+            Some("::cffi::IterMarshaler::<::cffi::CopyMarshaler::<u32>>")
+        );
+        assert_eq!(
+            resolve("impl Iterator<Item = Foo>", Direction::Return),
+            None
+        );
+        assert!(default_marshaler(
+            &syn::parse_quote! { impl Iterator<Item = String> + '_ },
+            Direction::Return
+        )
+        .is_err());
+        assert_eq!(
+            resolve("std::num::NonZeroU32", Direction::Input).as_deref(),
+            Some("::cffi::NonZeroMarshaler::<u32>")
+        );
+        assert_eq!(
+            resolve("NonZero<i64>", Direction::Input).as_deref(),
+            Some("::cffi::NonZeroMarshaler::<i64>")
+        );
+        assert_eq!(
+            resolve("std::ptr::NonNull<Foo>", Direction::Input).as_deref(),
+            Some("::cffi::NonNullMarshaler::<Foo>")
+        );
+        assert_eq!(
+            resolve("extern \"C\" fn(u32)", Direction::Input).as_deref(),
+            Some("::cffi::FnPtrMarshaler::<extern\"C\"fn(u32)>")
+        );
+        assert_eq!(resolve("fn(u32)", Direction::Input), None);
+        assert_eq!(resolve("Foo", Direction::Input), None);
+    }
+
+    #[test]
+    fn registry_file() {
+        let entries = parse_registry(
+            r#"
+            [package]
+            name = "ignored"
+
+            [marshalers] # comment
+            Uuid = "crate::UuidMarshaler"
+            "Tree<T>" = 'crate::TreeMarshaler::<T>' # comment
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            entries,
+            [
+                ("Uuid".to_string(), "crate::UuidMarshaler".to_string()),
+                (
+                    "Tree<T>".to_string(),
+                    "crate::TreeMarshaler::<T>".to_string()
+                ),
+            ]
+        );
+
+        assert!(parse_registry("[marshalers]\nUuid = crate::UuidMarshaler").is_err());
+    }
+}
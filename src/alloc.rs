@@ -0,0 +1,274 @@
+use std::alloc::Layout;
+use std::ffi::c_void;
+use std::sync::OnceLock;
+
+use super::{AllocationError, Slice};
+
+pub type AllocFn = unsafe extern "C" fn(size: usize, align: usize) -> *mut c_void;
+pub type FreeFn = unsafe extern "C" fn(ptr: *mut c_void, size: usize, align: usize);
+
+/// The allocator used for every buffer that cffi hands to foreign code to own, such as the
+/// `Slice`s produced by `VecMarshaler`, `StringMarshaler`, `StrMarshaler` and `PathBufMarshaler`,
+/// and freed by the functions in [`crate::ffi`].
+///
+/// Opaque handles (`BoxMarshaler`, `ArcMarshaler`) are not covered, as they may only ever be
+/// released by Rust.
+///
+/// The allocator may only be chosen once, before cffi allocates anything. By default this is the
+/// Rust global allocator, or libc `malloc`/`free` when the `malloc` feature is enabled. With the
+/// Rust allocator, non-empty `Vec` buffers are handed over and taken back without copying.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Allocator {
+    pub alloc: AllocFn,
+    pub free: FreeFn,
+}
+
+impl Allocator {
+    /// Uses the Rust global allocator.
+    pub const RUST: Allocator = Allocator {
+        alloc: rust_alloc,
+        free: rust_free,
+    };
+
+    /// Uses libc `malloc` and `free`, so foreign code may release buffers with `free()`.
+    pub const LIBC: Allocator = Allocator {
+        alloc: libc_alloc,
+        free: libc_free,
+    };
+}
+
+impl Default for Allocator {
+    #[cfg(not(feature = "malloc"))]
+    fn default() -> Self {
+        Allocator::RUST
+    }
+
+    #[cfg(feature = "malloc")]
+    fn default() -> Self {
+        Allocator::LIBC
+    }
+}
+
+static ALLOCATOR: OnceLock<Allocator> = OnceLock::new();
+
+/// Returns the allocator in use, fixing it to the default if none was set.
+pub fn allocator() -> &'static Allocator {
+    ALLOCATOR.get_or_init(Allocator::default)
+}
+
+/// Sets the allocator for all cffi-managed foreign memory.
+///
+/// Fails, returning the given allocator, if one has already been set or used.
+pub fn set_allocator(allocator: Allocator) -> Result<(), Allocator> {
+    ALLOCATOR.set(allocator)
+}
+
+/// Sets the allocator for all cffi-managed foreign memory. Returns 1 on success, or 0 if either
+/// function is null or an allocator has already been set or used.
+#[no_mangle]
+pub extern "C" fn cffi_set_allocator(alloc: Option<AllocFn>, free: Option<FreeFn>) -> u8 {
+    match (alloc, free) {
+        (Some(alloc), Some(free)) => set_allocator(Allocator { alloc, free }).is_ok() as u8,
+        _ => 0,
+    }
+}
+
+/// Allocates `size` bytes aligned to `align` with the cffi allocator, for foreign code to hand
+/// to Rust as a buffer that Rust takes ownership of, such as the `String` or `Vec` returned by a
+/// `#[cffi::foreign_trait]` method. Returns null if the allocation fails.
+///
+/// A buffer returned as a `Slice<T>` of `len` items must be allocated with `size` of
+/// `len * sizeof(T)` and `align` of `alignof(T)`, as Rust frees it with that layout.
+#[no_mangle]
+pub extern "C" fn cffi_alloc(size: usize, align: usize) -> *mut c_void {
+    let layout = match Layout::from_size_align(size, align) {
+        Ok(v) => padded(v),
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    let data = unsafe { (allocator().alloc)(layout.size(), layout.align()) };
+    if !data.is_null() {
+        crate::track::produced(data);
+    }
+    data
+}
+
+unsafe extern "C" fn rust_alloc(size: usize, align: usize) -> *mut c_void {
+    match Layout::from_size_align(size, align) {
+        Ok(layout) if layout.size() > 0 => std::alloc::alloc(layout).cast(),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+unsafe extern "C" fn rust_free(ptr: *mut c_void, size: usize, align: usize) {
+    std::alloc::dealloc(ptr.cast(), Layout::from_size_align_unchecked(size, align));
+}
+
+// `malloc` is guaranteed to be suitably aligned for any fundamental type.
+const MALLOC_ALIGN: usize = std::mem::align_of::<libc::max_align_t>();
+
+unsafe extern "C" fn libc_alloc(size: usize, align: usize) -> *mut c_void {
+    if align <= MALLOC_ALIGN {
+        return libc::malloc(size);
+    }
+
+    #[cfg(unix)]
+    {
+        let mut ptr = std::ptr::null_mut();
+        if libc::posix_memalign(&mut ptr, align, size) == 0 {
+            return ptr;
+        }
+    }
+
+    std::ptr::null_mut()
+}
+
+unsafe extern "C" fn libc_free(ptr: *mut c_void, _size: usize, _align: usize) {
+    libc::free(ptr);
+}
+
+// Zero-sized requests are rounded up so that every buffer handed out is a real allocation
+// that the foreign side may free like any other.
+fn padded(layout: Layout) -> Layout {
+    Layout::from_size_align(layout.size().max(1), layout.align()).unwrap()
+}
+
+/// Whether a buffer of `len` `T`s from the cffi allocator has the allocation of a `Vec<T>` of
+/// that capacity, so that the two can be converted without copying. This holds for the Rust
+/// allocator, unless the buffer was padded from zero size.
+fn is_vec_allocation<T>(len: usize) -> bool {
+    let allocator = allocator();
+
+    len > 0
+        && std::mem::size_of::<T>() > 0
+        && std::ptr::fn_addr_eq(allocator.alloc, Allocator::RUST.alloc)
+        && std::ptr::fn_addr_eq(allocator.free, Allocator::RUST.free)
+}
+
+/// Moves the contents of `vec` into memory owned by the cffi allocator, handing over the `Vec`'s
+/// own buffer if it is already allocated the same way.
+pub(crate) fn into_foreign_slice<T>(mut vec: Vec<T>) -> Slice<T> {
+    let len = vec.len();
+
+    if is_vec_allocation::<T>(len) {
+        let data = Box::into_raw(vec.into_boxed_slice()).cast::<T>();
+        crate::track::produced(std::ptr::slice_from_raw_parts(data, len));
+        return Slice { data, len };
+    }
+
+    let layout = padded(Layout::array::<T>(len).expect("slice too large"));
+    let data = unsafe { (allocator().alloc)(layout.size(), layout.align()) }.cast::<T>();
+
+    if data.is_null() {
+        std::alloc::handle_alloc_error(layout);
+    }
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(vec.as_ptr(), data, len);
+        vec.set_len(0);
+    }
+
+    crate::track::produced(std::ptr::slice_from_raw_parts(data, len));
+    Slice { data, len }
+}
+
+/// Moves the contents of a slice allocated by the cffi allocator back into a `Vec`, taking over
+/// its buffer if it has a `Vec`'s allocation and freeing it otherwise.
+///
+/// Only fails if the slice is known to have been freed already, which requires the
+/// `track-allocations` feature.
+///
+/// # Safety
+///
+/// `slice` must have been created by [`into_foreign_slice`] and not already freed.
+pub(crate) unsafe fn from_foreign_slice<T>(slice: Slice<T>) -> Result<Vec<T>, AllocationError> {
+    crate::track::check(slice.data)?;
+
+    if is_vec_allocation::<T>(slice.len) {
+        crate::track::consumed(slice.data);
+        return Ok(Vec::from_raw_parts(slice.data, slice.len, slice.len));
+    }
+
+    let mut vec = Vec::with_capacity(slice.len);
+    std::ptr::copy_nonoverlapping(slice.data, vec.as_mut_ptr(), slice.len);
+    vec.set_len(slice.len);
+    free_foreign_slice(slice);
+    Ok(vec)
+}
+
+/// Frees memory allocated by the cffi allocator with the given (unpadded) layout.
+///
+/// # Safety
+///
+/// `data` must be null, or have been allocated by the cffi allocator with `layout`.
+pub(crate) unsafe fn free_foreign(data: *mut c_void, layout: Layout) {
+    if data.is_null() || crate::track::check(data).is_err() {
+        return;
+    }
+
+    let layout = padded(layout);
+    (allocator().free)(data, layout.size(), layout.align());
+    crate::track::consumed(data);
+}
+
+/// Frees a slice allocated by the cffi allocator without dropping its contents.
+///
+/// # Safety
+///
+/// `slice` must have been created by [`into_foreign_slice`] and not already freed.
+pub(crate) unsafe fn free_foreign_slice<T>(slice: Slice<T>) {
+    let layout = Layout::array::<T>(slice.len).expect("slice too large");
+    free_foreign(slice.data.cast(), layout);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "malloc"))]
+    #[test]
+    fn hands_over_vec_buffer() {
+        let mut vec = Vec::with_capacity(8);
+        vec.extend_from_slice(&[1u32, 2, 3]);
+        vec.shrink_to_fit();
+        let data = vec.as_ptr();
+
+        let slice = into_foreign_slice(vec);
+        assert_eq!(slice.data.cast_const(), data);
+        assert_eq!(slice.len, 3);
+
+        let vec = unsafe { from_foreign_slice(slice) }.unwrap();
+        assert_eq!(vec.as_ptr(), data);
+        assert_eq!(vec, [1, 2, 3]);
+    }
+
+    #[test]
+    fn empty_vec() {
+        let slice = into_foreign_slice(Vec::<u32>::new());
+        assert!(!slice.data.is_null());
+        assert_eq!(slice.len, 0);
+
+        let vec = unsafe { from_foreign_slice(slice) }.unwrap();
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn libc_alignment() {
+        unsafe {
+            let ptr = (Allocator::LIBC.alloc)(24, 8);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 8, 0);
+            (Allocator::LIBC.free)(ptr, 24, 8);
+
+            let ptr = (Allocator::LIBC.alloc)(24, 4096);
+            if cfg!(unix) {
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % 4096, 0);
+                (Allocator::LIBC.free)(ptr, 24, 4096);
+            } else {
+                assert!(ptr.is_null());
+            }
+        }
+    }
+}
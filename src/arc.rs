@@ -6,19 +6,57 @@ use std::sync::Arc;
 
 use crate::TraitObject;
 
-use super::null_ptr_error;
-use super::{FromForeign, InputType, ReturnType, ToForeign};
+use super::{DropForeign, FromForeign, InputType, ReturnType, ToForeign, ToForeignTraitObject};
 
 pub struct ArcMarshaler<T: ?Sized>(PhantomData<T>);
 
+impl<T> ArcMarshaler<T> {
+    /// Increments the strong count of a pointer produced by `to_foreign`, returning the pointer.
+    /// Null pointers are returned unchanged.
+    ///
+    /// # Safety
+    ///
+    /// `foreign` must be null or have been produced by `to_foreign` and not yet released.
+    pub unsafe fn retain(foreign: *const T) -> *const T {
+        if foreign.is_null() {
+            return foreign;
+        }
+
+        if let Err(e) = crate::tag::arc_retain(foreign) {
+            log::error!("ArcMarshaler::retain: {}", e);
+        }
+
+        foreign
+    }
+
+    /// Decrements the strong count of a pointer produced by `to_foreign` or [`retain`], dropping
+    /// the value once the last reference is released. Null pointers are ignored.
+    ///
+    /// # Safety
+    ///
+    /// `foreign` must be null or have been produced by `to_foreign` or [`retain`], and each
+    /// pointer must only be released once.
+    ///
+    /// [`retain`]: ArcMarshaler::retain
+    pub unsafe fn release(foreign: *const T) {
+        if foreign.is_null() {
+            return;
+        }
+
+        if let Err(e) = crate::tag::arc_from_raw(foreign) {
+            log::error!("ArcMarshaler::release: {}", e);
+        }
+    }
+}
+
 impl<T: ?Sized> InputType for ArcMarshaler<T> {
     type Foreign = *const T;
-    type ForeignTraitObject = ();
+    type ForeignTraitObject = *const TraitObject<T>;
 }
 
 impl<T: ?Sized> ReturnType for ArcMarshaler<T> {
     type Foreign = *const T;
-    type ForeignTraitObject = TraitObject<T>;
+    type ForeignTraitObject = *const TraitObject<T>;
 
     fn foreign_default() -> Self::Foreign {
         // This is not UB so long as it is only called when T is not a trait object. This is currently guaranteed by the generator.
@@ -28,15 +66,11 @@ impl<T: ?Sized> ReturnType for ArcMarshaler<T> {
     }
 
     fn foreign_default_trait_object() -> Self::ForeignTraitObject {
-        TraitObject {
-            data: std::ptr::null_mut(),
-            vtable: std::ptr::null_mut(),
-            ty: PhantomData,
-        }
+        std::ptr::null()
     }
 }
 
-impl<T: ?Sized> ToForeign<Arc<T>, *const T> for ArcMarshaler<T> {
+impl<T> ToForeign<Arc<T>, *const T> for ArcMarshaler<T> {
     type Error = Infallible;
 
     #[inline(always)]
@@ -53,11 +87,29 @@ impl<T: ?Sized> ToForeign<Arc<T>, *const T> for ArcMarshaler<T> {
         // // std::mem::forget(pinned_arc);
 
         // Ok(pinned_ref as *const _)
-        Ok(Arc::into_raw(local))
+        Ok(crate::tag::arc_into_raw(local))
+    }
+}
+
+impl<T> ToForeign<T, *const T> for ArcMarshaler<T> {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign(local: T) -> Result<*const T, Self::Error> {
+        ArcMarshaler::to_foreign(Arc::new(local))
+    }
+}
+
+impl<T> ToForeign<Result<T, Box<dyn Error>>, *const T> for ArcMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(local: Result<T, Box<dyn Error>>) -> Result<*const T, Self::Error> {
+        local.map(|x| ArcMarshaler::to_foreign(Arc::new(x)).unwrap())
     }
 }
 
-impl<T: ?Sized> FromForeign<*const T, Arc<T>> for ArcMarshaler<T> {
+impl<T> FromForeign<*const T, Arc<T>> for ArcMarshaler<T> {
     type Error = Box<dyn Error>;
 
     #[inline(always)]
@@ -68,19 +120,102 @@ impl<T: ?Sized> FromForeign<*const T, Arc<T>> for ArcMarshaler<T> {
             ty = std::any::type_name::<T>()
         );
 
-        if foreign.is_null() {
-            return Err(null_ptr_error());
-        }
-
-        Ok(Arc::from_raw(foreign as *const _))
+        crate::tag::arc_from_raw(foreign)
     }
 }
 
-impl<T: ?Sized> ToForeign<Result<Arc<T>, Box<dyn Error>>, *const T> for ArcMarshaler<T> {
+impl<T> ToForeign<Result<Arc<T>, Box<dyn Error>>, *const T> for ArcMarshaler<T> {
     type Error = Box<dyn Error>;
 
     #[inline(always)]
     fn to_foreign(local: Result<Arc<T>, Box<dyn Error>>) -> Result<*const T, Self::Error> {
-        local.and_then(|x| Ok(Arc::into_raw(x) as *const _))
+        local.map(crate::tag::arc_into_raw)
+    }
+}
+
+impl<T> DropForeign<*const T> for ArcMarshaler<T> {
+    #[inline(always)]
+    unsafe fn drop_foreign(foreign: *const T) {
+        ArcMarshaler::release(foreign)
+    }
+}
+
+impl<T: ?Sized> ToForeignTraitObject<Arc<T>, T> for ArcMarshaler<T> {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign_trait_object(local: Arc<T>) -> Result<*const TraitObject<T>, Self::Error> {
+        log::debug!(
+            "<ArcMarshaler<{ty}> as ToForeignTraitObject<Arc<{ty}>, {ty}>>::to_foreign_trait_object",
+            ty = std::any::type_name::<T>()
+        );
+
+        Ok(crate::tag::box_into_raw(Box::new(TraitObject(local))))
+    }
+}
+
+impl<T: ?Sized> ToForeignTraitObject<Result<Arc<T>, Box<dyn Error>>, T> for ArcMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign_trait_object(
+        local: Result<Arc<T>, Box<dyn Error>>,
+    ) -> Result<*const TraitObject<T>, Self::Error> {
+        local.map(|x| crate::tag::box_into_raw(Box::new(TraitObject(x))) as *const _)
+    }
+}
+
+impl<T: ?Sized> FromForeign<*const TraitObject<T>, Arc<T>> for ArcMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(foreign: *const TraitObject<T>) -> Result<Arc<T>, Self::Error> {
+        Ok(crate::tag::box_from_raw(foreign)?.0)
+    }
+}
+
+impl<T: ?Sized> DropForeign<*const TraitObject<T>> for ArcMarshaler<T> {
+    #[inline(always)]
+    unsafe fn drop_foreign(foreign: *const TraitObject<T>) {
+        TraitObject::free(foreign)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArcRefMarshaler;
+    use std::fmt::Display;
+
+    #[test]
+    fn retain_release() {
+        let local = Arc::new(7u32);
+        let ptr = ArcMarshaler::<u32>::to_foreign(local.clone()).unwrap();
+        assert_eq!(unsafe { ArcMarshaler::retain(ptr) }, ptr);
+
+        unsafe { ArcMarshaler::release(ptr) };
+        let borrowed: &u32 = unsafe { ArcRefMarshaler::from_foreign(ptr) }.unwrap();
+        assert_eq!(*borrowed, 7);
+
+        let cloned: Arc<u32> = unsafe { ArcRefMarshaler::from_foreign(ptr) }.unwrap();
+        assert!(Arc::ptr_eq(&cloned, &local));
+        drop(cloned);
+
+        unsafe { ArcMarshaler::release(ptr) };
+        assert_eq!(Arc::strong_count(&local), 1);
+    }
+
+    #[test]
+    fn trait_object_handle() {
+        let local: Arc<dyn Display> = Arc::new(42);
+        let handle = ArcMarshaler::<dyn Display>::to_foreign_trait_object(local.clone()).unwrap();
+        assert_eq!(Arc::strong_count(&local), 2);
+
+        let borrowed: &dyn Display =
+            unsafe { ArcRefMarshaler::<dyn Display>::from_foreign(handle) }.unwrap();
+        assert_eq!(borrowed.to_string(), "42");
+
+        unsafe { TraitObject::free(handle) };
+        assert_eq!(Arc::strong_count(&local), 1);
     }
 }
@@ -4,30 +4,48 @@ use std::sync::Arc;
 
 use crate::TraitObject;
 
-use super::null_ptr_error;
 use super::{FromForeign, InputType};
 
 pub struct ArcRefMarshaler<T: ?Sized>(PhantomData<T>);
 
 impl<T: ?Sized> InputType for ArcRefMarshaler<T> {
     type Foreign = *const T;
-    type ForeignTraitObject = TraitObject<T>;
+    type ForeignTraitObject = *const TraitObject<T>;
 }
 
-impl<'a, T: ?Sized> FromForeign<*const T, Arc<T>> for ArcRefMarshaler<T> {
+impl<T> FromForeign<*const T, Arc<T>> for ArcRefMarshaler<T> {
     type Error = Box<dyn Error>;
 
     #[inline(always)]
     unsafe fn from_foreign(foreign: *const T) -> Result<Arc<T>, Self::Error> {
-        if foreign.is_null() {
-            return Err(null_ptr_error());
-        }
+        crate::tag::arc_clone(foreign)
+    }
+}
+
+impl<'a, T> FromForeign<*const T, &'a T> for ArcRefMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(foreign: *const T) -> Result<&'a T, Self::Error> {
+        crate::tag::arc_ref(foreign)
+    }
+}
 
-        let arc = Arc::from_raw(foreign);
-        let cloned = Arc::clone(&arc);
-        let _x = Arc::into_raw(arc);
+impl<T: ?Sized> FromForeign<*const TraitObject<T>, Arc<T>> for ArcRefMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(foreign: *const TraitObject<T>) -> Result<Arc<T>, Self::Error> {
+        Ok(Arc::clone(&crate::tag::box_ref(foreign)?.0))
+    }
+}
+
+impl<'a, T: ?Sized> FromForeign<*const TraitObject<T>, &'a T> for ArcRefMarshaler<T> {
+    type Error = Box<dyn Error>;
 
-        Ok(cloned)
+    #[inline(always)]
+    unsafe fn from_foreign(foreign: *const TraitObject<T>) -> Result<&'a T, Self::Error> {
+        Ok(&*crate::tag::box_ref(foreign)?.0)
     }
 }
 
@@ -0,0 +1,140 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::io;
+use std::marker::PhantomData;
+
+use super::null_ptr_error;
+use super::{DropForeign, FromForeign, InputType, ReturnType, Slice, ToForeign};
+
+/// The foreign representation of an array, as produced by [`ArrayMarshaler`]. C cannot pass
+/// arrays by value, so the array is wrapped in a struct.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Array<T, const N: usize> {
+    pub data: [T; N],
+}
+
+/// Marshals a fixed-size array by value, inside an [`Array`] struct:
+///
+///   - `ArrayMarshaler<u8, 32>`: `[u8; 32]` ↔ `Array<u8, 32>`
+///
+/// Arrays of passthrough types are marshaled with this by default. See [`ArrayRefMarshaler`] for
+/// borrowing an array from a pointer instead.
+pub struct ArrayMarshaler<T, const N: usize>(PhantomData<T>);
+
+impl<T: Copy, const N: usize> InputType for ArrayMarshaler<T, N> {
+    type Foreign = Array<T, N>;
+    type ForeignTraitObject = ();
+}
+
+impl<T: Copy + Default, const N: usize> ReturnType for ArrayMarshaler<T, N> {
+    type Foreign = Array<T, N>;
+    type ForeignTraitObject = ();
+
+    #[inline(always)]
+    fn foreign_default() -> Self::Foreign {
+        Array {
+            data: [T::default(); N],
+        }
+    }
+}
+
+impl<T: Copy, const N: usize> ToForeign<[T; N], Array<T, N>> for ArrayMarshaler<T, N> {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign(data: [T; N]) -> Result<Array<T, N>, Self::Error> {
+        Ok(Array { data })
+    }
+}
+
+impl<T: Copy, const N: usize> ToForeign<Result<[T; N], Box<dyn Error>>, Array<T, N>>
+    for ArrayMarshaler<T, N>
+{
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(result: Result<[T; N], Box<dyn Error>>) -> Result<Array<T, N>, Self::Error> {
+        result.map(|data| Array { data })
+    }
+}
+
+impl<T: Copy, const N: usize> FromForeign<Array<T, N>, [T; N]> for ArrayMarshaler<T, N> {
+    type Error = Infallible;
+
+    #[inline(always)]
+    unsafe fn from_foreign(array: Array<T, N>) -> Result<[T; N], Self::Error> {
+        Ok(array.data)
+    }
+}
+
+impl<T: Copy, const N: usize> DropForeign<Array<T, N>> for ArrayMarshaler<T, N> {
+    #[inline(always)]
+    unsafe fn drop_foreign(_: Array<T, N>) {}
+}
+
+/// Borrows a fixed-size array from a pointer and length, checking that the length is `N`:
+///
+///   - `ArrayRefMarshaler<f32, 4>`: `Slice<f32>` → `&[f32; 4]`
+///
+/// References to arrays of passthrough types are marshaled with this by default, and
+/// `#[marshal(split)]` passes the pointer and length as separate parameters.
+pub struct ArrayRefMarshaler<T, const N: usize>(PhantomData<T>);
+
+impl<T, const N: usize> InputType for ArrayRefMarshaler<T, N> {
+    type Foreign = Slice<T>;
+    type ForeignTraitObject = ();
+}
+
+impl<'a, T, const N: usize> FromForeign<Slice<T>, &'a [T; N]> for ArrayRefMarshaler<T, N> {
+    type Error = Box<dyn Error>;
+
+    unsafe fn from_foreign(slice: Slice<T>) -> Result<&'a [T; N], Self::Error> {
+        if slice.data.is_null() {
+            return Err(null_ptr_error());
+        }
+
+        if slice.len != N {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expected {} elements, got {}", N, slice.len),
+            )));
+        }
+
+        Ok(&*(slice.data as *const [T; N]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn by_value() {
+        let foreign = ArrayMarshaler::<u8, 4>::to_foreign([1, 2, 3, 4]).unwrap();
+        assert_eq!(foreign.data, [1, 2, 3, 4]);
+
+        let local: [u8; 4] = unsafe { ArrayMarshaler::<u8, 4>::from_foreign(foreign) }.unwrap();
+        assert_eq!(local, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn by_reference() {
+        let data = [1.0f32, 2.0, 3.0, 4.0];
+        let slice = Slice {
+            data: data.as_ptr() as *mut f32,
+            len: data.len(),
+        };
+
+        let local: &[f32; 4] = unsafe { ArrayRefMarshaler::<f32, 4>::from_foreign(slice) }.unwrap();
+        assert_eq!(local, &data);
+
+        let short = Slice {
+            data: data.as_ptr() as *mut f32,
+            len: 3,
+        };
+        let result: Result<&[f32; 4], _> =
+            unsafe { ArrayRefMarshaler::<f32, 4>::from_foreign(short) };
+        assert!(result.is_err());
+    }
+}
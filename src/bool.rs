@@ -1,4 +1,4 @@
-use super::{FromForeign, InputType, ReturnType, ToForeign};
+use super::{DropForeign, FromForeign, InputType, ReturnType, ToForeign};
 use std::convert::Infallible;
 
 pub struct BoolMarshaler;
@@ -34,3 +34,8 @@ impl ToForeign<bool, u8> for BoolMarshaler {
         Ok(if b { 1 } else { 0 })
     }
 }
+
+impl DropForeign<u8> for BoolMarshaler {
+    #[inline(always)]
+    unsafe fn drop_foreign(_: u8) {}
+}
@@ -1,9 +1,17 @@
 use std::error::Error;
 use std::marker::PhantomData;
 
-use super::null_ptr_error;
 use super::{FromForeign, InputType};
 
+/// Borrows a value owned by a pointer from [`BoxMarshaler`](crate::BoxMarshaler) without taking
+/// ownership of it.
+///
+/// ### From the foreign interface:
+///
+///   - `*mut T` → `&T` (ref)
+///   - `*mut T` → `&mut T` (mut ref)
+///
+/// The foreign caller must not use the pointer from elsewhere while a mutable borrow is live.
 pub struct BoxRefMarshaler<T>(PhantomData<T>);
 
 impl<T> InputType for BoxRefMarshaler<T> {
@@ -11,45 +19,59 @@ impl<T> InputType for BoxRefMarshaler<T> {
     type ForeignTraitObject = ();
 }
 
-// impl<'a, T> FromForeign<*mut Box<T>, &'a Box<T>> for BoxRefMarshaler<T> {
-//     type Error = Box<dyn Error>;
-
-//     #[inline(always)]
-//     unsafe fn from_foreign(foreign: *mut Box<T>) -> Result<&'a Box<T>, Self::Error> {
-//         log::debug!(
-//             "<BoxMarshaler<{ty}> as FromForeign<*mut Box<T>, &'a Box<T>>>::from_foreign({:?})",
-//             foreign,
-//             ty = std::any::type_name::<T>()
-//         );
+impl<'a, T> FromForeign<*mut T, &'a T> for BoxRefMarshaler<T> {
+    type Error = Box<dyn Error>;
 
-//         if foreign.is_null() {
-//             return Err(null_ptr_error());
-//         }
+    #[inline(always)]
+    unsafe fn from_foreign(foreign: *mut T) -> Result<&'a T, Self::Error> {
+        log::debug!(
+            "<BoxRefMarshaler<{ty}> as FromForeign<*mut T, &'a T>>::from_foreign({:?})",
+            foreign,
+            ty = std::any::type_name::<T>()
+        );
 
-//         Ok(unsafe { &*foreign as &'a Box<T> })
-//     }
-// }
+        crate::tag::box_ref(foreign)
+    }
+}
 
-impl<'a, T> FromForeign<*mut T, &'a T> for BoxRefMarshaler<T> {
+impl<'a, T> FromForeign<*mut T, &'a mut T> for BoxRefMarshaler<T> {
     type Error = Box<dyn Error>;
 
     #[inline(always)]
-    unsafe fn from_foreign(foreign: *mut T) -> Result<&'a T, Self::Error> {
+    unsafe fn from_foreign(foreign: *mut T) -> Result<&'a mut T, Self::Error> {
         log::debug!(
-            "<BoxMarshaler<{ty}> as FromForeign<*mut Box<T>, &'a mut Box<T>>>::from_foreign({:?})",
+            "<BoxRefMarshaler<{ty}> as FromForeign<*mut T, &'a mut T>>::from_foreign({:?})",
             foreign,
             ty = std::any::type_name::<T>()
         );
 
-        if foreign.is_null() {
-            return Err(null_ptr_error());
-        }
+        crate::tag::box_mut(foreign)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BoxMarshaler, ToForeign};
+
+    #[test]
+    fn mutable_borrow() {
+        let ptr = BoxMarshaler::<Vec<u32>>::to_foreign(vec![1, 2]).unwrap() as *mut Vec<u32>;
+
+        let value: &mut Vec<u32> = unsafe { BoxRefMarshaler::from_foreign(ptr) }.unwrap();
+        value.push(3);
 
-        // let mut boxed = unsafe { Box::from_raw(foreign as *mut _ as *mut _) };
-        // let ptr = &mut boxed as *mut _;
-        // std::mem::forget(boxed);
-        // // let ptr = unsafe { std::mem::transmute::<*mut T, *mut Box<T>>(foreign) };
+        let value: &Vec<u32> = unsafe { BoxRefMarshaler::from_foreign(ptr) }.unwrap();
+        assert_eq!(value, &[1, 2, 3]);
+
+        let value: Vec<u32> = unsafe { BoxMarshaler::from_foreign(ptr as *const _) }.unwrap();
+        assert_eq!(value, [1, 2, 3]);
+    }
 
-        Ok(&*foreign)
+    #[test]
+    fn null() {
+        let result: Result<&mut u32, _> =
+            unsafe { BoxRefMarshaler::from_foreign(std::ptr::null_mut()) };
+        assert!(result.is_err());
     }
 }
@@ -2,8 +2,7 @@ use std::convert::Infallible;
 use std::error::Error;
 use std::marker::PhantomData;
 
-use super::null_ptr_error;
-use super::{FromForeign, InputType, ReturnType, ToForeign};
+use super::{DropForeign, FromForeign, InputType, ReturnType, ToForeign};
 
 /// The `Box` marshaler is the catch-all just-throw-it-on-the-heap opaque pointer solution.
 ///
@@ -16,9 +15,11 @@ use super::{FromForeign, InputType, ReturnType, ToForeign};
 ///
 /// ### From the foreign interface:
 ///
-///   - `*const/mut T` → `Box<T>` (owned)
-///   - `*const T` → `&T` (ref)
-///   - `*mut T` → `&mut T` (mut ref)
+///   - `*const T` → `Box<T>` (owned)
+///   - `*const T` → `T` (owned, unboxed)
+///
+/// Borrowing `&T` and `&mut T` without taking ownership is handled by
+/// [`BoxRefMarshaler`](crate::BoxRefMarshaler).
 ///
 /// ## Freeing `T`
 ///
@@ -38,12 +39,12 @@ use super::{FromForeign, InputType, ReturnType, ToForeign};
 ///     let something = Something { data: vec![1, 3, 55] };
 ///
 ///     // BoxMarshaler::to_foreign is Infallible
-///     let ptr: *const Something = BoxMarshaler::to_foreign(something).unwrap();
+///     let ptr: *const Something = BoxMarshaler::to_foreign(Box::new(something)).unwrap();
 ///
 ///     /* send `ptr` over ffi, process it in some way, etc */
 ///
 ///     // This isn't infallible though, checks for null pointers.
-///     let boxed: Box<Something> = match BoxMarshaler::from_foreign(ptr) {
+///     let boxed: Box<Something> = match unsafe { BoxMarshaler::from_foreign(ptr) } {
 ///         Ok(v) => v,
 ///         Err(e) => panic!("!")
 ///     };
@@ -77,16 +78,25 @@ impl<T> ToForeign<Box<T>, *const T> for BoxMarshaler<T> {
             ty = std::any::type_name::<T>(),
             o = "*const T"
         );
-        Ok(Box::into_raw(local) as *const _ as *const _)
+        Ok(crate::tag::box_into_raw(local) as *const _)
+    }
+}
+
+impl<T> ToForeign<T, *const T> for BoxMarshaler<T> {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign(local: T) -> Result<*const T, Self::Error> {
+        BoxMarshaler::to_foreign(Box::new(local))
     }
 }
 
-impl<T: ?Sized> ToForeign<Result<Box<T>, Box<dyn Error>>, *const T> for BoxMarshaler<T> {
+impl<T> ToForeign<Result<Box<T>, Box<dyn Error>>, *const T> for BoxMarshaler<T> {
     type Error = Box<dyn Error>;
 
     #[inline(always)]
     fn to_foreign(local: Result<Box<T>, Box<dyn Error>>) -> Result<*const T, Self::Error> {
-        local.and_then(|x| Ok(Box::into_raw(x) as *const _ as *const _))
+        local.map(|x| crate::tag::box_into_raw(x) as *const _)
     }
 }
 
@@ -110,11 +120,30 @@ impl<T> FromForeign<*const T, Box<T>> for BoxMarshaler<T> {
             ty = std::any::type_name::<T>()
         );
 
+        crate::tag::box_from_raw(foreign)
+    }
+}
+
+impl<T> FromForeign<*const T, T> for BoxMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(foreign: *const T) -> Result<T, Self::Error> {
+        let boxed: Box<T> = BoxMarshaler::from_foreign(foreign)?;
+        Ok(*boxed)
+    }
+}
+
+impl<T> DropForeign<*const T> for BoxMarshaler<T> {
+    #[inline(always)]
+    unsafe fn drop_foreign(foreign: *const T) {
         if foreign.is_null() {
-            return Err(null_ptr_error());
+            return;
         }
 
-        Ok(Box::from_raw(foreign as *mut _))
+        if let Err(e) = crate::tag::box_from_raw(foreign) {
+            log::error!("BoxMarshaler::drop_foreign: {}", e);
+        }
     }
 }
 
@@ -161,16 +190,3 @@ impl<T> FromForeign<*const T, Box<T>> for BoxMarshaler<T> {
 //         Ok(Box::into_raw(local))
 //     }
 // }
-
-// impl<'a, T: ?Sized> FromForeign<*mut T, &'a mut T> for BoxMarshaler<T> {
-//     type Error = Box<dyn Error>;
-
-//     #[inline(always)]
-//     unsafe fn from_foreign(foreign: *mut T) -> Result<&'a mut T, Self::Error> {
-//         if foreign.is_null() {
-//             return Err(null_ptr_error());
-//         }
-
-//         Ok(unsafe { &mut *foreign })
-//     }
-// }
@@ -0,0 +1,131 @@
+use std::error::Error;
+use std::fmt;
+
+use super::null_ptr_error;
+
+/// A buffer owned by the foreign caller, into which a marshaler writes its output.
+///
+/// On the C side this is represented by three parameters: `T* buf, size_t cap, size_t* len_out`.
+/// The required length (in elements) is always written to `len_out`, so a caller may pass a null
+/// buffer with a capacity of zero to query the size before allocating.
+pub struct OutBuffer<T> {
+    data: *mut T,
+    cap: usize,
+    len: *mut usize,
+}
+
+impl<T> fmt::Debug for OutBuffer<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_struct(&format!("OutBuffer<{}>", std::any::type_name::<T>()))
+            .field("data", &self.data.cast::<std::ffi::c_void>())
+            .field("cap", &self.cap)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<T: Copy> OutBuffer<T> {
+    /// # Safety
+    ///
+    /// `data` must be null or valid for writes of `cap` elements, and `len` must be null or
+    /// valid for a single write.
+    #[inline(always)]
+    pub unsafe fn new(data: *mut T, cap: usize, len: *mut usize) -> OutBuffer<T> {
+        OutBuffer { data, cap, len }
+    }
+
+    /// Copies `items` into the buffer, failing with [`BufferTooSmall`] if it does not fit.
+    pub fn write(&mut self, items: &[T]) -> Result<(), Box<dyn Error>> {
+        log::debug!("{:?} write len: {}", self, items.len());
+
+        if !self.len.is_null() {
+            unsafe { *self.len = items.len() };
+        }
+
+        if items.len() > self.cap {
+            return Err(Box::new(BufferTooSmall {
+                required: items.len(),
+                capacity: self.cap,
+            }));
+        }
+
+        if items.is_empty() {
+            return Ok(());
+        }
+
+        if self.data.is_null() {
+            return Err(null_ptr_error());
+        }
+
+        unsafe { std::ptr::copy_nonoverlapping(items.as_ptr(), self.data, items.len()) };
+        Ok(())
+    }
+}
+
+/// Returned when a caller-provided [`OutBuffer`] cannot hold the marshaled output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferTooSmall {
+    pub required: usize,
+    pub capacity: usize,
+}
+
+impl fmt::Display for BufferTooSmall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "buffer too small: {} elements required, capacity is {}",
+            self.required, self.capacity
+        )
+    }
+}
+
+impl Error for BufferTooSmall {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_fit() {
+        let mut data = [0u8; 5];
+        let mut len = 0;
+        let mut buffer = unsafe { OutBuffer::new(data.as_mut_ptr(), data.len(), &mut len) };
+
+        buffer.write(b"hello").unwrap();
+        assert_eq!(&data, b"hello");
+        assert_eq!(len, 5);
+    }
+
+    #[test]
+    fn too_small() {
+        let mut data = [0u8; 4];
+        let mut len = 0;
+        let mut buffer = unsafe { OutBuffer::new(data.as_mut_ptr(), data.len(), &mut len) };
+
+        let err = buffer.write(b"hello").unwrap_err();
+        let err = err.downcast_ref::<BufferTooSmall>().unwrap();
+        assert_eq!(
+            *err,
+            BufferTooSmall {
+                required: 5,
+                capacity: 4
+            }
+        );
+        assert_eq!(len, 5);
+        assert_eq!(data, [0; 4]);
+    }
+
+    #[test]
+    fn length_query() {
+        let mut len = 0;
+        let mut buffer = unsafe { OutBuffer::<u8>::new(std::ptr::null_mut(), 0, &mut len) };
+
+        assert!(buffer.write(b"hello").is_err());
+        assert_eq!(len, 5);
+
+        let mut buffer = unsafe { OutBuffer::<u8>::new(std::ptr::null_mut(), 0, &mut len) };
+        buffer.write(b"").unwrap();
+        assert_eq!(len, 0);
+    }
+}
@@ -0,0 +1,79 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::io;
+
+use super::{DropForeign, FromForeign, InputType, ReturnType, ToForeign};
+
+/// Marshals a `char` as its Unicode scalar value:
+///
+///   - `char` ↔ `u32`
+///
+/// Values from C that are not valid scalar values, such as surrogates, are rejected with an error
+/// rather than turned into an invalid `char`.
+pub struct CharMarshaler;
+
+impl InputType for CharMarshaler {
+    type Foreign = u32;
+    type ForeignTraitObject = ();
+}
+
+impl ReturnType for CharMarshaler {
+    type Foreign = u32;
+    type ForeignTraitObject = ();
+
+    fn foreign_default() -> u32 {
+        0
+    }
+}
+
+impl FromForeign<u32, char> for CharMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(c: u32) -> Result<char, Self::Error> {
+        char::from_u32(c).ok_or_else(|| {
+            Box::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid char: {:#x}", c),
+            )) as _
+        })
+    }
+}
+
+impl ToForeign<char, u32> for CharMarshaler {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign(c: char) -> Result<u32, Self::Error> {
+        Ok(c as u32)
+    }
+}
+
+impl ToForeign<Result<char, Box<dyn Error>>, u32> for CharMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(result: Result<char, Box<dyn Error>>) -> Result<u32, Self::Error> {
+        result.map(|c| c as u32)
+    }
+}
+
+impl DropForeign<u32> for CharMarshaler {
+    #[inline(always)]
+    unsafe fn drop_foreign(_: u32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scalar_values() {
+        assert_eq!(
+            unsafe { CharMarshaler::from_foreign(0x1f600) }.unwrap(),
+            '😀'
+        );
+        assert!(unsafe { CharMarshaler::from_foreign(0xd800) }.is_err());
+        assert!(unsafe { CharMarshaler::from_foreign(0x110000) }.is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use super::{FromForeign, InputType, ReturnType, ToForeign};
+use super::{DropForeign, FromForeign, InputType, ReturnType, ToForeign};
 use std::convert::Infallible;
 
 pub struct CopyMarshaler<T: Copy>(std::marker::PhantomData<T>);
@@ -34,3 +34,8 @@ impl<T: Copy> ToForeign<T, T> for CopyMarshaler<T> {
         Ok(x)
     }
 }
+
+impl<T: Copy> DropForeign<T> for CopyMarshaler<T> {
+    #[inline(always)]
+    unsafe fn drop_foreign(_: T) {}
+}
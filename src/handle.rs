@@ -0,0 +1,388 @@
+use std::any::Any;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::{DropForeign, ErrCallback, FromForeign, InputType, ReturnType, ToForeign};
+
+struct Entry {
+    value: Arc<dyn Any + Send + Sync>,
+    type_name: &'static str,
+}
+
+struct Slot {
+    generation: u32,
+    entry: Option<Entry>,
+}
+
+/// A slot map of every live handle. A handle packs the slot's generation into its upper 32 bits
+/// and its index plus one into the lower 32 bits, so 0 is never a valid handle and a handle is
+/// invalidated as soon as its slot is freed.
+struct Registry {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    slots: Vec::new(),
+    free: Vec::new(),
+});
+
+fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Registry {
+    fn insert(&mut self, entry: Entry) -> u64 {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 1,
+                    entry: None,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+
+        let slot = &mut self.slots[index as usize];
+        slot.entry = Some(entry);
+        ((slot.generation as u64) << 32) | (index as u64 + 1)
+    }
+
+    fn slot(&mut self, handle: u64) -> Result<&mut Slot, HandleError> {
+        if handle == 0 {
+            return Err(HandleError::Null);
+        }
+
+        let index = (handle & 0xffff_ffff) as usize;
+        let generation = (handle >> 32) as u32;
+
+        match index.checked_sub(1).and_then(|i| self.slots.get_mut(i)) {
+            Some(slot) if slot.generation == generation && slot.entry.is_some() => Ok(slot),
+            _ => Err(HandleError::Stale(handle)),
+        }
+    }
+
+    fn get(&mut self, handle: u64) -> Result<&Entry, HandleError> {
+        Ok(self.slot(handle)?.entry.as_ref().unwrap())
+    }
+
+    fn remove(&mut self, handle: u64) -> Result<Entry, HandleError> {
+        let slot = self.slot(handle)?;
+        let entry = slot.entry.take().unwrap();
+        slot.generation = slot.generation.checked_add(1).unwrap_or(1);
+        self.free.push((handle & 0xffff_ffff) as u32 - 1);
+        Ok(entry)
+    }
+}
+
+fn downcast<T: Send + Sync + 'static>(handle: u64, entry: &Entry) -> Result<Arc<T>, HandleError> {
+    Arc::clone(&entry.value)
+        .downcast::<T>()
+        .map_err(|_| HandleError::WrongType {
+            handle,
+            expected: std::any::type_name::<T>(),
+            found: entry.type_name,
+        })
+}
+
+/// Returned when a handle given to a [`HandleMarshaler`] cannot be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandleError {
+    /// The handle was 0.
+    Null,
+    /// The handle was never issued, or has already been freed.
+    Stale(u64),
+    /// The handle refers to a value of another type.
+    WrongType {
+        handle: u64,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The value cannot be taken by value while shared references to it are alive.
+    Shared(u64),
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandleError::Null => write!(f, "null handle"),
+            HandleError::Stale(handle) => write!(f, "stale or invalid handle {:#x}", handle),
+            HandleError::WrongType {
+                handle,
+                expected,
+                found,
+            } => write!(
+                f,
+                "handle {:#x} refers to a {}, not a {}",
+                handle, found, expected
+            ),
+            HandleError::Shared(handle) => {
+                write!(
+                    f,
+                    "handle {:#x} is still shared and cannot be taken",
+                    handle
+                )
+            }
+        }
+    }
+}
+
+impl Error for HandleError {}
+
+/// A handle that was still registered when checked with [`live_handles`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveHandle {
+    pub handle: u64,
+    pub type_name: &'static str,
+}
+
+/// Lists every handle that has not yet been freed.
+pub fn live_handles() -> Vec<LiveHandle> {
+    let registry = registry();
+    registry
+        .slots
+        .iter()
+        .enumerate()
+        .filter_map(|(index, slot)| {
+            slot.entry.as_ref().map(|entry| LiveHandle {
+                handle: ((slot.generation as u64) << 32) | (index as u64 + 1),
+                type_name: entry.type_name,
+            })
+        })
+        .collect()
+}
+
+/// An opt-in alternative to [`BoxMarshaler`](crate::BoxMarshaler) and
+/// [`ArcMarshaler`](crate::ArcMarshaler) that hands out `u64` handles into a global registry
+/// instead of raw pointers.
+///
+/// Every handle is validated when converted back, so a double free, use after free or a handle
+/// of the wrong type results in a [`HandleError`] rather than undefined behaviour.
+///
+/// ### To the foreign interface:
+///
+///   - `T` → `u64`
+///   - `Arc<T>` → `u64`
+///
+/// ### From the foreign interface:
+///
+///   - `u64` → `T` (owned, freeing the handle)
+///   - `u64` → `Arc<T>` (shared, the handle stays valid)
+///
+/// ## Freeing
+///
+/// Handles are freed with `cffi_handle_free`. Any handles still live at shutdown can be logged
+/// with `cffi_handle_report_leaks`, or inspected with [`live_handles`].
+pub struct HandleMarshaler<T>(PhantomData<T>);
+
+impl<T: Send + Sync + 'static> HandleMarshaler<T> {
+    fn insert(value: Arc<T>) -> u64 {
+        let handle = registry().insert(Entry {
+            value,
+            type_name: std::any::type_name::<T>(),
+        });
+
+        log::debug!(
+            "HandleMarshaler<{ty}>::insert -> {:#x}",
+            handle,
+            ty = std::any::type_name::<T>()
+        );
+
+        handle
+    }
+}
+
+impl<T> InputType for HandleMarshaler<T> {
+    type Foreign = u64;
+    type ForeignTraitObject = ();
+}
+
+impl<T> ReturnType for HandleMarshaler<T> {
+    type Foreign = u64;
+    type ForeignTraitObject = ();
+
+    fn foreign_default() -> Self::Foreign {
+        0
+    }
+}
+
+impl<T: Send + Sync + 'static> ToForeign<T, u64> for HandleMarshaler<T> {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign(local: T) -> Result<u64, Self::Error> {
+        Ok(HandleMarshaler::insert(Arc::new(local)))
+    }
+}
+
+impl<T: Send + Sync + 'static> ToForeign<Arc<T>, u64> for HandleMarshaler<T> {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign(local: Arc<T>) -> Result<u64, Self::Error> {
+        Ok(HandleMarshaler::insert(local))
+    }
+}
+
+impl<T: Send + Sync + 'static> ToForeign<Result<T, Box<dyn Error>>, u64> for HandleMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(local: Result<T, Box<dyn Error>>) -> Result<u64, Self::Error> {
+        local.map(|x| HandleMarshaler::insert(Arc::new(x)))
+    }
+}
+
+impl<T: Send + Sync + 'static> FromForeign<u64, T> for HandleMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(handle: u64) -> Result<T, Self::Error> {
+        log::debug!(
+            "<HandleMarshaler<{ty}> as FromForeign<u64, {ty}>>::from_foreign({:#x})",
+            handle,
+            ty = std::any::type_name::<T>()
+        );
+
+        let mut registry = registry();
+        let arc = downcast::<T>(handle, registry.get(handle)?)?;
+
+        // The registry's own reference and ours: anything more is shared elsewhere.
+        if Arc::strong_count(&arc) > 2 {
+            return Err(Box::new(HandleError::Shared(handle)));
+        }
+
+        drop(registry.remove(handle)?);
+        match Arc::try_unwrap(arc) {
+            Ok(v) => Ok(v),
+            Err(_) => unreachable!("handle value was shared after removal"),
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> FromForeign<u64, Arc<T>> for HandleMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(handle: u64) -> Result<Arc<T>, Self::Error> {
+        log::debug!(
+            "<HandleMarshaler<{ty}> as FromForeign<u64, Arc<{ty}>>>::from_foreign({:#x})",
+            handle,
+            ty = std::any::type_name::<T>()
+        );
+
+        let mut registry = registry();
+        Ok(downcast::<T>(handle, registry.get(handle)?)?)
+    }
+}
+
+impl<T> DropForeign<u64> for HandleMarshaler<T> {
+    #[inline(always)]
+    unsafe fn drop_foreign(handle: u64) {
+        let entry = registry().remove(handle);
+        drop(entry);
+    }
+}
+
+/// Frees a handle issued by a [`HandleMarshaler`] of any type. Returns 1 on success, or 0 if the
+/// handle is null, stale or already freed.
+#[no_mangle]
+pub extern "C" fn cffi_handle_free(handle: u64, exception: ErrCallback) -> u8 {
+    // Drop the value only after the registry lock is released, in case it holds handles itself.
+    let result = registry().remove(handle);
+
+    match result {
+        Ok(entry) => {
+            drop(entry);
+            1
+        }
+        Err(e) => {
+            if let Some(callback) = exception {
+                let err = format!("{:?}", e);
+                callback(err.as_bytes().as_ptr(), err.len());
+            }
+            0
+        }
+    }
+}
+
+/// Logs a warning for every handle that has not been freed, returning how many there are.
+#[no_mangle]
+pub extern "C" fn cffi_handle_report_leaks() -> usize {
+    let live = live_handles();
+
+    for LiveHandle { handle, type_name } in &live {
+        log::warn!("leaked handle {:#x} ({})", handle, type_name);
+    }
+
+    live.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let handle = HandleMarshaler::<String>::to_foreign("value".to_string()).unwrap();
+        let shared: Arc<String> =
+            unsafe { HandleMarshaler::<String>::from_foreign(handle) }.unwrap();
+        assert_eq!(*shared, "value");
+        drop(shared);
+
+        let owned: String = unsafe { HandleMarshaler::<String>::from_foreign(handle) }.unwrap();
+        assert_eq!(owned, "value");
+    }
+
+    #[test]
+    fn stale_handle() {
+        let handle = HandleMarshaler::<u32>::to_foreign(1u32).unwrap();
+        assert_eq!(cffi_handle_free(handle, None), 1);
+        assert_eq!(cffi_handle_free(handle, None), 0);
+
+        let result: Result<Arc<u32>, _> = unsafe { HandleMarshaler::<u32>::from_foreign(handle) };
+        assert!(result.is_err());
+
+        // The slot may be reused, but never under the old handle.
+        let next = HandleMarshaler::<u32>::to_foreign(2u32).unwrap();
+        assert_ne!(next, handle);
+        cffi_handle_free(next, None);
+    }
+
+    #[test]
+    fn wrong_type() {
+        let handle = HandleMarshaler::<u32>::to_foreign(1u32).unwrap();
+        let result: Result<Arc<String>, _> =
+            unsafe { HandleMarshaler::<String>::from_foreign(handle) };
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("u32"), "{}", err);
+        cffi_handle_free(handle, None);
+    }
+
+    #[test]
+    fn shared_handle() {
+        let handle = HandleMarshaler::<Vec<u8>>::to_foreign(vec![1u8]).unwrap();
+        let shared: Arc<Vec<u8>> =
+            unsafe { HandleMarshaler::<Vec<u8>>::from_foreign(handle) }.unwrap();
+        let result: Result<Vec<u8>, _> =
+            unsafe { HandleMarshaler::<Vec<u8>>::from_foreign(handle) };
+        assert!(result.is_err());
+
+        drop(shared);
+        let owned: Vec<u8> = unsafe { HandleMarshaler::<Vec<u8>>::from_foreign(handle) }.unwrap();
+        assert_eq!(owned, [1]);
+    }
+
+    #[test]
+    fn leaks() {
+        let handle = HandleMarshaler::<u64>::to_foreign(0u64).unwrap();
+        assert!(live_handles().iter().any(|x| x.handle == handle));
+        assert!(cffi_handle_report_leaks() >= 1);
+        cffi_handle_free(handle, None);
+        assert!(!live_handles().iter().any(|x| x.handle == handle));
+    }
+}
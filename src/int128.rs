@@ -0,0 +1,104 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::marker::PhantomData;
+
+use super::{DropForeign, FromForeign, InputType, ReturnType, ToForeign};
+
+/// The foreign representation of a 128-bit integer, as produced by [`Int128Marshaler`]: the low
+/// and high 64 bits of its two's complement value.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Int128 {
+    pub lo: u64,
+    pub hi: u64,
+}
+
+/// Marshals an `i128` or `u128` as an [`Int128`] struct, as C has no portable 128-bit integer:
+///
+///   - `Int128Marshaler<u128>`: `u128` ↔ `Int128`
+///   - `Int128Marshaler<i128>`: `i128` ↔ `Int128`
+pub struct Int128Marshaler<T>(PhantomData<T>);
+
+impl<T> InputType for Int128Marshaler<T> {
+    type Foreign = Int128;
+    type ForeignTraitObject = ();
+}
+
+impl<T> ReturnType for Int128Marshaler<T> {
+    type Foreign = Int128;
+    type ForeignTraitObject = ();
+
+    fn foreign_default() -> Int128 {
+        Int128::default()
+    }
+}
+
+macro_rules! int128_marshaler {
+    ($($ty:ty),*) => {$(
+        impl FromForeign<Int128, $ty> for Int128Marshaler<$ty> {
+            type Error = Infallible;
+
+            #[inline(always)]
+            unsafe fn from_foreign(i: Int128) -> Result<$ty, Self::Error> {
+                Ok((((i.hi as u128) << 64) | i.lo as u128) as $ty)
+            }
+        }
+
+        impl ToForeign<$ty, Int128> for Int128Marshaler<$ty> {
+            type Error = Infallible;
+
+            #[inline(always)]
+            fn to_foreign(i: $ty) -> Result<Int128, Self::Error> {
+                let i = i as u128;
+                Ok(Int128 {
+                    lo: i as u64,
+                    hi: (i >> 64) as u64,
+                })
+            }
+        }
+
+        impl ToForeign<Result<$ty, Box<dyn Error>>, Int128> for Int128Marshaler<$ty> {
+            type Error = Box<dyn Error>;
+
+            #[inline(always)]
+            fn to_foreign(result: Result<$ty, Box<dyn Error>>) -> Result<Int128, Self::Error> {
+                result.map(|i| match Int128Marshaler::<$ty>::to_foreign(i) {
+                    Ok(v) => v,
+                    Err(e) => match e {},
+                })
+            }
+        }
+
+        impl DropForeign<Int128> for Int128Marshaler<$ty> {
+            #[inline(always)]
+            unsafe fn drop_foreign(_: Int128) {}
+        }
+    )*};
+}
+
+int128_marshaler!(u128, i128);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for i in [0, 1, -1, i128::MIN, i128::MAX, 1 << 64] {
+            let foreign = Int128Marshaler::<i128>::to_foreign(i).unwrap();
+            assert_eq!(
+                unsafe { Int128Marshaler::<i128>::from_foreign(foreign) }.unwrap(),
+                i
+            );
+        }
+
+        let foreign = Int128Marshaler::<u128>::to_foreign(u128::MAX - 1).unwrap();
+        assert_eq!(
+            foreign,
+            Int128 {
+                lo: u64::MAX - 1,
+                hi: u64::MAX
+            }
+        );
+    }
+}
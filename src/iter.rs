@@ -0,0 +1,123 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::marker::PhantomData;
+
+use super::null_ptr_error;
+use super::{ReturnType, ToForeign};
+
+/// An opaque, pull-based cursor over items already converted to their foreign type `F`.
+pub struct Cursor<F>(Box<dyn Iterator<Item = Result<F, Box<dyn Error>>>>);
+
+impl<F> Cursor<F> {
+    /// Advances the cursor, writing the next item to `out`. Returns `false` once exhausted.
+    ///
+    /// # Safety
+    ///
+    /// `cursor` must have been produced by an [`IterMarshaler`] and not freed, and `out` must be
+    /// valid for writes.
+    pub unsafe fn next(cursor: *mut Cursor<F>, out: *mut F) -> Result<bool, Box<dyn Error>> {
+        log::debug!(
+            "Cursor<{ty}>::next({:?}, {:?})",
+            cursor,
+            out,
+            ty = std::any::type_name::<F>()
+        );
+
+        if cursor.is_null() || out.is_null() {
+            return Err(null_ptr_error());
+        }
+
+        match (*cursor).0.next() {
+            None => Ok(false),
+            Some(item) => {
+                out.write(item?);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Frees the cursor, dropping any items not yet pulled. Null cursors are ignored.
+    ///
+    /// # Safety
+    ///
+    /// `cursor` must have been produced by an [`IterMarshaler`] and not already freed.
+    pub unsafe fn free(cursor: *mut Cursor<F>) {
+        if !cursor.is_null() {
+            drop(Box::from_raw(cursor));
+        }
+    }
+}
+
+/// Names the foreign item type of the [`Cursor`] a marshaler produces.
+pub trait CursorType {
+    type Item;
+}
+
+/// Marshals an `Iterator` as an opaque [`Cursor`], lazily converting each item with the element
+/// marshaler `M` as it is pulled.
+///
+/// Functions returning `impl Iterator<Item = T>` or `Box<dyn Iterator<Item = T>>` marshaled with
+/// this also export `{name}_next(cursor, out, exception) -> u8` and `{name}_free(cursor)`.
+///
+/// The iterator must be `'static`, as the cursor outlives the call that returns it, so methods may
+/// not return iterators that borrow `self`: `fn names(&self) -> impl Iterator<Item = String>`
+/// must clone or collect what it iterates over, rather than return `self.names.iter().cloned()`.
+pub struct IterMarshaler<M>(PhantomData<M>);
+
+impl<M: ReturnType> CursorType for IterMarshaler<M> {
+    type Item = M::Foreign;
+}
+
+impl<M: ReturnType> ReturnType for IterMarshaler<M> {
+    type Foreign = *mut Cursor<M::Foreign>;
+    type ForeignTraitObject = ();
+
+    fn foreign_default() -> Self::Foreign {
+        std::ptr::null_mut()
+    }
+}
+
+impl<M, I, L, F> ToForeign<I, *mut Cursor<F>> for IterMarshaler<M>
+where
+    I: Iterator<Item = L> + 'static,
+    M: ToForeign<L, F> + 'static,
+    M::Error: Into<Box<dyn Error>>,
+    F: 'static,
+{
+    type Error = Infallible;
+
+    fn to_foreign(iter: I) -> Result<*mut Cursor<F>, Self::Error> {
+        let items = iter.map(|item| M::to_foreign(item).map_err(Into::into));
+        Ok(Box::into_raw(Box::new(Cursor(Box::new(items)))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FromForeign, Slice, StringMarshaler};
+
+    #[test]
+    fn pull_strings() {
+        let iter = (0..3).map(|i| format!("item {}", i));
+        let cursor = IterMarshaler::<StringMarshaler>::to_foreign(iter).unwrap();
+
+        let mut out = Slice::default();
+        let mut items = vec![];
+        while unsafe { Cursor::next(cursor, &mut out) }.unwrap() {
+            let item: String = unsafe { StringMarshaler::from_foreign(out) }.unwrap();
+            items.push(item);
+            out = Slice::default();
+        }
+
+        assert_eq!(items, ["item 0", "item 1", "item 2"]);
+        unsafe { Cursor::free(cursor) };
+    }
+
+    #[test]
+    fn free_unfinished() {
+        let iter = vec!["a".to_string(), "b".to_string()].into_iter();
+        let cursor = IterMarshaler::<StringMarshaler>::to_foreign(iter).unwrap();
+        unsafe { Cursor::free(cursor) };
+    }
+}
@@ -1,43 +1,102 @@
-pub use cffi_impl::marshal;
+pub use cffi_impl::{alias, foreign_trait, marshal, passthrough};
 
 #[cfg(feature = "url")]
 mod url;
 
+mod alloc;
 mod arc;
 mod arc_ref;
+mod array;
 mod bool;
 mod box_ref;
 mod boxed;
+mod buffer;
+mod char;
 mod copy;
+mod handle;
+mod int128;
+mod iter;
+mod map;
+mod option;
 mod pathbuf;
+mod result;
 mod str;
+mod stream;
 mod string;
+mod sync_handle;
+mod tag;
+mod track;
+mod tuple;
 mod unit;
+mod validate;
 mod vec;
+mod vec_of;
 mod vec_ref;
 
 /// Exported functions for consumption via C API
 pub mod ffi {
-    pub use super::{string::cffi_string_free, vec::cffi_vec_free};
+    pub use super::alloc::{cffi_alloc, cffi_set_allocator};
+    pub use super::handle::{cffi_handle_free, cffi_handle_report_leaks};
+    pub use super::map::cffi_map_string_free;
+    pub use super::stream::{
+        cffi_read_handle_free, cffi_read_handle_read, cffi_read_handle_seek,
+        cffi_write_handle_flush, cffi_write_handle_free, cffi_write_handle_seek,
+        cffi_write_handle_write,
+    };
+    pub use super::string::cffi_string_free;
+    #[cfg(feature = "track-allocations")]
+    pub use super::track::cffi_debug_dump_live_allocations;
+    pub use super::vec::{
+        cffi_vec_free, cffi_vec_free_f32, cffi_vec_free_f64, cffi_vec_free_i16, cffi_vec_free_i32,
+        cffi_vec_free_i64, cffi_vec_free_i8, cffi_vec_free_isize, cffi_vec_free_u16,
+        cffi_vec_free_u32, cffi_vec_free_u64, cffi_vec_free_u8, cffi_vec_free_usize,
+    };
+    pub use super::vec_of::cffi_vec_string_free;
 }
 
 #[cfg(feature = "url")]
 pub use self::url::UrlMarshaler;
 
+pub use self::alloc::{allocator, set_allocator, AllocFn, Allocator, FreeFn};
 pub use self::bool::BoolMarshaler;
+pub use self::buffer::{BufferTooSmall, OutBuffer};
+pub use self::char::CharMarshaler;
+pub use self::handle::{live_handles, HandleError, HandleMarshaler, LiveHandle};
+pub use self::int128::{Int128, Int128Marshaler};
+pub use self::iter::{Cursor, CursorType, IterMarshaler};
+pub use self::map::{MapMarshaler, MapSlice};
+pub use self::option::{Nullable, OptionMarshaler};
 pub use self::pathbuf::PathBufMarshaler;
+pub use self::result::{MarshaledError, ResultMarshaler};
 pub use self::str::StrMarshaler;
+pub use self::stream::{
+    FlushFn, ForeignReader, ForeignWriter, ReadFn, ReadHandle, ReadHandleMarshaler, ReadSeek,
+    ReadVtable, ReaderMarshaler, ReleaseFn, SeekFn, WriteFn, WriteHandle, WriteHandleMarshaler,
+    WriteSeek, WriteVtable, WriterMarshaler,
+};
+pub use self::sync_handle::{SyncHandle, SyncHandleMarshaler, SyncMut, SyncRef};
+pub use self::tag::TypeTagError;
+pub use self::track::AllocationError;
+#[cfg(feature = "track-allocations")]
+pub use self::track::{live_allocations, LiveAllocation};
+pub use self::validate::{
+    FiniteMarshaler, Float, FnPtrMarshaler, Integer, NonNullMarshaler, NonZeroMarshaler, Primitive,
+    RangeMarshaler,
+};
 pub use self::vec::VecMarshaler;
+pub use self::vec_of::VecOfMarshaler;
 pub use arc::ArcMarshaler;
 pub use arc_ref::ArcRefMarshaler;
+pub use array::{Array, ArrayMarshaler, ArrayRefMarshaler};
 pub use box_ref::BoxRefMarshaler;
 pub use boxed::BoxMarshaler;
 pub use copy::CopyMarshaler;
 pub use string::StringMarshaler;
+pub use tuple::{Tuple2, Tuple3, Tuple4, Tuple5, Tuple6, TupleMarshaler};
 pub use unit::UnitMarshaler;
 pub use vec_ref::VecRefMarshaler;
 
-use std::{io, marker::PhantomData};
+use std::{io, sync::Arc};
 
 pub type ErrCallback = Option<extern "C" fn(*const u8, usize)>;
 pub type RetCallback<T> = Option<extern "C" fn(T)>;
@@ -65,13 +124,36 @@ pub trait ToForeign<Local, Foreign>: Sized {
     fn to_foreign(_: Local) -> Result<Foreign, Self::Error>;
 }
 
-pub trait ToForeignTraitObject<Local: ?Sized, Foreign: ?Sized> {
+/// Writes `Local` into a buffer provided by the foreign caller, rather than allocating.
+pub trait ToForeignBuffer<Local>: Sized {
+    type Element;
+    type Error;
+    fn to_foreign_buffer(_: Local, _: &mut OutBuffer<Self::Element>) -> Result<(), Self::Error>;
+}
+
+pub trait ToForeignTraitObject<Local, Foreign: ?Sized> {
     type Error;
-    fn to_foreign_trait_object(_: Local) -> Result<crate::TraitObject<Foreign>, Self::Error>;
+    fn to_foreign_trait_object(_: Local) -> Result<*const TraitObject<Foreign>, Self::Error>;
+}
+
+/// Releases a value previously produced by `ToForeign`, without converting it back.
+///
+/// This lets composed marshalers such as [`VecOfMarshaler`] free their elements.
+pub trait DropForeign<Foreign> {
+    /// # Safety
+    ///
+    /// `Foreign` must have been produced by the matching `ToForeign` implementation and not
+    /// already freed or consumed.
+    unsafe fn drop_foreign(_: Foreign);
 }
 
 pub trait FromForeign<Foreign, Local>: Sized {
     type Error;
+
+    /// # Safety
+    ///
+    /// `Foreign` must be a value produced by the matching `ToForeign` implementation, or a
+    /// valid equivalent provided by the foreign caller.
     unsafe fn from_foreign(_: Foreign) -> Result<Local, Self::Error>;
 }
 
@@ -102,11 +184,43 @@ pub struct Slice<T: ?Sized> {
 }
 
 impl<T> Slice<T> {
-    unsafe fn cast<U>(self) -> Slice<U> {
+    /// Reinterprets the slice's data as a different element type, keeping `len`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must uphold the same requirements as casting `*mut T` to `*mut U`.
+    pub unsafe fn cast<U>(self) -> Slice<U> {
         std::mem::transmute::<Slice<T>, Slice<U>>(self)
     }
 }
 
+/// A foreign value that generated functions may take as two C parameters rather than one
+/// struct, which is requested with `#[marshal(M, split)]` (or `#[marshal(split)]` for the default
+/// marshaler) on a parameter. The second parameter is named after the first with a `_len`
+/// suffix.
+///
+/// `Slice<T>` is split into its `data` and `len`, so a `&str` parameter `name` is taken as
+/// `const uint8_t* name, size_t name_len`.
+pub trait ForeignPair: Sized {
+    type First;
+    type Second;
+
+    fn join(first: Self::First, second: Self::Second) -> Self;
+}
+
+impl<T> ForeignPair for Slice<T> {
+    type First = *const T;
+    type Second = libc::size_t;
+
+    #[inline(always)]
+    fn join(data: *const T, len: libc::size_t) -> Self {
+        Slice {
+            data: data as *mut T,
+            len,
+        }
+    }
+}
+
 impl<T> std::default::Default for Slice<T> {
     fn default() -> Self {
         Slice {
@@ -132,18 +246,99 @@ impl<T> AsRef<[T]> for Slice<T> {
     }
 }
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct TraitObject<T: ?Sized> {
-    pub data: *mut (),
-    pub vtable: *mut (),
-    pub ty: PhantomData<T>,
+/// An opaque handle to a shared trait object.
+///
+/// Foreign code only ever holds a thin `*const TraitObject<dyn Trait>`, and calls methods on it
+/// through the functions generated by `#[marshal]` on the trait, so it never depends on the
+/// layout of Rust's fat pointers or vtables.
+pub struct TraitObject<T: ?Sized>(pub(crate) Arc<T>);
+
+impl<T: ?Sized> TraitObject<T> {
+    /// Frees the handle, releasing its reference to the trait object. Null handles are ignored.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must have been produced by `ToForeignTraitObject` and not already freed.
+    pub unsafe fn free(handle: *const TraitObject<T>) {
+        log::debug!(
+            "TraitObject<{ty}>::free({:?})",
+            handle,
+            ty = std::any::type_name::<T>()
+        );
+
+        if handle.is_null() {
+            return;
+        }
+
+        if let Err(e) = tag::box_from_raw(handle) {
+            log::error!("TraitObject::free: {}", e);
+        }
+    }
 }
 
+/// Exports a function that frees a `Vec<$ty>` returned by a [`VecMarshaler`], dropping each
+/// element.
+///
+/// ```rust
+/// pub struct Point { x: f32, y: f32 }
+///
+/// cffi::vec_free!(my_lib_points_free, Point);
+/// ```
 #[macro_export]
-macro_rules! trait_object {
-    ($input:path : $ty:ty) => {
-        std::mem::transmute_copy::<_, $crate::TraitObject<$ty>>(&$input)
+macro_rules! vec_free {
+    ($name:ident, $ty:ty) => {
+        /// # Safety
+        ///
+        /// `slice` must have been produced by a `VecMarshaler` and not already freed.
+        #[no_mangle]
+        pub unsafe extern "C" fn $name(slice: $crate::Slice<$ty>) {
+            $crate::VecMarshaler::<$ty>::free(slice);
+        }
+    };
+}
+
+/// Exports a function that deep-frees a `Vec` returned by a [`VecOfMarshaler`] using the
+/// element marshaler `$marshaler`.
+///
+/// ```rust
+/// cffi::vec_of_free!(my_lib_paths_free, cffi::PathBufMarshaler);
+/// ```
+#[macro_export]
+macro_rules! vec_of_free {
+    ($name:ident, $marshaler:ty) => {
+        /// # Safety
+        ///
+        /// `slice` must have been produced by a `VecOfMarshaler` and not already freed.
+        #[no_mangle]
+        pub unsafe extern "C" fn $name(
+            slice: $crate::Slice<<$marshaler as $crate::ReturnType>::Foreign>,
+        ) {
+            $crate::VecOfMarshaler::<$marshaler>::free(slice);
+        }
+    };
+}
+
+/// Exports a function that deep-frees a map returned by a [`MapMarshaler`] using the key and
+/// value marshalers `$key` and `$value`.
+///
+/// ```rust
+/// cffi::map_free!(my_lib_counts_free, cffi::StringMarshaler, cffi::CopyMarshaler<u32>);
+/// ```
+#[macro_export]
+macro_rules! map_free {
+    ($name:ident, $key:ty, $value:ty) => {
+        /// # Safety
+        ///
+        /// `map` must have been produced by a `MapMarshaler` and not already freed.
+        #[no_mangle]
+        pub unsafe extern "C" fn $name(
+            map: $crate::MapSlice<
+                <$key as $crate::ReturnType>::Foreign,
+                <$value as $crate::ReturnType>::Foreign,
+            >,
+        ) {
+            $crate::MapMarshaler::<$key, $value>::free(map);
+        }
     };
 }
 
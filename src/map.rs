@@ -0,0 +1,304 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::marker::PhantomData;
+
+use super::{DropForeign, FromForeign, InputType, ReturnType, Slice, ToForeign, VecOfMarshaler};
+
+/// The foreign representation of a map: parallel arrays of keys and values, where
+/// `keys.data[i]` maps to `values.data[i]`. Both slices have the same length.
+#[repr(C)]
+pub struct MapSlice<K, V> {
+    pub keys: Slice<K>,
+    pub values: Slice<V>,
+}
+
+impl<K, V> Default for MapSlice<K, V> {
+    fn default() -> Self {
+        MapSlice {
+            keys: Slice::default(),
+            values: Slice::default(),
+        }
+    }
+}
+
+impl<K, V> std::fmt::Debug for MapSlice<K, V> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter
+            .debug_struct("MapSlice")
+            .field("keys", &self.keys)
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+/// Marshals a `HashMap` or `BTreeMap` as a [`MapSlice`], converting each key with the element
+/// marshaler `K` and each value with `V`, as per [`VecOfMarshaler`].
+///
+/// ## Freeing
+///
+/// The result must be freed with [`MapMarshaler::free`], which also frees each key and value.
+/// Export it for your marshalers with [`map_free!`](crate::map_free); `cffi_map_string_free` is
+/// provided for maps of `String` to `String`.
+pub struct MapMarshaler<K, V>(PhantomData<(K, V)>);
+
+impl<K, V> MapMarshaler<K, V> {
+    /// Frees a map previously returned by `to_foreign`, including each key and value.
+    ///
+    /// # Safety
+    ///
+    /// `map` must have been produced by `MapMarshaler::<K, V>::to_foreign` and not already freed.
+    pub unsafe fn free<FK, FV>(map: MapSlice<FK, FV>)
+    where
+        K: DropForeign<FK>,
+        V: DropForeign<FV>,
+    {
+        VecOfMarshaler::<K>::free(map.keys);
+        VecOfMarshaler::<V>::free(map.values);
+    }
+
+    fn split<LK, LV, FK, FV>(
+        entries: impl ExactSizeIterator<Item = (LK, LV)>,
+    ) -> Result<MapSlice<FK, FV>, Box<dyn Error>>
+    where
+        K: ToForeign<LK, FK> + DropForeign<FK>,
+        V: ToForeign<LV, FV> + DropForeign<FV>,
+        K::Error: Into<Box<dyn Error>>,
+        V::Error: Into<Box<dyn Error>>,
+    {
+        let len = entries.len();
+        let (keys, values) = entries.fold(
+            (Vec::with_capacity(len), Vec::with_capacity(len)),
+            |(mut keys, mut values), (k, v)| {
+                keys.push(k);
+                values.push(v);
+                (keys, values)
+            },
+        );
+
+        let keys = VecOfMarshaler::<K>::to_foreign(keys)?;
+        let values = match VecOfMarshaler::<V>::to_foreign(values) {
+            Ok(v) => v,
+            Err(e) => {
+                unsafe { VecOfMarshaler::<K>::free(keys) };
+                return Err(e);
+            }
+        };
+
+        Ok(MapSlice { keys, values })
+    }
+
+    unsafe fn join<LK, LV, FK, FV>(
+        map: MapSlice<FK, FV>,
+    ) -> Result<impl Iterator<Item = (LK, LV)>, Box<dyn Error>>
+    where
+        K: FromForeign<FK, LK> + DropForeign<FK>,
+        V: FromForeign<FV, LV> + DropForeign<FV>,
+        K::Error: Into<Box<dyn Error>>,
+        V::Error: Into<Box<dyn Error>>,
+    {
+        if map.keys.len != map.values.len {
+            let err = io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "map has {} keys but {} values",
+                    map.keys.len, map.values.len
+                ),
+            );
+            MapMarshaler::<K, V>::free(map);
+            return Err(Box::new(err));
+        }
+
+        let MapSlice { keys, values } = map;
+        let keys: Vec<LK> = match VecOfMarshaler::<K>::from_foreign(keys) {
+            Ok(v) => v,
+            Err(e) => {
+                VecOfMarshaler::<V>::free(values);
+                return Err(e);
+            }
+        };
+        let values: Vec<LV> = VecOfMarshaler::<V>::from_foreign(values)?;
+
+        Ok(keys.into_iter().zip(values))
+    }
+}
+
+impl<K: InputType, V: InputType> InputType for MapMarshaler<K, V> {
+    type Foreign = MapSlice<K::Foreign, V::Foreign>;
+    type ForeignTraitObject = ();
+}
+
+impl<K: ReturnType, V: ReturnType> ReturnType for MapMarshaler<K, V> {
+    type Foreign = MapSlice<K::Foreign, V::Foreign>;
+    type ForeignTraitObject = ();
+
+    fn foreign_default() -> Self::Foreign {
+        MapSlice::default()
+    }
+}
+
+impl<K, V, LK, LV, FK, FV, S> ToForeign<HashMap<LK, LV, S>, MapSlice<FK, FV>> for MapMarshaler<K, V>
+where
+    K: ToForeign<LK, FK> + DropForeign<FK>,
+    V: ToForeign<LV, FV> + DropForeign<FV>,
+    K::Error: Into<Box<dyn Error>>,
+    V::Error: Into<Box<dyn Error>>,
+{
+    type Error = Box<dyn Error>;
+
+    fn to_foreign(map: HashMap<LK, LV, S>) -> Result<MapSlice<FK, FV>, Self::Error> {
+        MapMarshaler::<K, V>::split(map.into_iter())
+    }
+}
+
+impl<K, V, LK, LV, FK, FV> ToForeign<BTreeMap<LK, LV>, MapSlice<FK, FV>> for MapMarshaler<K, V>
+where
+    K: ToForeign<LK, FK> + DropForeign<FK>,
+    V: ToForeign<LV, FV> + DropForeign<FV>,
+    K::Error: Into<Box<dyn Error>>,
+    V::Error: Into<Box<dyn Error>>,
+{
+    type Error = Box<dyn Error>;
+
+    fn to_foreign(map: BTreeMap<LK, LV>) -> Result<MapSlice<FK, FV>, Self::Error> {
+        MapMarshaler::<K, V>::split(map.into_iter())
+    }
+}
+
+impl<K, V, LK, LV, FK, FV, S>
+    ToForeign<Result<HashMap<LK, LV, S>, Box<dyn Error>>, MapSlice<FK, FV>> for MapMarshaler<K, V>
+where
+    K: ToForeign<LK, FK> + DropForeign<FK>,
+    V: ToForeign<LV, FV> + DropForeign<FV>,
+    K::Error: Into<Box<dyn Error>>,
+    V::Error: Into<Box<dyn Error>>,
+{
+    type Error = Box<dyn Error>;
+
+    fn to_foreign(
+        result: Result<HashMap<LK, LV, S>, Box<dyn Error>>,
+    ) -> Result<MapSlice<FK, FV>, Self::Error> {
+        result.and_then(|map| MapMarshaler::<K, V>::split(map.into_iter()))
+    }
+}
+
+impl<K, V, LK, LV, FK, FV> ToForeign<Result<BTreeMap<LK, LV>, Box<dyn Error>>, MapSlice<FK, FV>>
+    for MapMarshaler<K, V>
+where
+    K: ToForeign<LK, FK> + DropForeign<FK>,
+    V: ToForeign<LV, FV> + DropForeign<FV>,
+    K::Error: Into<Box<dyn Error>>,
+    V::Error: Into<Box<dyn Error>>,
+{
+    type Error = Box<dyn Error>;
+
+    fn to_foreign(
+        result: Result<BTreeMap<LK, LV>, Box<dyn Error>>,
+    ) -> Result<MapSlice<FK, FV>, Self::Error> {
+        result.and_then(|map| MapMarshaler::<K, V>::split(map.into_iter()))
+    }
+}
+
+impl<K, V, LK, LV, FK, FV, S> FromForeign<MapSlice<FK, FV>, HashMap<LK, LV, S>>
+    for MapMarshaler<K, V>
+where
+    K: FromForeign<FK, LK> + DropForeign<FK>,
+    V: FromForeign<FV, LV> + DropForeign<FV>,
+    K::Error: Into<Box<dyn Error>>,
+    V::Error: Into<Box<dyn Error>>,
+    LK: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    type Error = Box<dyn Error>;
+
+    unsafe fn from_foreign(map: MapSlice<FK, FV>) -> Result<HashMap<LK, LV, S>, Self::Error> {
+        MapMarshaler::<K, V>::join(map).map(Iterator::collect)
+    }
+}
+
+impl<K, V, LK, LV, FK, FV> FromForeign<MapSlice<FK, FV>, BTreeMap<LK, LV>> for MapMarshaler<K, V>
+where
+    K: FromForeign<FK, LK> + DropForeign<FK>,
+    V: FromForeign<FV, LV> + DropForeign<FV>,
+    K::Error: Into<Box<dyn Error>>,
+    V::Error: Into<Box<dyn Error>>,
+    LK: Ord,
+{
+    type Error = Box<dyn Error>;
+
+    unsafe fn from_foreign(map: MapSlice<FK, FV>) -> Result<BTreeMap<LK, LV>, Self::Error> {
+        MapMarshaler::<K, V>::join(map).map(Iterator::collect)
+    }
+}
+
+impl<K, V, FK, FV> DropForeign<MapSlice<FK, FV>> for MapMarshaler<K, V>
+where
+    K: DropForeign<FK>,
+    V: DropForeign<FV>,
+{
+    #[inline(always)]
+    unsafe fn drop_foreign(map: MapSlice<FK, FV>) {
+        MapMarshaler::<K, V>::free(map)
+    }
+}
+
+crate::map_free!(
+    cffi_map_string_free,
+    crate::StringMarshaler,
+    crate::StringMarshaler
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CopyMarshaler, StringMarshaler};
+
+    type ConfigMarshaler = MapMarshaler<StringMarshaler, StringMarshaler>;
+
+    fn config() -> BTreeMap<String, String> {
+        [("lang", "se"), ("mode", "strict")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_btree_map() {
+        let map = ConfigMarshaler::to_foreign(config()).unwrap();
+        assert_eq!(map.keys.len, 2);
+        let out: BTreeMap<String, String> = unsafe { ConfigMarshaler::from_foreign(map) }.unwrap();
+        assert_eq!(out, config());
+    }
+
+    #[test]
+    fn round_trip_hash_map() {
+        let input: HashMap<String, u32> = [("a".to_string(), 1), ("b".to_string(), 2)]
+            .into_iter()
+            .collect();
+        let map =
+            MapMarshaler::<StringMarshaler, CopyMarshaler<u32>>::to_foreign(input.clone()).unwrap();
+        let out: HashMap<String, u32> =
+            unsafe { MapMarshaler::<StringMarshaler, CopyMarshaler<u32>>::from_foreign(map) }
+                .unwrap();
+        assert_eq!(out, input);
+    }
+
+    #[test]
+    fn free_map() {
+        let map = ConfigMarshaler::to_foreign(config()).unwrap();
+        unsafe { cffi_map_string_free(map) };
+    }
+
+    #[test]
+    fn mismatched_lengths() {
+        let mut map = ConfigMarshaler::to_foreign(config()).unwrap();
+        let values = map.values;
+        map.values = VecOfMarshaler::<StringMarshaler>::to_foreign(vec!["x".to_string()]).unwrap();
+
+        let result: Result<BTreeMap<String, String>, _> =
+            unsafe { ConfigMarshaler::from_foreign(map) };
+        assert!(result.is_err());
+        unsafe { VecOfMarshaler::<StringMarshaler>::free(values) };
+    }
+}
@@ -0,0 +1,158 @@
+use std::error::Error;
+use std::marker::PhantomData;
+
+use super::{DropForeign, FromForeign, InputType, ReturnType, Slice, ToForeign};
+
+/// A foreign representation with a null value, which [`OptionMarshaler`] uses for `None`.
+pub trait Nullable {
+    fn null() -> Self;
+    fn is_null(&self) -> bool;
+}
+
+impl<T> Nullable for *const T {
+    #[inline(always)]
+    fn null() -> Self {
+        std::ptr::null()
+    }
+
+    #[inline(always)]
+    fn is_null(&self) -> bool {
+        <*const T>::is_null(*self)
+    }
+}
+
+impl<T> Nullable for *mut T {
+    #[inline(always)]
+    fn null() -> Self {
+        std::ptr::null_mut()
+    }
+
+    #[inline(always)]
+    fn is_null(&self) -> bool {
+        <*mut T>::is_null(*self)
+    }
+}
+
+impl<T> Nullable for Slice<T> {
+    #[inline(always)]
+    fn null() -> Self {
+        Slice::default()
+    }
+
+    #[inline(always)]
+    fn is_null(&self) -> bool {
+        self.data.is_null()
+    }
+}
+
+/// Marshals an `Option` with the marshaler `M`, passing `None` as the null value of `M`'s
+/// foreign type, such as a null pointer or a `Slice` with null `data`.
+///
+/// `#[marshal(Option<M>)]` is shorthand for `#[marshal(OptionMarshaler<M>)]`:
+///
+///   - `OptionMarshaler<StringMarshaler>`: `Option<String>` ↔ `Slice<u8>`
+///   - `OptionMarshaler<VecOfMarshaler<StringMarshaler>>`: `Option<Vec<String>>` ↔
+///     `Slice<Slice<u8>>`
+///
+/// `M` must never produce a null value for `Some`, which holds for the marshalers in this crate.
+pub struct OptionMarshaler<M>(PhantomData<M>);
+
+impl<M: InputType> InputType for OptionMarshaler<M> {
+    type Foreign = M::Foreign;
+    type ForeignTraitObject = ();
+}
+
+impl<M: ReturnType> ReturnType for OptionMarshaler<M> {
+    type Foreign = M::Foreign;
+    type ForeignTraitObject = ();
+
+    #[inline(always)]
+    fn foreign_default() -> Self::Foreign {
+        M::foreign_default()
+    }
+}
+
+impl<M, L, F> ToForeign<Option<L>, F> for OptionMarshaler<M>
+where
+    M: ToForeign<L, F>,
+    M::Error: Into<Box<dyn Error>>,
+    F: Nullable,
+{
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(option: Option<L>) -> Result<F, Self::Error> {
+        match option {
+            Some(v) => M::to_foreign(v).map_err(Into::into),
+            None => Ok(F::null()),
+        }
+    }
+}
+
+impl<M, L, F> ToForeign<Result<Option<L>, Box<dyn Error>>, F> for OptionMarshaler<M>
+where
+    M: ToForeign<L, F>,
+    M::Error: Into<Box<dyn Error>>,
+    F: Nullable,
+{
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(result: Result<Option<L>, Box<dyn Error>>) -> Result<F, Self::Error> {
+        result.and_then(OptionMarshaler::<M>::to_foreign)
+    }
+}
+
+impl<M, L, F> FromForeign<F, Option<L>> for OptionMarshaler<M>
+where
+    M: FromForeign<F, L>,
+    M::Error: Into<Box<dyn Error>>,
+    F: Nullable,
+{
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(foreign: F) -> Result<Option<L>, Self::Error> {
+        if foreign.is_null() {
+            return Ok(None);
+        }
+
+        M::from_foreign(foreign).map(Some).map_err(Into::into)
+    }
+}
+
+impl<M, F> DropForeign<F> for OptionMarshaler<M>
+where
+    M: DropForeign<F>,
+    F: Nullable,
+{
+    #[inline(always)]
+    unsafe fn drop_foreign(foreign: F) {
+        if !foreign.is_null() {
+            M::drop_foreign(foreign);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{StringMarshaler, VecOfMarshaler};
+
+    type Marshaler = OptionMarshaler<VecOfMarshaler<StringMarshaler>>;
+
+    #[test]
+    fn nested() {
+        let strings = vec!["one".to_string(), "two".to_string()];
+
+        let foreign: Slice<Slice<u8>> = Marshaler::to_foreign(Some(strings.clone())).unwrap();
+        assert!(!foreign.is_null());
+        let local: Option<Vec<String>> = unsafe { Marshaler::from_foreign(foreign) }.unwrap();
+        assert_eq!(local, Some(strings));
+
+        let foreign: Slice<Slice<u8>> = Marshaler::to_foreign(None::<Vec<String>>).unwrap();
+        assert!(foreign.is_null());
+        let local: Option<Vec<String>> = unsafe { Marshaler::from_foreign(foreign) }.unwrap();
+        assert_eq!(local, None);
+    }
+}
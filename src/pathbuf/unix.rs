@@ -7,7 +7,9 @@ use libc::wchar_t;
 
 use crate::null_ptr_error;
 use crate::vec::VecMarshaler;
-use crate::{FromForeign, InputType, ReturnType, Slice, ToForeign};
+use crate::{
+    DropForeign, FromForeign, InputType, OutBuffer, ReturnType, Slice, ToForeign, ToForeignBuffer,
+};
 
 pub struct PathBufMarshaler;
 
@@ -65,6 +67,43 @@ impl<E> ToForeign<Result<PathBuf, E>, Slice<u8>> for PathBufMarshaler {
 
     #[inline(always)]
     fn to_foreign(input: Result<PathBuf, E>) -> Result<Slice<u8>, Self::Error> {
-        input.and_then(|x| Ok(PathBufMarshaler::to_foreign(x).unwrap()))
+        input.map(|x| PathBufMarshaler::to_foreign(x).unwrap())
+    }
+}
+
+#[cfg(unix)]
+impl ToForeignBuffer<PathBuf> for PathBufMarshaler {
+    type Element = u8;
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign_buffer(input: PathBuf, buffer: &mut OutBuffer<u8>) -> Result<(), Self::Error> {
+        use std::os::unix::ffi::OsStrExt;
+
+        buffer.write(input.as_os_str().as_bytes())
+    }
+}
+
+#[cfg(unix)]
+impl<E: Into<Box<dyn Error>>> ToForeignBuffer<Result<PathBuf, E>> for PathBufMarshaler {
+    type Element = u8;
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign_buffer(
+        input: Result<PathBuf, E>,
+        buffer: &mut OutBuffer<u8>,
+    ) -> Result<(), Self::Error> {
+        input
+            .map_err(Into::into)
+            .and_then(|x| PathBufMarshaler::to_foreign_buffer(x, buffer))
+    }
+}
+
+#[cfg(unix)]
+impl DropForeign<Slice<u8>> for PathBufMarshaler {
+    #[inline(always)]
+    unsafe fn drop_foreign(slice: Slice<u8>) {
+        VecMarshaler::free(slice)
     }
 }
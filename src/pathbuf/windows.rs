@@ -9,7 +9,9 @@ use libc::wchar_t;
 
 use crate::null_ptr_error;
 use crate::vec::VecMarshaler;
-use crate::{FromForeign, InputType, ReturnType, Slice, ToForeign};
+use crate::{
+    DropForeign, FromForeign, InputType, OutBuffer, ReturnType, Slice, ToForeign, ToForeignBuffer,
+};
 
 pub struct PathBufMarshaler;
 
@@ -68,3 +70,42 @@ impl<E> ToForeign<Result<PathBuf, E>, Slice<u16>> for PathBufMarshaler {
         input.and_then(|x| Ok(PathBufMarshaler::to_foreign(x).unwrap()))
     }
 }
+
+impl ToForeignBuffer<PathBuf> for PathBufMarshaler {
+    type Element = u16;
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign_buffer(input: PathBuf, buffer: &mut OutBuffer<u16>) -> Result<(), Self::Error> {
+        use std::os::windows::ffi::OsStrExt;
+
+        let vec: Vec<wchar_t> = input
+            .into_os_string()
+            .encode_wide()
+            .chain(Some(0).into_iter())
+            .collect();
+        buffer.write(&vec)
+    }
+}
+
+impl<E: Into<Box<dyn Error>>> ToForeignBuffer<Result<PathBuf, E>> for PathBufMarshaler {
+    type Element = u16;
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign_buffer(
+        input: Result<PathBuf, E>,
+        buffer: &mut OutBuffer<u16>,
+    ) -> Result<(), Self::Error> {
+        input
+            .map_err(Into::into)
+            .and_then(|x| PathBufMarshaler::to_foreign_buffer(x, buffer))
+    }
+}
+
+impl DropForeign<Slice<u16>> for PathBufMarshaler {
+    #[inline(always)]
+    unsafe fn drop_foreign(slice: Slice<u16>) {
+        VecMarshaler::free(slice)
+    }
+}
@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+
+use super::{DropForeign, ReturnType, Slice, ToForeign};
+
+/// An error marshaled by the error marshaler of a [`ResultMarshaler`], or a value rejected by a
+/// validating marshaler such as [`RangeMarshaler`]. It formats as the message itself, so the
+/// exception callback receives it unchanged.
+///
+/// [`RangeMarshaler`]: crate::RangeMarshaler
+pub struct MarshaledError(pub(crate) String);
+
+impl MarshaledError {
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for MarshaledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Display for MarshaledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for MarshaledError {}
+
+/// Returns a `Result<T, E>` by marshaling `Ok` values with `M` and `Err` values with `E`.
+///
+/// `#[marshal(Result<M, E>)]` is shorthand for `#[marshal(ResultMarshaler<M, E>)]`. The `Ok`
+/// value is returned as `M` would return it. The `Err` value is marshaled by `E` into a UTF-8
+/// message, which is passed to the exception callback. `E` must produce UTF-8: the callback
+/// receives text, so binary errors are replaced with a message saying they could not be passed:
+///
+///   - `ResultMarshaler<StringMarshaler, StringMarshaler>`: `Result<String, String>` →
+///     `Slice<u8>`, with the error string passed to the callback
+///
+/// ### To the foreign interface:
+///
+///   - `Result<T, E>` → `M::Foreign`
+pub struct ResultMarshaler<M, E>(PhantomData<(M, E)>);
+
+impl<M: ReturnType, E> ReturnType for ResultMarshaler<M, E> {
+    type Foreign = M::Foreign;
+    type ForeignTraitObject = ();
+
+    #[inline(always)]
+    fn foreign_default() -> Self::Foreign {
+        M::foreign_default()
+    }
+}
+
+impl<M, E, L, LE, F> ToForeign<Result<L, LE>, F> for ResultMarshaler<M, E>
+where
+    M: ToForeign<L, F>,
+    M::Error: Into<Box<dyn Error>>,
+    E: ToForeign<LE, Slice<u8>> + DropForeign<Slice<u8>>,
+    E::Error: Into<Box<dyn Error>>,
+{
+    type Error = Box<dyn Error>;
+
+    fn to_foreign(result: Result<L, LE>) -> Result<F, Self::Error> {
+        let error = match result {
+            Ok(v) => return M::to_foreign(v).map_err(Into::into),
+            Err(e) => E::to_foreign(e).map_err(Into::into)?,
+        };
+
+        let message = match error.data.is_null() {
+            true => String::new(),
+            false => match std::str::from_utf8(error.as_ref()) {
+                Ok(v) => v.to_string(),
+                Err(_) => "error marshaler produced a message that is not UTF-8".to_string(),
+            },
+        };
+        unsafe { E::drop_foreign(error) };
+
+        Err(Box::new(MarshaledError(message)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{StringMarshaler, VecMarshaler};
+
+    type Marshaler = ResultMarshaler<StringMarshaler, StringMarshaler>;
+
+    #[test]
+    fn error_message() {
+        let foreign: Slice<u8> = Marshaler::to_foreign(Ok::<_, String>("ok".to_string())).unwrap();
+        unsafe { StringMarshaler::drop_foreign(foreign) };
+
+        let result: Result<Slice<u8>, _> =
+            Marshaler::to_foreign(Err::<String, _>("not found".to_string()));
+        assert_eq!(format!("{:?}", result.unwrap_err()), "not found");
+    }
+
+    #[test]
+    fn non_utf8_error() {
+        type Marshaler = ResultMarshaler<StringMarshaler, VecMarshaler<u8>>;
+
+        let result: Result<Slice<u8>, _> = Marshaler::to_foreign(Err::<String, _>(vec![0xff, 0]));
+        assert_eq!(
+            format!("{:?}", result.unwrap_err()),
+            "error marshaler produced a message that is not UTF-8"
+        );
+    }
+}
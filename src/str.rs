@@ -2,9 +2,9 @@ use std::error::Error;
 use std::marker::PhantomData;
 
 use super::null_ptr_error;
-use super::{FromForeign, InputType, ReturnType, Slice, ToForeign};
+use super::{DropForeign, FromForeign, InputType, ReturnType, Slice, ToForeign};
 
-pub struct StrMarshaler<'a>(&'a PhantomData<()>);
+pub struct StrMarshaler<'a>(PhantomData<&'a ()>);
 
 impl InputType for StrMarshaler<'_> {
     type Foreign = Slice<u8>;
@@ -26,13 +26,14 @@ impl<'a> ToForeign<&'a str, Slice<u8>> for StrMarshaler<'a> {
 
     #[inline(always)]
     fn to_foreign(input: &'a str) -> Result<Slice<u8>, Self::Error> {
-        let bytes = input.to_owned().into_boxed_str().into_boxed_bytes();
-        let len = bytes.len();
+        Ok(crate::alloc::into_foreign_slice(input.as_bytes().to_vec()))
+    }
+}
 
-        Ok(Slice {
-            data: Box::into_raw(bytes) as _,
-            len,
-        })
+impl DropForeign<Slice<u8>> for StrMarshaler<'_> {
+    #[inline(always)]
+    unsafe fn drop_foreign(slice: Slice<u8>) {
+        crate::VecMarshaler::free(slice)
     }
 }
 
@@ -0,0 +1,826 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::ffi::c_void;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::null_ptr_error;
+use super::{ErrCallback, FromForeign, InputType, ReturnType, ToForeign};
+
+pub type ReadFn = unsafe extern "C" fn(userdata: *mut c_void, buf: *mut u8, len: usize) -> isize;
+pub type WriteFn = unsafe extern "C" fn(userdata: *mut c_void, buf: *const u8, len: usize) -> isize;
+pub type FlushFn = unsafe extern "C" fn(userdata: *mut c_void) -> i32;
+pub type SeekFn = unsafe extern "C" fn(userdata: *mut c_void, offset: i64, whence: i32) -> i64;
+pub type ReleaseFn = unsafe extern "C" fn(userdata: *mut c_void);
+
+/// A readable stream implemented by the foreign caller.
+///
+/// `read` returns the number of bytes read (0 at end of stream) or a negative value on error.
+/// `seek` is optional, taking a `whence` of 0 (start), 1 (current) or 2 (end) and returning the
+/// new position or a negative value on error. `release`, if set, is called with `userdata` once
+/// Rust is done with the stream.
+#[repr(C)]
+pub struct ReadVtable {
+    pub userdata: *mut c_void,
+    pub read: Option<ReadFn>,
+    pub seek: Option<SeekFn>,
+    pub release: Option<ReleaseFn>,
+}
+
+/// A writable stream implemented by the foreign caller.
+///
+/// `write` returns the number of bytes written or a negative value on error, and `flush`
+/// returns 0 on success. `seek` and `release` behave as for [`ReadVtable`].
+#[repr(C)]
+pub struct WriteVtable {
+    pub userdata: *mut c_void,
+    pub write: Option<WriteFn>,
+    pub flush: Option<FlushFn>,
+    pub seek: Option<SeekFn>,
+    pub release: Option<ReleaseFn>,
+}
+
+fn foreign_error(op: &str, code: i64) -> io::Error {
+    io::Error::other(format!("foreign {} failed with code {}", op, code))
+}
+
+unsafe fn foreign_seek(
+    seek: Option<SeekFn>,
+    userdata: *mut c_void,
+    pos: SeekFrom,
+) -> io::Result<u64> {
+    let seek = match seek {
+        Some(v) => v,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "foreign stream is not seekable",
+            ))
+        }
+    };
+
+    let (offset, whence) = match pos {
+        SeekFrom::Start(x) => match i64::try_from(x) {
+            Ok(x) => (x, 0),
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("seek to {} is past the largest foreign offset", x),
+                ))
+            }
+        },
+        SeekFrom::Current(x) => (x, 1),
+        SeekFrom::End(x) => (x, 2),
+    };
+
+    match seek(userdata, offset, whence) {
+        x if x < 0 => Err(foreign_error("seek", x)),
+        x => Ok(x as u64),
+    }
+}
+
+/// Tells the foreign side that Rust is done with `userdata`, whether the vtable was used or
+/// rejected.
+unsafe fn release(release: Option<ReleaseFn>, userdata: *mut c_void) {
+    if let Some(release) = release {
+        release(userdata);
+    }
+}
+
+/// Adapts a [`ReadVtable`] into a Rust `Read + Seek` implementor.
+pub struct ForeignReader {
+    vtable: ReadVtable,
+    read: ReadFn,
+}
+
+impl Read for ForeignReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match unsafe { (self.read)(self.vtable.userdata, buf.as_mut_ptr(), buf.len()) } {
+            x if x < 0 => Err(foreign_error("read", x as i64)),
+            x => Ok((x as usize).min(buf.len())),
+        }
+    }
+}
+
+impl Seek for ForeignReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        unsafe { foreign_seek(self.vtable.seek, self.vtable.userdata, pos) }
+    }
+}
+
+impl Drop for ForeignReader {
+    fn drop(&mut self) {
+        unsafe { release(self.vtable.release, self.vtable.userdata) };
+    }
+}
+
+/// Adapts a [`WriteVtable`] into a Rust `Write + Seek` implementor.
+pub struct ForeignWriter {
+    vtable: WriteVtable,
+    write: WriteFn,
+}
+
+impl Write for ForeignWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match unsafe { (self.write)(self.vtable.userdata, buf.as_ptr(), buf.len()) } {
+            x if x < 0 => Err(foreign_error("write", x as i64)),
+            x => Ok((x as usize).min(buf.len())),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.vtable.flush {
+            Some(flush) => match unsafe { flush(self.vtable.userdata) } {
+                0 => Ok(()),
+                x => Err(foreign_error("flush", x as i64)),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Seek for ForeignWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        unsafe { foreign_seek(self.vtable.seek, self.vtable.userdata, pos) }
+    }
+}
+
+impl Drop for ForeignWriter {
+    fn drop(&mut self) {
+        unsafe { release(self.vtable.release, self.vtable.userdata) };
+    }
+}
+
+/// Marshals a foreign [`ReadVtable`] into a [`ForeignReader`], `Box<dyn Read>` or
+/// `Box<dyn ReadSeek>`.
+pub struct ReaderMarshaler;
+
+impl InputType for ReaderMarshaler {
+    type Foreign = ReadVtable;
+    type ForeignTraitObject = ();
+}
+
+impl FromForeign<ReadVtable, ForeignReader> for ReaderMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(vtable: ReadVtable) -> Result<ForeignReader, Self::Error> {
+        match vtable.read {
+            Some(read) => Ok(ForeignReader { vtable, read }),
+            None => {
+                release(vtable.release, vtable.userdata);
+                Err(null_ptr_error())
+            }
+        }
+    }
+}
+
+impl FromForeign<ReadVtable, Box<dyn Read>> for ReaderMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(vtable: ReadVtable) -> Result<Box<dyn Read>, Self::Error> {
+        let reader: ForeignReader = ReaderMarshaler::from_foreign(vtable)?;
+        Ok(Box::new(reader))
+    }
+}
+
+impl FromForeign<ReadVtable, Box<dyn ReadSeek>> for ReaderMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(vtable: ReadVtable) -> Result<Box<dyn ReadSeek>, Self::Error> {
+        let reader: ForeignReader = ReaderMarshaler::from_foreign(vtable)?;
+        Ok(Box::new(reader))
+    }
+}
+
+/// Marshals a foreign [`WriteVtable`] into a [`ForeignWriter`], `Box<dyn Write>` or
+/// `Box<dyn WriteSeek>`.
+pub struct WriterMarshaler;
+
+impl InputType for WriterMarshaler {
+    type Foreign = WriteVtable;
+    type ForeignTraitObject = ();
+}
+
+impl FromForeign<WriteVtable, ForeignWriter> for WriterMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(vtable: WriteVtable) -> Result<ForeignWriter, Self::Error> {
+        match vtable.write {
+            Some(write) => Ok(ForeignWriter { vtable, write }),
+            None => {
+                release(vtable.release, vtable.userdata);
+                Err(null_ptr_error())
+            }
+        }
+    }
+}
+
+impl FromForeign<WriteVtable, Box<dyn Write>> for WriterMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(vtable: WriteVtable) -> Result<Box<dyn Write>, Self::Error> {
+        let writer: ForeignWriter = WriterMarshaler::from_foreign(vtable)?;
+        Ok(Box::new(writer))
+    }
+}
+
+impl FromForeign<WriteVtable, Box<dyn WriteSeek>> for WriterMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(vtable: WriteVtable) -> Result<Box<dyn WriteSeek>, Self::Error> {
+        let writer: ForeignWriter = WriterMarshaler::from_foreign(vtable)?;
+        Ok(Box::new(writer))
+    }
+}
+
+/// A `Read + Seek` implementor, which [`ReadHandleMarshaler`] hands out as a seekable handle and
+/// [`ReaderMarshaler`] accepts from a [`ReadVtable`].
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek + ?Sized> ReadSeek for T {}
+
+/// A `Write + Seek` implementor, which [`WriteHandleMarshaler`] hands out as a seekable handle
+/// and [`WriterMarshaler`] accepts from a [`WriteVtable`].
+pub trait WriteSeek: Write + Seek {}
+
+impl<T: Write + Seek + ?Sized> WriteSeek for T {}
+
+/// Wraps a stream that cannot seek, failing every seek.
+struct NotSeekable<T: ?Sized>(Box<T>);
+
+impl<T: Read + ?Sized> Read for NotSeekable<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Write + ?Sized> Write for NotSeekable<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: ?Sized> Seek for NotSeekable<T> {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "stream is not seekable",
+        ))
+    }
+}
+
+/// An opaque handle to a Rust `Read` implementor, used with `cffi_read_handle_read`,
+/// `cffi_read_handle_seek` and `cffi_read_handle_free`.
+pub struct ReadHandle(Box<dyn ReadSeek>);
+
+/// An opaque handle to a Rust `Write` implementor, used with `cffi_write_handle_write`,
+/// `cffi_write_handle_flush`, `cffi_write_handle_seek` and `cffi_write_handle_free`.
+pub struct WriteHandle(Box<dyn WriteSeek>);
+
+/// Marshals a `Box<dyn Read>` as an opaque [`ReadHandle`], or a `Box<dyn ReadSeek>` as a
+/// seekable one.
+pub struct ReadHandleMarshaler;
+
+impl ReturnType for ReadHandleMarshaler {
+    type Foreign = *mut ReadHandle;
+    type ForeignTraitObject = ();
+
+    fn foreign_default() -> Self::Foreign {
+        std::ptr::null_mut()
+    }
+}
+
+impl ToForeign<Box<dyn Read>, *mut ReadHandle> for ReadHandleMarshaler {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign(reader: Box<dyn Read>) -> Result<*mut ReadHandle, Self::Error> {
+        ReadHandleMarshaler::to_foreign(Box::new(NotSeekable(reader)) as Box<dyn ReadSeek>)
+    }
+}
+
+impl ToForeign<Box<dyn ReadSeek>, *mut ReadHandle> for ReadHandleMarshaler {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign(reader: Box<dyn ReadSeek>) -> Result<*mut ReadHandle, Self::Error> {
+        Ok(Box::into_raw(Box::new(ReadHandle(reader))))
+    }
+}
+
+impl ToForeign<Result<Box<dyn Read>, Box<dyn Error>>, *mut ReadHandle> for ReadHandleMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(
+        result: Result<Box<dyn Read>, Box<dyn Error>>,
+    ) -> Result<*mut ReadHandle, Self::Error> {
+        result.map(|x| Box::into_raw(Box::new(ReadHandle(Box::new(NotSeekable(x))))))
+    }
+}
+
+impl ToForeign<Result<Box<dyn ReadSeek>, Box<dyn Error>>, *mut ReadHandle> for ReadHandleMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(
+        result: Result<Box<dyn ReadSeek>, Box<dyn Error>>,
+    ) -> Result<*mut ReadHandle, Self::Error> {
+        result.map(|x| Box::into_raw(Box::new(ReadHandle(x))))
+    }
+}
+
+/// Marshals a `Box<dyn Write>` as an opaque [`WriteHandle`], or a `Box<dyn WriteSeek>` as a
+/// seekable one.
+pub struct WriteHandleMarshaler;
+
+impl ReturnType for WriteHandleMarshaler {
+    type Foreign = *mut WriteHandle;
+    type ForeignTraitObject = ();
+
+    fn foreign_default() -> Self::Foreign {
+        std::ptr::null_mut()
+    }
+}
+
+impl ToForeign<Box<dyn Write>, *mut WriteHandle> for WriteHandleMarshaler {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign(writer: Box<dyn Write>) -> Result<*mut WriteHandle, Self::Error> {
+        WriteHandleMarshaler::to_foreign(Box::new(NotSeekable(writer)) as Box<dyn WriteSeek>)
+    }
+}
+
+impl ToForeign<Box<dyn WriteSeek>, *mut WriteHandle> for WriteHandleMarshaler {
+    type Error = Infallible;
+
+    #[inline(always)]
+    fn to_foreign(writer: Box<dyn WriteSeek>) -> Result<*mut WriteHandle, Self::Error> {
+        Ok(Box::into_raw(Box::new(WriteHandle(writer))))
+    }
+}
+
+impl ToForeign<Result<Box<dyn Write>, Box<dyn Error>>, *mut WriteHandle> for WriteHandleMarshaler {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(
+        result: Result<Box<dyn Write>, Box<dyn Error>>,
+    ) -> Result<*mut WriteHandle, Self::Error> {
+        result.map(|x| Box::into_raw(Box::new(WriteHandle(Box::new(NotSeekable(x))))))
+    }
+}
+
+impl ToForeign<Result<Box<dyn WriteSeek>, Box<dyn Error>>, *mut WriteHandle>
+    for WriteHandleMarshaler
+{
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(
+        result: Result<Box<dyn WriteSeek>, Box<dyn Error>>,
+    ) -> Result<*mut WriteHandle, Self::Error> {
+        result.map(|x| Box::into_raw(Box::new(WriteHandle(x))))
+    }
+}
+
+fn throw(exception: ErrCallback, err: impl std::fmt::Debug) {
+    if let Some(callback) = exception {
+        let err = format!("{:?}", err);
+        callback(err.as_bytes().as_ptr(), err.len());
+    }
+}
+
+/// Seeks `stream` from `whence`: 0 (start), 1 (current) or 2 (end), as for [`SeekFn`].
+fn seek_handle(stream: &mut dyn Seek, offset: i64, whence: i32) -> io::Result<u64> {
+    let pos = match whence {
+        0 if offset >= 0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid seek to {} from {}", offset, whence),
+            ))
+        }
+    };
+
+    stream.seek(pos)
+}
+
+/// Reads up to `len` bytes into `buf`, returning the number read, or -1 on error.
+///
+/// # Safety
+///
+/// `handle` must be a live [`ReadHandle`] and `buf` must be valid for writes of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cffi_read_handle_read(
+    handle: *mut ReadHandle,
+    buf: *mut u8,
+    len: usize,
+    exception: ErrCallback,
+) -> isize {
+    if handle.is_null() || (buf.is_null() && len > 0) {
+        throw(exception, null_ptr_error());
+        return -1;
+    }
+
+    if len == 0 {
+        return 0;
+    }
+
+    let buf = std::slice::from_raw_parts_mut(buf, len);
+    match (*handle).0.read(buf) {
+        Ok(v) => v as isize,
+        Err(e) => {
+            throw(exception, e);
+            -1
+        }
+    }
+}
+
+/// Seeks a [`ReadHandle`] by `offset` from `whence`: 0 (start), 1 (current) or 2 (end). Returns
+/// the new position, or -1 on error, including for handles to streams that are not seekable.
+///
+/// # Safety
+///
+/// `handle` must be a live [`ReadHandle`].
+#[no_mangle]
+pub unsafe extern "C" fn cffi_read_handle_seek(
+    handle: *mut ReadHandle,
+    offset: i64,
+    whence: i32,
+    exception: ErrCallback,
+) -> i64 {
+    if handle.is_null() {
+        throw(exception, null_ptr_error());
+        return -1;
+    }
+
+    match seek_handle(&mut (*handle).0, offset, whence) {
+        Ok(v) => v as i64,
+        Err(e) => {
+            throw(exception, e);
+            -1
+        }
+    }
+}
+
+/// Frees a [`ReadHandle`]. Null handles are ignored.
+///
+/// # Safety
+///
+/// `handle` must be a [`ReadHandle`] that has not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn cffi_read_handle_free(handle: *mut ReadHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Writes up to `len` bytes from `buf`, returning the number written, or -1 on error.
+///
+/// # Safety
+///
+/// `handle` must be a live [`WriteHandle`] and `buf` must be valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn cffi_write_handle_write(
+    handle: *mut WriteHandle,
+    buf: *const u8,
+    len: usize,
+    exception: ErrCallback,
+) -> isize {
+    if handle.is_null() || (buf.is_null() && len > 0) {
+        throw(exception, null_ptr_error());
+        return -1;
+    }
+
+    if len == 0 {
+        return 0;
+    }
+
+    let buf = std::slice::from_raw_parts(buf, len);
+    match (*handle).0.write(buf) {
+        Ok(v) => v as isize,
+        Err(e) => {
+            throw(exception, e);
+            -1
+        }
+    }
+}
+
+/// Flushes a [`WriteHandle`], returning 0 on success or -1 on error.
+///
+/// # Safety
+///
+/// `handle` must be a live [`WriteHandle`].
+#[no_mangle]
+pub unsafe extern "C" fn cffi_write_handle_flush(
+    handle: *mut WriteHandle,
+    exception: ErrCallback,
+) -> i32 {
+    if handle.is_null() {
+        throw(exception, null_ptr_error());
+        return -1;
+    }
+
+    match (*handle).0.flush() {
+        Ok(()) => 0,
+        Err(e) => {
+            throw(exception, e);
+            -1
+        }
+    }
+}
+
+/// Seeks a [`WriteHandle`] by `offset` from `whence`: 0 (start), 1 (current) or 2 (end). Returns
+/// the new position, or -1 on error, including for handles to streams that are not seekable.
+///
+/// # Safety
+///
+/// `handle` must be a live [`WriteHandle`].
+#[no_mangle]
+pub unsafe extern "C" fn cffi_write_handle_seek(
+    handle: *mut WriteHandle,
+    offset: i64,
+    whence: i32,
+    exception: ErrCallback,
+) -> i64 {
+    if handle.is_null() {
+        throw(exception, null_ptr_error());
+        return -1;
+    }
+
+    match seek_handle(&mut (*handle).0, offset, whence) {
+        Ok(v) => v as i64,
+        Err(e) => {
+            throw(exception, e);
+            -1
+        }
+    }
+}
+
+/// Flushes and frees a [`WriteHandle`]. Null handles are ignored. A failed flush is only logged,
+/// so call `cffi_write_handle_flush` first to handle the error.
+///
+/// # Safety
+///
+/// `handle` must be a [`WriteHandle`] that has not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn cffi_write_handle_free(handle: *mut WriteHandle) {
+    if handle.is_null() {
+        return;
+    }
+
+    let mut handle = Box::from_raw(handle);
+    if let Err(e) = handle.0.flush() {
+        log::error!("failed to flush write handle when freeing it: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    type Buffer = io::Cursor<Vec<u8>>;
+
+    unsafe extern "C" fn cursor_read(userdata: *mut c_void, buf: *mut u8, len: usize) -> isize {
+        let cursor = &mut *(userdata as *mut Buffer);
+        match cursor.read(std::slice::from_raw_parts_mut(buf, len)) {
+            Ok(v) => v as isize,
+            Err(_) => -1,
+        }
+    }
+
+    unsafe extern "C" fn cursor_write(userdata: *mut c_void, buf: *const u8, len: usize) -> isize {
+        let cursor = &mut *(userdata as *mut Buffer);
+        match cursor.write(std::slice::from_raw_parts(buf, len)) {
+            Ok(v) => v as isize,
+            Err(_) => -1,
+        }
+    }
+
+    unsafe extern "C" fn cursor_flush(_userdata: *mut c_void) -> i32 {
+        FLUSHES.fetch_add(1, Ordering::SeqCst);
+        0
+    }
+
+    unsafe extern "C" fn cursor_seek(userdata: *mut c_void, offset: i64, whence: i32) -> i64 {
+        let cursor = &mut *(userdata as *mut Buffer);
+        let pos = match whence {
+            0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            _ => SeekFrom::End(offset),
+        };
+        cursor.seek(pos).map(|x| x as i64).unwrap_or(-1)
+    }
+
+    unsafe extern "C" fn cursor_release(userdata: *mut c_void) {
+        drop(Box::from_raw(userdata as *mut Buffer));
+    }
+
+    static FLUSHES: AtomicUsize = AtomicUsize::new(0);
+    static RELEASES: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn counted_release(userdata: *mut c_void) {
+        RELEASES.fetch_add(1, Ordering::SeqCst);
+        cursor_release(userdata);
+    }
+
+    fn buffer(data: &[u8]) -> *mut c_void {
+        Box::into_raw(Box::new(io::Cursor::new(data.to_vec()))).cast()
+    }
+
+    fn vtable(data: &[u8]) -> ReadVtable {
+        ReadVtable {
+            userdata: buffer(data),
+            read: Some(cursor_read),
+            seek: Some(cursor_seek),
+            release: Some(cursor_release),
+        }
+    }
+
+    fn write_vtable() -> WriteVtable {
+        WriteVtable {
+            userdata: buffer(b""),
+            write: Some(cursor_write),
+            flush: Some(cursor_flush),
+            seek: Some(cursor_seek),
+            release: Some(cursor_release),
+        }
+    }
+
+    #[test]
+    fn foreign_reader() {
+        let mut reader: ForeignReader =
+            unsafe { ReaderMarshaler::from_foreign(vtable(b"hello world")) }.unwrap();
+
+        reader.seek(SeekFrom::Start(6)).unwrap();
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "world");
+    }
+
+    #[test]
+    fn seek_past_foreign_offset() {
+        let mut reader: ForeignReader =
+            unsafe { ReaderMarshaler::from_foreign(vtable(b"hello")) }.unwrap();
+
+        let err = reader.seek(SeekFrom::Start(u64::MAX)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(reader.stream_position().unwrap(), 0);
+    }
+
+    #[test]
+    fn missing_read_fn() {
+        let mut vtable = vtable(b"");
+        vtable.read = None;
+        vtable.release = Some(counted_release);
+
+        let released = RELEASES.load(Ordering::SeqCst);
+        let result: Result<Box<dyn Read>, _> = unsafe { ReaderMarshaler::from_foreign(vtable) };
+        assert!(result.is_err());
+        assert!(RELEASES.load(Ordering::SeqCst) > released);
+    }
+
+    #[test]
+    fn foreign_writer() {
+        let vtable = write_vtable();
+        let userdata = vtable.userdata;
+        let mut writer: ForeignWriter = unsafe { WriterMarshaler::from_foreign(vtable) }.unwrap();
+
+        writer.write_all(b"hello world").unwrap();
+        writer.seek(SeekFrom::Start(6)).unwrap();
+        writer.write_all(b"there").unwrap();
+
+        let flushes = FLUSHES.load(Ordering::SeqCst);
+        writer.flush().unwrap();
+        assert!(FLUSHES.load(Ordering::SeqCst) > flushes);
+
+        let written = unsafe { &*(userdata as *const Buffer) }.get_ref().clone();
+        assert_eq!(written, b"hello there");
+    }
+
+    #[test]
+    fn seekable_foreign_streams() {
+        let mut reader: Box<dyn ReadSeek> =
+            unsafe { ReaderMarshaler::from_foreign(vtable(b"hello world")) }.unwrap();
+        reader.seek(SeekFrom::End(-5)).unwrap();
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "world");
+
+        let vtable = write_vtable();
+        let userdata = vtable.userdata;
+        let mut writer: Box<dyn WriteSeek> =
+            unsafe { WriterMarshaler::from_foreign(vtable) }.unwrap();
+        writer.write_all(b"hello world").unwrap();
+        writer.seek(SeekFrom::Start(0)).unwrap();
+        writer.write_all(b"j").unwrap();
+
+        let written = unsafe { &*(userdata as *const Buffer) }.get_ref().clone();
+        assert_eq!(written, b"jello world");
+    }
+
+    #[test]
+    fn missing_write_fn() {
+        let mut vtable = write_vtable();
+        vtable.write = None;
+        vtable.release = Some(counted_release);
+
+        let released = RELEASES.load(Ordering::SeqCst);
+        let result: Result<Box<dyn Write>, _> = unsafe { WriterMarshaler::from_foreign(vtable) };
+        assert!(result.is_err());
+        assert!(RELEASES.load(Ordering::SeqCst) > released);
+    }
+
+    #[test]
+    fn read_handle() {
+        let reader: Box<dyn Read> = Box::new(io::Cursor::new(b"abc".to_vec()));
+        let handle = ReadHandleMarshaler::to_foreign(reader).unwrap();
+
+        let mut buf = [0u8; 8];
+        let n = unsafe { cffi_read_handle_read(handle, buf.as_mut_ptr(), buf.len(), None) };
+        assert_eq!(&buf[..n as usize], b"abc");
+        assert_eq!(unsafe { cffi_read_handle_seek(handle, 0, 0, None) }, -1);
+        unsafe { cffi_read_handle_free(handle) };
+    }
+
+    #[test]
+    fn seekable_read_handle() {
+        let reader: Box<dyn ReadSeek> = Box::new(io::Cursor::new(b"abcdef".to_vec()));
+        let handle = ReadHandleMarshaler::to_foreign(reader).unwrap();
+
+        assert_eq!(unsafe { cffi_read_handle_seek(handle, -2, 2, None) }, 4);
+        let mut buf = [0u8; 8];
+        let n = unsafe { cffi_read_handle_read(handle, buf.as_mut_ptr(), buf.len(), None) };
+        assert_eq!(&buf[..n as usize], b"ef");
+        unsafe { cffi_read_handle_free(handle) };
+    }
+
+    /// Records what has been flushed, so that unflushed writes can be told apart.
+    struct Recorder {
+        pending: Vec<u8>,
+        flushed: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.pending.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed.lock().unwrap().append(&mut self.pending);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_handle() {
+        let flushed = Arc::new(Mutex::new(vec![]));
+        let writer: Box<dyn Write> = Box::new(Recorder {
+            pending: vec![],
+            flushed: flushed.clone(),
+        });
+        let handle = WriteHandleMarshaler::to_foreign(writer).unwrap();
+
+        let n = unsafe { cffi_write_handle_write(handle, b"abc".as_ptr(), 3, None) };
+        assert_eq!(n, 3);
+        assert!(flushed.lock().unwrap().is_empty());
+
+        assert_eq!(unsafe { cffi_write_handle_flush(handle, None) }, 0);
+        assert_eq!(*flushed.lock().unwrap(), b"abc");
+
+        let n = unsafe { cffi_write_handle_write(handle, b"def".as_ptr(), 3, None) };
+        assert_eq!(n, 3);
+        assert_eq!(unsafe { cffi_write_handle_seek(handle, 0, 0, None) }, -1);
+
+        unsafe { cffi_write_handle_free(handle) };
+        assert_eq!(*flushed.lock().unwrap(), b"abcdef");
+    }
+
+    #[test]
+    fn seekable_write_handle() {
+        let writer: Box<dyn WriteSeek> = Box::new(io::Cursor::new(vec![]));
+        let handle = WriteHandleMarshaler::to_foreign(writer).unwrap();
+
+        unsafe { cffi_write_handle_write(handle, b"abc".as_ptr(), 3, None) };
+        assert_eq!(unsafe { cffi_write_handle_seek(handle, 1, 0, None) }, 1);
+        assert_eq!(unsafe { cffi_write_handle_seek(handle, 0, 3, None) }, -1);
+        unsafe { cffi_write_handle_free(handle) };
+    }
+}
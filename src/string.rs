@@ -1,7 +1,10 @@
 use std::convert::Infallible;
 use std::error::Error;
 
-use super::{vec::VecMarshaler, FromForeign, InputType, ReturnType, Slice, ToForeign};
+use super::{
+    vec::VecMarshaler, DropForeign, FromForeign, InputType, OutBuffer, ReturnType, Slice,
+    ToForeign, ToForeignBuffer,
+};
 
 pub struct StringMarshaler;
 
@@ -34,7 +37,7 @@ impl ToForeign<Result<String, Box<dyn Error>>, Slice<u8>> for StringMarshaler {
 
     #[inline(always)]
     fn to_foreign(result: Result<String, Box<dyn Error>>) -> Result<Slice<u8>, Self::Error> {
-        result.and_then(|v| Ok(StringMarshaler::to_foreign(v).unwrap()))
+        result.map(|v| StringMarshaler::to_foreign(v).unwrap())
     }
 }
 
@@ -50,7 +53,30 @@ impl ToForeign<Option<String>, Slice<u8>> for StringMarshaler {
     }
 }
 
-impl<'a> FromForeign<Slice<u8>, String> for StringMarshaler {
+impl ToForeignBuffer<String> for StringMarshaler {
+    type Element = u8;
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign_buffer(string: String, buffer: &mut OutBuffer<u8>) -> Result<(), Self::Error> {
+        buffer.write(string.as_bytes())
+    }
+}
+
+impl ToForeignBuffer<Result<String, Box<dyn Error>>> for StringMarshaler {
+    type Element = u8;
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign_buffer(
+        result: Result<String, Box<dyn Error>>,
+        buffer: &mut OutBuffer<u8>,
+    ) -> Result<(), Self::Error> {
+        result.and_then(|v| StringMarshaler::to_foreign_buffer(v, buffer))
+    }
+}
+
+impl FromForeign<Slice<u8>, String> for StringMarshaler {
     type Error = Box<dyn Error>;
 
     #[inline(always)]
@@ -60,7 +86,19 @@ impl<'a> FromForeign<Slice<u8>, String> for StringMarshaler {
     }
 }
 
+impl DropForeign<Slice<u8>> for StringMarshaler {
+    #[inline(always)]
+    unsafe fn drop_foreign(slice: Slice<u8>) {
+        VecMarshaler::free(slice)
+    }
+}
+
+/// Frees a string previously returned by a [`StringMarshaler`].
+///
+/// # Safety
+///
+/// `slice` must have been produced by `StringMarshaler::to_foreign` and not already freed.
 #[no_mangle]
 pub unsafe extern "C" fn cffi_string_free(slice: Slice<u8>) {
-    crate::vec::cffi_vec_free(slice.cast());
+    VecMarshaler::free(slice);
 }
@@ -0,0 +1,213 @@
+use std::error::Error;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use super::{DropForeign, FromForeign, InputType, ReturnType, ToForeign};
+
+/// A heap-allocated object guarded by a `RwLock`, as handed out by [`SyncHandleMarshaler`].
+///
+/// Consuming the object leaves the handle empty rather than freeing it, so that calls racing
+/// with the consuming one fail instead of using freed memory. The handle itself is freed with
+/// [`DropForeign`].
+pub struct SyncHandle<T>(RwLock<Option<T>>);
+
+/// A read lock on the object in a [`SyncHandle`].
+pub struct SyncRef<'a, T>(RwLockReadGuard<'a, Option<T>>);
+
+impl<T> Deref for SyncRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Only constructed for handles that have not been consumed.
+        self.0.as_ref().unwrap()
+    }
+}
+
+/// A write lock on the object in a [`SyncHandle`].
+pub struct SyncMut<'a, T>(RwLockWriteGuard<'a, Option<T>>);
+
+impl<T> Deref for SyncMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for SyncMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.0.as_mut().unwrap()
+    }
+}
+
+/// Like [`BoxMarshaler`](crate::BoxMarshaler), but the object is wrapped in a [`SyncHandle`] so
+/// foreign code may call into it from several threads at once.
+///
+/// This is the marshaler used by `#[marshal(sync)] impl`, whose `&self` methods take a read lock
+/// and `&mut self` methods a write lock.
+///
+/// ### To the foreign interface:
+///
+///   - `T` → `*const SyncHandle<T>`
+///
+/// ### From the foreign interface:
+///
+///   - `*const SyncHandle<T>` → `T` (owned, empties the handle)
+///   - `*const SyncHandle<T>` → `SyncRef<T>`
+///   - `*const SyncHandle<T>` → `SyncMut<T>`
+pub struct SyncHandleMarshaler<T>(PhantomData<T>);
+
+fn poisoned<T, G>(_: PoisonError<G>) -> Box<dyn Error> {
+    format!(
+        "{} handle was poisoned by a panic",
+        std::any::type_name::<T>()
+    )
+    .into()
+}
+
+fn consumed<T>() -> Box<dyn Error> {
+    format!("{} handle was already consumed", std::any::type_name::<T>()).into()
+}
+
+impl<T> InputType for SyncHandleMarshaler<T> {
+    type Foreign = *const SyncHandle<T>;
+    type ForeignTraitObject = ();
+}
+
+impl<T> ReturnType for SyncHandleMarshaler<T> {
+    type Foreign = *const SyncHandle<T>;
+    type ForeignTraitObject = ();
+
+    fn foreign_default() -> Self::Foreign {
+        std::ptr::null()
+    }
+}
+
+impl<T: Send + Sync> ToForeign<T, *const SyncHandle<T>> for SyncHandleMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(local: T) -> Result<*const SyncHandle<T>, Self::Error> {
+        let handle = Box::new(SyncHandle(RwLock::new(Some(local))));
+        Ok(crate::tag::box_into_raw(handle) as *const _)
+    }
+}
+
+impl<T: Send + Sync> ToForeign<Result<T, Box<dyn Error>>, *const SyncHandle<T>>
+    for SyncHandleMarshaler<T>
+{
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(local: Result<T, Box<dyn Error>>) -> Result<*const SyncHandle<T>, Self::Error> {
+        local.and_then(SyncHandleMarshaler::to_foreign)
+    }
+}
+
+impl<T> FromForeign<*const SyncHandle<T>, T> for SyncHandleMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(foreign: *const SyncHandle<T>) -> Result<T, Self::Error> {
+        let handle = crate::tag::box_ref(foreign)?;
+        let mut guard = handle.0.write().map_err(poisoned::<T, _>)?;
+        guard.take().ok_or_else(consumed::<T>)
+    }
+}
+
+impl<'a, T> FromForeign<*const SyncHandle<T>, SyncRef<'a, T>> for SyncHandleMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(foreign: *const SyncHandle<T>) -> Result<SyncRef<'a, T>, Self::Error> {
+        let handle = crate::tag::box_ref(foreign)?;
+        let guard = handle.0.read().map_err(poisoned::<T, _>)?;
+        match guard.is_some() {
+            true => Ok(SyncRef(guard)),
+            false => Err(consumed::<T>()),
+        }
+    }
+}
+
+impl<'a, T> FromForeign<*const SyncHandle<T>, SyncMut<'a, T>> for SyncHandleMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(foreign: *const SyncHandle<T>) -> Result<SyncMut<'a, T>, Self::Error> {
+        let handle = crate::tag::box_ref(foreign)?;
+        let guard = handle.0.write().map_err(poisoned::<T, _>)?;
+        match guard.is_some() {
+            true => Ok(SyncMut(guard)),
+            false => Err(consumed::<T>()),
+        }
+    }
+}
+
+impl<T> DropForeign<*const SyncHandle<T>> for SyncHandleMarshaler<T> {
+    #[inline(always)]
+    unsafe fn drop_foreign(foreign: *const SyncHandle<T>) {
+        if foreign.is_null() {
+            return;
+        }
+
+        if let Err(e) = crate::tag::box_from_raw(foreign) {
+            log::error!("SyncHandleMarshaler::drop_foreign: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_writes() {
+        let ptr = SyncHandleMarshaler::<u32>::to_foreign(0u32).unwrap() as usize;
+
+        let threads = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let mut guard: SyncMut<u32> = unsafe {
+                            SyncHandleMarshaler::from_foreign(ptr as *const SyncHandle<u32>)
+                        }
+                        .unwrap();
+                        *guard += 1;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let guard: SyncRef<u32> =
+            unsafe { SyncHandleMarshaler::from_foreign(ptr as *const SyncHandle<u32>) }.unwrap();
+        assert_eq!(*guard, 4000);
+        drop(guard);
+
+        let value: u32 =
+            unsafe { SyncHandleMarshaler::from_foreign(ptr as *const SyncHandle<u32>) }.unwrap();
+        assert_eq!(value, 4000);
+        unsafe { SyncHandleMarshaler::drop_foreign(ptr as *const SyncHandle<u32>) };
+    }
+
+    #[test]
+    fn consumed() {
+        let ptr = SyncHandleMarshaler::<String>::to_foreign("one".to_string()).unwrap();
+
+        let value: String = unsafe { SyncHandleMarshaler::from_foreign(ptr) }.unwrap();
+        assert_eq!(value, "one");
+
+        let result: Result<String, _> = unsafe { SyncHandleMarshaler::from_foreign(ptr) };
+        assert!(result.is_err());
+        let result: Result<SyncRef<String>, _> = unsafe { SyncHandleMarshaler::from_foreign(ptr) };
+        assert!(result.is_err());
+        let result: Result<SyncMut<String>, _> = unsafe { SyncHandleMarshaler::from_foreign(ptr) };
+        assert!(result.is_err());
+
+        unsafe { SyncHandleMarshaler::drop_foreign(ptr) };
+    }
+}
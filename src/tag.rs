@@ -0,0 +1,397 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use super::null_ptr_error;
+
+/// Returned when a tagged pointer does not refer to a live value of the expected type.
+///
+/// Only produced with the `type-tags` feature enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeTagError {
+    /// The pointer was not produced by cffi, or the memory it points to has been overwritten.
+    Untagged { expected: &'static str },
+    /// The pointer refers to a value of another type.
+    WrongType {
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for TypeTagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeTagError::Untagged { expected } => write!(
+                f,
+                "expected a pointer to {}, but it was not allocated by cffi",
+                expected
+            ),
+            TypeTagError::WrongType { expected, found } => {
+                write!(f, "expected a pointer to {}, found {}", expected, found)
+            }
+        }
+    }
+}
+
+impl Error for TypeTagError {}
+
+/// The header placed before every value handed out by `BoxMarshaler` and `ArcMarshaler` and
+/// before every trait object handle when the `type-tags` feature is enabled.
+///
+/// Freed values are not detected, as their tags can no longer be read; the `track-allocations`
+/// feature catches those.
+///
+/// `TypeId` would require `T: 'static`, which the marshalers do not, so the type is identified
+/// by a hash of its name instead.
+#[cfg(feature = "type-tags")]
+#[repr(C)]
+pub(crate) struct TypeTag {
+    magic: u64,
+    type_hash: u64,
+    type_name: &'static str,
+}
+
+#[cfg(feature = "type-tags")]
+impl TypeTag {
+    // "cffitag!"
+    const MAGIC: u64 = 0x6366_6669_7461_6721;
+
+    fn hash(name: &str) -> u64 {
+        // FNV-1a
+        name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    pub(crate) fn of<T: ?Sized>() -> TypeTag {
+        let type_name = std::any::type_name::<T>();
+        TypeTag {
+            magic: TypeTag::MAGIC,
+            type_hash: TypeTag::hash(type_name),
+            type_name,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `tag` must point to readable memory at least the size of a `TypeTag`.
+    pub(crate) unsafe fn check<T: ?Sized>(tag: *const TypeTag) -> Result<(), TypeTagError> {
+        let expected = std::any::type_name::<T>();
+        let magic = std::ptr::addr_of!((*tag).magic).read();
+
+        if magic != TypeTag::MAGIC {
+            return Err(TypeTagError::Untagged { expected });
+        }
+
+        if (*tag).type_hash != TypeTag::hash(expected) {
+            return Err(TypeTagError::WrongType {
+                expected,
+                found: (*tag).type_name,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "type-tags"))]
+mod raw {
+    use std::sync::Arc;
+
+    #[inline(always)]
+    pub(super) fn into_raw<T>(value: Box<T>) -> *mut T {
+        Box::into_raw(value)
+    }
+
+    #[inline(always)]
+    pub(super) unsafe fn from_raw<T>(ptr: *const T) -> Result<Box<T>, super::TypeTagError> {
+        Ok(Box::from_raw(ptr as *mut T))
+    }
+
+    #[inline(always)]
+    pub(super) unsafe fn as_ref<'a, T>(ptr: *const T) -> Result<&'a T, super::TypeTagError> {
+        Ok(&*ptr)
+    }
+
+    #[inline(always)]
+    pub(super) unsafe fn as_mut<'a, T>(ptr: *mut T) -> Result<&'a mut T, super::TypeTagError> {
+        Ok(&mut *ptr)
+    }
+
+    #[inline(always)]
+    pub(super) fn arc_into_raw<T>(value: Arc<T>) -> *const T {
+        Arc::into_raw(value)
+    }
+
+    #[inline(always)]
+    pub(super) unsafe fn arc_retain<T>(ptr: *const T) -> Result<(), super::TypeTagError> {
+        Arc::increment_strong_count(ptr);
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub(super) unsafe fn arc_take<T>(ptr: *const T) -> Result<Arc<T>, super::TypeTagError> {
+        Ok(Arc::from_raw(ptr))
+    }
+
+    #[inline(always)]
+    pub(super) unsafe fn arc_as_ref<'a, T>(ptr: *const T) -> Result<&'a T, super::TypeTagError> {
+        Ok(&*ptr)
+    }
+
+    #[inline(always)]
+    pub(super) unsafe fn arc_clone<T>(ptr: *const T) -> Result<Arc<T>, super::TypeTagError> {
+        Arc::increment_strong_count(ptr);
+        Ok(Arc::from_raw(ptr))
+    }
+}
+
+#[cfg(feature = "type-tags")]
+mod raw {
+    use std::sync::Arc;
+
+    use super::{TypeTag, TypeTagError};
+
+    #[repr(C)]
+    struct Tagged<T> {
+        tag: TypeTag,
+        value: T,
+    }
+
+    unsafe fn tagged<T>(ptr: *const T) -> Result<*mut Tagged<T>, TypeTagError> {
+        let tagged = ptr as *mut Tagged<T>;
+        TypeTag::check::<T>(tagged.cast())?;
+        Ok(tagged)
+    }
+
+    #[allow(clippy::boxed_local)]
+    pub(super) fn into_raw<T>(value: Box<T>) -> *mut T {
+        let tagged = Box::new(Tagged {
+            tag: TypeTag::of::<T>(),
+            value: *value,
+        });
+        Box::into_raw(tagged).cast()
+    }
+
+    pub(super) unsafe fn from_raw<T>(ptr: *const T) -> Result<Box<T>, TypeTagError> {
+        let tagged = Box::from_raw(tagged(ptr)?);
+        Ok(Box::new(tagged.value))
+    }
+
+    pub(super) unsafe fn as_ref<'a, T>(ptr: *const T) -> Result<&'a T, TypeTagError> {
+        Ok(&(*tagged(ptr)?).value)
+    }
+
+    pub(super) unsafe fn as_mut<'a, T>(ptr: *mut T) -> Result<&'a mut T, TypeTagError> {
+        Ok(&mut (*tagged(ptr)?).value)
+    }
+
+    // `Arc` handles point to a reference counted tag and a clone of the `Arc`, so that the
+    // value's own allocation is shared with Rust unchanged.
+
+    unsafe fn arc_tagged<T>(ptr: *const T) -> Result<*const Tagged<Arc<T>>, TypeTagError> {
+        let tagged = ptr as *const Tagged<Arc<T>>;
+        TypeTag::check::<Arc<T>>(tagged.cast())?;
+        Ok(tagged)
+    }
+
+    pub(super) fn arc_into_raw<T>(value: Arc<T>) -> *const T {
+        let tagged = Arc::new(Tagged {
+            tag: TypeTag::of::<Arc<T>>(),
+            value,
+        });
+        Arc::into_raw(tagged).cast()
+    }
+
+    pub(super) unsafe fn arc_retain<T>(ptr: *const T) -> Result<(), TypeTagError> {
+        Arc::increment_strong_count(arc_tagged(ptr)?);
+        Ok(())
+    }
+
+    pub(super) unsafe fn arc_take<T>(ptr: *const T) -> Result<Arc<T>, TypeTagError> {
+        let tagged = Arc::from_raw(arc_tagged(ptr)?);
+        Ok(Arc::clone(&tagged.value))
+    }
+
+    pub(super) unsafe fn arc_as_ref<'a, T>(ptr: *const T) -> Result<&'a T, TypeTagError> {
+        Ok(&*(*arc_tagged(ptr)?).value)
+    }
+
+    pub(super) unsafe fn arc_clone<T>(ptr: *const T) -> Result<Arc<T>, TypeTagError> {
+        Ok(Arc::clone(&(*arc_tagged(ptr)?).value))
+    }
+}
+
+/// Whether boxed `T`s are tracked by address. Boxes of zero-sized values are not allocated and
+/// all share one dangling address, so they can only be told apart behind a [`TypeTag`].
+fn is_tracked<T>() -> bool {
+    cfg!(feature = "type-tags") || std::mem::size_of::<T>() != 0
+}
+
+/// Moves `value` to the heap for foreign code to hold, behind a [`TypeTag`] when the `type-tags`
+/// feature is enabled.
+pub(crate) fn box_into_raw<T>(value: Box<T>) -> *mut T {
+    let ptr = raw::into_raw(value);
+    if is_tracked::<T>() {
+        crate::track::produced(ptr);
+    }
+    ptr
+}
+
+/// Takes back ownership of a pointer from [`box_into_raw`], validating it if enabled.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been produced by [`box_into_raw`].
+pub(crate) unsafe fn box_from_raw<T>(ptr: *const T) -> Result<Box<T>, Box<dyn Error>> {
+    if ptr.is_null() {
+        return Err(null_ptr_error());
+    }
+
+    if is_tracked::<T>() {
+        crate::track::check(ptr)?;
+    }
+    let value = raw::from_raw(ptr)?;
+    if is_tracked::<T>() {
+        crate::track::consumed(ptr);
+    }
+    Ok(value)
+}
+
+/// Borrows the value behind a pointer from [`box_into_raw`], validating it if enabled.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been produced by [`box_into_raw`], and outlive `'a`.
+pub(crate) unsafe fn box_ref<'a, T>(ptr: *const T) -> Result<&'a T, Box<dyn Error>> {
+    if ptr.is_null() {
+        return Err(null_ptr_error());
+    }
+
+    if is_tracked::<T>() {
+        crate::track::check(ptr)?;
+    }
+    Ok(raw::as_ref(ptr)?)
+}
+
+/// Mutably borrows the value behind a pointer from [`box_into_raw`], validating it if enabled.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been produced by [`box_into_raw`], outlive `'a`, and not be
+/// borrowed elsewhere for `'a`.
+pub(crate) unsafe fn box_mut<'a, T>(ptr: *mut T) -> Result<&'a mut T, Box<dyn Error>> {
+    if ptr.is_null() {
+        return Err(null_ptr_error());
+    }
+
+    if is_tracked::<T>() {
+        crate::track::check(ptr)?;
+    }
+    Ok(raw::as_mut(ptr)?)
+}
+
+/// Hands one reference to `value` to foreign code, behind a [`TypeTag`] when the `type-tags`
+/// feature is enabled.
+pub(crate) fn arc_into_raw<T>(value: Arc<T>) -> *const T {
+    let ptr = raw::arc_into_raw(value);
+    crate::track::retained(ptr);
+    ptr
+}
+
+/// Adds a foreign reference to a pointer from [`arc_into_raw`], validating it if enabled.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been produced by [`arc_into_raw`] and not yet released.
+pub(crate) unsafe fn arc_retain<T>(ptr: *const T) -> Result<(), Box<dyn Error>> {
+    if ptr.is_null() {
+        return Err(null_ptr_error());
+    }
+
+    crate::track::check(ptr)?;
+    raw::arc_retain(ptr)?;
+    crate::track::retained(ptr);
+    Ok(())
+}
+
+/// Takes back one foreign reference to a pointer from [`arc_into_raw`], validating it if
+/// enabled.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been produced by [`arc_into_raw`], and each reference must only
+/// be taken back once.
+pub(crate) unsafe fn arc_from_raw<T>(ptr: *const T) -> Result<Arc<T>, Box<dyn Error>> {
+    if ptr.is_null() {
+        return Err(null_ptr_error());
+    }
+
+    crate::track::check(ptr)?;
+    let arc = raw::arc_take(ptr)?;
+    crate::track::consumed(ptr);
+    Ok(arc)
+}
+
+/// Borrows the value behind a pointer from [`arc_into_raw`], validating it if enabled.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been produced by [`arc_into_raw`], and not be released for `'a`.
+pub(crate) unsafe fn arc_ref<'a, T>(ptr: *const T) -> Result<&'a T, Box<dyn Error>> {
+    if ptr.is_null() {
+        return Err(null_ptr_error());
+    }
+
+    crate::track::check(ptr)?;
+    Ok(raw::arc_as_ref(ptr)?)
+}
+
+/// Clones the `Arc` behind a pointer from [`arc_into_raw`], validating it if enabled.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been produced by [`arc_into_raw`] and not yet released.
+pub(crate) unsafe fn arc_clone<T>(ptr: *const T) -> Result<Arc<T>, Box<dyn Error>> {
+    if ptr.is_null() {
+        return Err(null_ptr_error());
+    }
+
+    crate::track::check(ptr)?;
+    Ok(raw::arc_clone(ptr)?)
+}
+
+#[cfg(all(test, feature = "type-tags"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrong_type() {
+        let ptr = box_into_raw(Box::new(1u32));
+        let err = unsafe { box_ref::<u64>(ptr.cast()) }.unwrap_err();
+        assert_eq!(err.to_string(), "expected a pointer to u64, found u32");
+        drop(unsafe { box_from_raw(ptr) }.unwrap());
+    }
+
+    #[test]
+    fn untagged() {
+        let value = [0u64; 4];
+        let err = unsafe { box_ref::<u32>(value.as_ptr().cast()) }.unwrap_err();
+        assert!(err.to_string().contains("not allocated by cffi"));
+    }
+
+    #[test]
+    fn arc_handles() {
+        let ptr = arc_into_raw(Arc::new(1u32));
+        assert_eq!(*unsafe { arc_ref::<u32>(ptr) }.unwrap(), 1);
+
+        let err = unsafe { box_ref::<u32>(ptr) }.unwrap_err();
+        assert!(err.to_string().contains("found alloc::sync::Arc<u32>"));
+        let err = unsafe { arc_ref::<u64>(ptr.cast()) }.unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("expected a pointer to alloc::sync::Arc<u64>"));
+
+        drop(unsafe { arc_from_raw(ptr) }.unwrap());
+    }
+}
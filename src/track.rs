@@ -0,0 +1,259 @@
+use std::error::Error;
+use std::fmt;
+
+/// Returned when a pointer handed back to cffi has already been freed or consumed.
+///
+/// Only produced with the `track-allocations` feature enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllocationError {
+    pub address: usize,
+    pub type_name: &'static str,
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#x} ({}) has already been freed or consumed",
+            self.address, self.type_name
+        )
+    }
+}
+
+impl Error for AllocationError {}
+
+#[cfg(feature = "track-allocations")]
+mod imp {
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    use super::AllocationError;
+
+    /// How many freed addresses are remembered. Only frees of these are reported as double
+    /// frees, so that the tracker does not grow without bound in a long-running process.
+    const FREED_LIMIT: usize = 4096;
+
+    /// Every pointer handed to foreign code and not yet returned (`live`), with the number of
+    /// references foreign code holds to it, and the last `FREED_LIMIT` pointers that have been
+    /// returned since their address was last handed out (`freed`), numbered in the order they
+    /// were freed. Pointers in neither are not checked.
+    struct Tracker {
+        live: BTreeMap<usize, (&'static str, usize)>,
+        freed: BTreeMap<usize, (&'static str, u64)>,
+        freed_order: VecDeque<(usize, u64)>,
+        frees: u64,
+    }
+
+    impl Tracker {
+        fn free(&mut self, address: usize, type_name: &'static str) {
+            self.frees += 1;
+            self.freed.insert(address, (type_name, self.frees));
+            self.freed_order.push_back((address, self.frees));
+
+            while self.freed_order.len() > FREED_LIMIT {
+                let (address, n) = self.freed_order.pop_front().unwrap();
+                // The address may since have been handed out and freed again.
+                if self
+                    .freed
+                    .get(&address)
+                    .is_some_and(|&(_, freed)| freed == n)
+                {
+                    self.freed.remove(&address);
+                }
+            }
+        }
+    }
+
+    static TRACKER: Mutex<Tracker> = Mutex::new(Tracker {
+        live: BTreeMap::new(),
+        freed: BTreeMap::new(),
+        freed_order: VecDeque::new(),
+        frees: 0,
+    });
+
+    fn tracker() -> MutexGuard<'static, Tracker> {
+        TRACKER.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records a new allocation at `ptr` handed to foreign code.
+    pub(crate) fn produced<T: ?Sized>(ptr: *const T) {
+        let address = ptr.cast::<()>() as usize;
+        let type_name = std::any::type_name::<T>();
+        log::debug!("produced {:#x} ({})", address, type_name);
+
+        let mut tracker = tracker();
+        tracker.freed.remove(&address);
+        tracker.live.insert(address, (type_name, 1));
+    }
+
+    /// Records another reference to `ptr` handed to foreign code, which may already hold others,
+    /// as it does for an `Arc` handed over twice.
+    pub(crate) fn retained<T: ?Sized>(ptr: *const T) {
+        let address = ptr.cast::<()>() as usize;
+        let type_name = std::any::type_name::<T>();
+        log::debug!("retained {:#x} ({})", address, type_name);
+
+        let mut tracker = tracker();
+        tracker.freed.remove(&address);
+        tracker.live.entry(address).or_insert((type_name, 0)).1 += 1;
+    }
+
+    pub(crate) fn check<T: ?Sized>(ptr: *const T) -> Result<(), AllocationError> {
+        let address = ptr.cast::<()>() as usize;
+
+        match tracker().freed.get(&address) {
+            Some(&(type_name, _)) => {
+                let err = AllocationError { address, type_name };
+                log::error!("{}", err);
+                Err(err)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Records that foreign code gave up one of its references to `ptr`, which is freed once
+    /// none are left.
+    pub(crate) fn consumed<T: ?Sized>(ptr: *const T) {
+        let address = ptr.cast::<()>() as usize;
+        let mut tracker = tracker();
+
+        let type_name = match tracker.live.get_mut(&address) {
+            Some((_, refs)) if *refs > 1 => {
+                *refs -= 1;
+                return;
+            }
+            Some(&mut (type_name, _)) => type_name,
+            None => return,
+        };
+
+        log::debug!(
+            "consumed {:#x} ({}); it must not be used again",
+            address,
+            type_name
+        );
+        tracker.live.remove(&address);
+        tracker.free(address, type_name);
+    }
+
+    /// A pointer handed to foreign code that has not yet been freed or consumed.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct LiveAllocation {
+        pub address: usize,
+        pub type_name: &'static str,
+    }
+
+    /// Lists every pointer handed to foreign code that has not yet been freed or consumed.
+    pub fn live_allocations() -> Vec<LiveAllocation> {
+        tracker()
+            .live
+            .iter()
+            .map(|(&address, &(type_name, _))| LiveAllocation { address, type_name })
+            .collect()
+    }
+
+    /// Logs a warning for every live allocation, returning how many there are.
+    #[no_mangle]
+    pub extern "C" fn cffi_debug_dump_live_allocations() -> usize {
+        let live = live_allocations();
+
+        for LiveAllocation { address, type_name } in &live {
+            log::warn!("live allocation {:#x} ({})", address, type_name);
+        }
+
+        live.len()
+    }
+}
+
+#[cfg(not(feature = "track-allocations"))]
+mod imp {
+    use super::AllocationError;
+
+    #[inline(always)]
+    pub(crate) fn produced<T: ?Sized>(_ptr: *const T) {}
+
+    #[inline(always)]
+    pub(crate) fn retained<T: ?Sized>(_ptr: *const T) {}
+
+    #[inline(always)]
+    pub(crate) fn check<T: ?Sized>(_ptr: *const T) -> Result<(), AllocationError> {
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub(crate) fn consumed<T: ?Sized>(_ptr: *const T) {}
+}
+
+#[cfg(feature = "track-allocations")]
+pub use imp::{cffi_debug_dump_live_allocations, live_allocations, LiveAllocation};
+pub(crate) use imp::{check, consumed, produced, retained};
+
+#[cfg(all(test, feature = "track-allocations"))]
+mod tests {
+    use super::*;
+    use crate::{BoxMarshaler, BoxRefMarshaler, FromForeign, StringMarshaler, ToForeign};
+
+    #[test]
+    fn double_free() {
+        let ptr = BoxMarshaler::<u32>::to_foreign(1u32).unwrap();
+        assert!(live_allocations().iter().any(|x| x.address == ptr as usize));
+
+        let value: Box<u32> = unsafe { BoxMarshaler::from_foreign(ptr) }.unwrap();
+        assert_eq!(*value, 1);
+        drop(value);
+
+        let err = unsafe { <BoxMarshaler<u32> as FromForeign<_, Box<u32>>>::from_foreign(ptr) }
+            .unwrap_err();
+        assert!(err.to_string().contains("already been freed"), "{}", err);
+    }
+
+    #[test]
+    fn slices() {
+        let slice = StringMarshaler::to_foreign("tracked".to_string()).unwrap();
+        let address = slice.data as usize;
+        assert!(live_allocations().iter().any(|x| x.address == address));
+
+        let value: String = unsafe { StringMarshaler::from_foreign(slice) }.unwrap();
+        assert_eq!(value, "tracked");
+        assert!(!live_allocations().iter().any(|x| x.address == address));
+    }
+
+    #[test]
+    fn zero_sized() {
+        struct Unit;
+
+        let first = BoxMarshaler::<Unit>::to_foreign(Unit).unwrap();
+        let second = BoxMarshaler::<Unit>::to_foreign(Unit).unwrap();
+
+        let value: Box<Unit> = unsafe { BoxMarshaler::from_foreign(first) }.unwrap();
+        drop(value);
+
+        let value: Result<&Unit, _> =
+            unsafe { BoxRefMarshaler::<Unit>::from_foreign(second as *mut Unit) };
+        assert!(value.is_ok());
+        let value: Box<Unit> = unsafe { BoxMarshaler::from_foreign(second) }.unwrap();
+        drop(value);
+    }
+
+    #[test]
+    fn arc_references() {
+        use crate::tag::{arc_from_raw, arc_into_raw, arc_retain};
+        use std::sync::Arc;
+
+        let is_live =
+            |ptr: *const u32| live_allocations().iter().any(|x| x.address == ptr as usize);
+
+        // Rust keeps its own reference throughout, which must not count as a foreign one.
+        let arc = Arc::new(1u32);
+        let ptr = arc_into_raw(Arc::clone(&arc));
+        unsafe { arc_retain(ptr) }.unwrap();
+
+        drop(unsafe { arc_from_raw(ptr) }.unwrap());
+        assert!(is_live(ptr));
+        drop(unsafe { arc_from_raw(ptr) }.unwrap());
+        assert!(!is_live(ptr));
+
+        let err = unsafe { arc_from_raw(ptr) }.unwrap_err();
+        assert!(err.to_string().contains("already been freed"), "{}", err);
+        assert_eq!(Arc::strong_count(&arc), 1);
+    }
+}
@@ -0,0 +1,189 @@
+use std::error::Error;
+use std::marker::PhantomData;
+
+use super::{DropForeign, FromForeign, InputType, ReturnType, ToForeign};
+
+/// Marshals a tuple element-wise, converting each element with the matching marshaler of the
+/// tuple of marshalers `M`, into a `#[repr(C)]` struct of the elements' foreign values:
+///
+///   - `TupleMarshaler<(StringMarshaler, CopyMarshaler<u32>)>`: `(String, u32)` →
+///     `Tuple2<Slice<u8>, u32>`
+///
+/// `#[marshal((M1, M2))]` is shorthand for `#[marshal(TupleMarshaler<(M1, M2)>)]`, and tuples of
+/// types with default marshalers or passed through are marshaled without an attribute. Tuples of
+/// two to six elements are supported.
+///
+/// ## Freeing
+///
+/// A returned tuple is freed with its [`DropForeign`] implementation, which frees each element
+/// with its own marshaler. `#[marshal]` exports this as `<function>_free`.
+pub struct TupleMarshaler<M>(PhantomData<M>);
+
+/// Converts each element in turn, dropping the foreign values converted so far if one fails.
+macro_rules! to_foreign_each {
+    (($($done:ident: $done_m:ident),*)) => {};
+    (($($done:ident: $done_m:ident),*) $v:ident: $m:ident, $($rest:tt)*) => {
+        let $v = match $m::to_foreign($v) {
+            Ok(v) => v,
+            Err(e) => {
+                $(unsafe { $done_m::drop_foreign($done) };)*
+                return Err(e.into());
+            }
+        };
+        to_foreign_each!(($($done: $done_m,)* $v: $m) $($rest)*);
+    };
+}
+
+/// Converts each element in turn, dropping the foreign values not yet converted if one fails.
+macro_rules! from_foreign_each {
+    () => {};
+    ($v:ident: $m:ident, $($rest:ident: $rest_m:ident,)*) => {
+        let $v = match $m::from_foreign($v) {
+            Ok(v) => v,
+            Err(e) => {
+                // The failed element has been consumed; release the ones not yet converted.
+                $($rest_m::drop_foreign($rest);)*
+                return Err(e.into());
+            }
+        };
+        from_foreign_each!($($rest: $rest_m,)*);
+    };
+}
+
+macro_rules! tuple_marshaler {
+    ($($tuple:ident($($v:ident: $m:ident $l:ident $f:ident),+);)*) => {$(
+        /// The foreign representation of a tuple, as produced by [`TupleMarshaler`].
+        #[repr(C)]
+        pub struct $tuple<$($f),+>($(pub $f),+);
+
+        impl<$($m: InputType),+> InputType for TupleMarshaler<($($m,)+)> {
+            type Foreign = $tuple<$($m::Foreign),+>;
+            type ForeignTraitObject = ();
+        }
+
+        impl<$($m: ReturnType),+> ReturnType for TupleMarshaler<($($m,)+)> {
+            type Foreign = $tuple<$($m::Foreign),+>;
+            type ForeignTraitObject = ();
+
+            #[inline(always)]
+            fn foreign_default() -> Self::Foreign {
+                $tuple($($m::foreign_default()),+)
+            }
+        }
+
+        impl<$($m, $l, $f),+> ToForeign<($($l,)+), $tuple<$($f),+>> for TupleMarshaler<($($m,)+)>
+        where
+            $($m: ToForeign<$l, $f> + DropForeign<$f>, $m::Error: Into<Box<dyn Error>>,)+
+        {
+            type Error = Box<dyn Error>;
+
+            fn to_foreign(($($v,)+): ($($l,)+)) -> Result<$tuple<$($f),+>, Self::Error> {
+                to_foreign_each!(() $($v: $m,)+);
+                Ok($tuple($($v),+))
+            }
+        }
+
+        impl<$($m, $l, $f),+> ToForeign<Result<($($l,)+), Box<dyn Error>>, $tuple<$($f),+>>
+            for TupleMarshaler<($($m,)+)>
+        where
+            $($m: ToForeign<$l, $f> + DropForeign<$f>, $m::Error: Into<Box<dyn Error>>,)+
+        {
+            type Error = Box<dyn Error>;
+
+            fn to_foreign(
+                result: Result<($($l,)+), Box<dyn Error>>,
+            ) -> Result<$tuple<$($f),+>, Self::Error> {
+                result.and_then(TupleMarshaler::<($($m,)+)>::to_foreign)
+            }
+        }
+
+        impl<$($m, $l, $f),+> FromForeign<$tuple<$($f),+>, ($($l,)+)> for TupleMarshaler<($($m,)+)>
+        where
+            $($m: FromForeign<$f, $l> + DropForeign<$f>, $m::Error: Into<Box<dyn Error>>,)+
+        {
+            type Error = Box<dyn Error>;
+
+            unsafe fn from_foreign(foreign: $tuple<$($f),+>) -> Result<($($l,)+), Self::Error> {
+                let $tuple($($v),+) = foreign;
+                from_foreign_each!($($v: $m,)+);
+                Ok(($($v,)+))
+            }
+        }
+
+        impl<$($m, $f),+> DropForeign<$tuple<$($f),+>> for TupleMarshaler<($($m,)+)>
+        where
+            $($m: DropForeign<$f>,)+
+        {
+            unsafe fn drop_foreign(foreign: $tuple<$($f),+>) {
+                let $tuple($($v),+) = foreign;
+                $($m::drop_foreign($v);)+
+            }
+        }
+    )*};
+}
+
+tuple_marshaler! {
+    Tuple2(a: MA LA FA, b: MB LB FB);
+    Tuple3(a: MA LA FA, b: MB LB FB, c: MC LC FC);
+    Tuple4(a: MA LA FA, b: MB LB FB, c: MC LC FC, d: MD LD FD);
+    Tuple5(a: MA LA FA, b: MB LB FB, c: MC LC FC, d: MD LD FD, e: ME LE FE);
+    Tuple6(a: MA LA FA, b: MB LB FB, c: MC LC FC, d: MD LD FD, e: ME LE FE, f: MF LF FF);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{BoxMarshaler, CopyMarshaler, Slice, StringMarshaler};
+
+    type Marshaler = TupleMarshaler<(StringMarshaler, CopyMarshaler<u32>, BoxMarshaler<u64>)>;
+
+    #[test]
+    fn round_trip() {
+        let foreign: Tuple3<Slice<u8>, u32, *const u64> =
+            Marshaler::to_foreign(("one".to_string(), 2, 3)).unwrap();
+        assert_eq!(foreign.1, 2);
+
+        let local: (String, u32, u64) = unsafe { Marshaler::from_foreign(foreign) }.unwrap();
+        assert_eq!(local, ("one".to_string(), 2, 3));
+    }
+
+    #[test]
+    fn drop_foreign() {
+        let foreign: Tuple3<Slice<u8>, u32, *const u64> =
+            Marshaler::to_foreign(("one".to_string(), 2, 3)).unwrap();
+        unsafe { Marshaler::drop_foreign(foreign) };
+    }
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Fails to convert 0, and counts the values it drops.
+    struct NonZeroCounted;
+
+    impl FromForeign<u32, u32> for NonZeroCounted {
+        type Error = Box<dyn Error>;
+
+        unsafe fn from_foreign(v: u32) -> Result<u32, Self::Error> {
+            match v {
+                0 => Err("zero".into()),
+                v => Ok(v),
+            }
+        }
+    }
+
+    impl DropForeign<u32> for NonZeroCounted {
+        unsafe fn drop_foreign(_: u32) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn from_foreign_failure_drops_the_rest() {
+        type Counted = TupleMarshaler<(NonZeroCounted, NonZeroCounted, NonZeroCounted)>;
+
+        let result: Result<(u32, u32, u32), _> = unsafe { Counted::from_foreign(Tuple3(1, 0, 3)) };
+        assert!(result.is_err());
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    }
+}
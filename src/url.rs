@@ -1,7 +1,7 @@
 use std::error::Error;
 use url::Url;
 
-use super::{FromForeign, InputType, ReturnType, Slice, ToForeign};
+use super::{DropForeign, FromForeign, InputType, ReturnType, Slice, ToForeign};
 
 pub struct UrlMarshaler;
 
@@ -37,7 +37,7 @@ impl ToForeign<Result<Url, Box<dyn Error>>, Slice<u8>> for UrlMarshaler {
 
     #[inline(always)]
     fn to_foreign(result: Result<Url, Box<dyn Error>>) -> Result<Slice<u8>, Self::Error> {
-        result.and_then(|url| Ok(UrlMarshaler::to_foreign(url).unwrap()))
+        result.map(|url| UrlMarshaler::to_foreign(url).unwrap())
     }
 }
 
@@ -54,8 +54,15 @@ impl ToForeign<Option<Url>, Slice<u8>> for UrlMarshaler {
     }
 }
 
+impl DropForeign<Slice<u8>> for UrlMarshaler {
+    #[inline(always)]
+    unsafe fn drop_foreign(slice: Slice<u8>) {
+        crate::VecMarshaler::free(slice)
+    }
+}
+
 // char pointer -> URL
-impl<'a> FromForeign<Slice<u8>, Url> for UrlMarshaler {
+impl FromForeign<Slice<u8>, Url> for UrlMarshaler {
     type Error = Box<dyn Error>;
 
     #[inline(always)]
@@ -0,0 +1,401 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::num::{
+    NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU16, NonZeroU32,
+    NonZeroU64, NonZeroU8, NonZeroUsize,
+};
+use std::ptr::NonNull;
+
+use super::{
+    null_ptr_error, CopyMarshaler, DropForeign, FromForeign, InputType, MarshaledError, ReturnType,
+    ToForeign,
+};
+
+/// A primitive type passed to and from C as it is, which the validating marshalers accept.
+///
+/// Not implemented for `i128` and `u128`, which are passed through [`Int128Marshaler`] rather than
+/// by value.
+///
+/// [`Int128Marshaler`]: crate::Int128Marshaler
+pub trait Primitive: Copy + Default + PartialEq + Display {}
+
+/// A [`Primitive`] integer, which [`RangeMarshaler`] compares to its bounds as an `i128`.
+pub trait Integer: Primitive {
+    fn to_i128(self) -> i128;
+}
+
+/// A [`Primitive`] float, which [`FiniteMarshaler`] checks as an `f64`.
+pub trait Float: Primitive {
+    fn to_f64(self) -> f64;
+}
+
+macro_rules! primitive {
+    ($trait:ident, $method:ident -> $target:ty: $($ty:ty),*) => {
+        $(
+            impl Primitive for $ty {}
+
+            impl $trait for $ty {
+                #[inline(always)]
+                fn $method(self) -> $target {
+                    self as $target
+                }
+            }
+        )*
+    };
+}
+
+primitive!(Integer, to_i128 -> i128: u8, i8, u16, i16, u32, i32, u64, i64, usize, isize);
+primitive!(Float, to_f64 -> f64: f32, f64);
+
+/// Passes an integer through like [`CopyMarshaler`], rejecting values outside `MIN..=MAX`:
+///
+///   - `RangeMarshaler<u8, 0, 100>`: `u8` ↔ `u8`
+///
+/// `#[marshal(Range<0, 100>)]` is shorthand for this on a parameter, with `T` its type.
+pub struct RangeMarshaler<T, const MIN: i128, const MAX: i128>(PhantomData<T>);
+
+impl<T: Integer, const MIN: i128, const MAX: i128> RangeMarshaler<T, MIN, MAX> {
+    fn check(value: T) -> Result<T, MarshaledError> {
+        match (MIN..=MAX).contains(&value.to_i128()) {
+            true => Ok(value),
+            false => Err(MarshaledError(format!(
+                "{} is out of range {}..={}",
+                value, MIN, MAX
+            ))),
+        }
+    }
+}
+
+/// Passes a float through like [`CopyMarshaler`], rejecting NaN and infinities:
+///
+///   - `FiniteMarshaler<f64>`: `f64` ↔ `f64`
+///
+/// `#[marshal(Finite)]` is shorthand for this on a parameter, with `T` its type.
+pub struct FiniteMarshaler<T>(PhantomData<T>);
+
+impl<T: Float> FiniteMarshaler<T> {
+    fn check(value: T) -> Result<T, MarshaledError> {
+        match value.to_f64().is_finite() {
+            true => Ok(value),
+            false => Err(MarshaledError(format!("{} is not finite", value))),
+        }
+    }
+}
+
+/// Passes an integer or float through like [`CopyMarshaler`], rejecting zero:
+///
+///   - `NonZeroMarshaler<u32>`: `u32` ↔ `u32`
+///
+/// `#[marshal(NonZero)]` is shorthand for this on a parameter, with `T` its type. It also takes
+/// the `NonZero` integer types as their plain integer, and is their default marshaler, as they
+/// are only passed through as they are inside an `Option`:
+///
+///   - `NonZeroMarshaler<u32>`: `NonZeroU32` ↔ `u32`
+pub struct NonZeroMarshaler<T>(PhantomData<T>);
+
+impl<T: Primitive> NonZeroMarshaler<T> {
+    fn check(value: T) -> Result<T, MarshaledError> {
+        match value == T::default() {
+            true => Err(MarshaledError("value must not be zero".into())),
+            false => Ok(value),
+        }
+    }
+}
+
+/// Implements the marshaling traits in terms of `CopyMarshaler` and the marshaler's `check`.
+macro_rules! validating_marshaler {
+    ($name:ident<T $(, const $c:ident: $c_ty:ty)*>, $($bound:tt)+) => {
+        impl<T, $(const $c: $c_ty),*> InputType for $name<T, $($c),*>
+        where
+            T: $($bound)+,
+        {
+            type Foreign = T;
+            type ForeignTraitObject = ();
+        }
+
+        impl<T, $(const $c: $c_ty),*> ReturnType for $name<T, $($c),*>
+        where
+            T: $($bound)+,
+        {
+            type Foreign = T;
+            type ForeignTraitObject = ();
+
+            #[inline(always)]
+            fn foreign_default() -> T {
+                CopyMarshaler::<T>::foreign_default()
+            }
+        }
+
+        impl<T, $(const $c: $c_ty),*> FromForeign<T, T> for $name<T, $($c),*>
+        where
+            T: $($bound)+,
+        {
+            type Error = Box<dyn Error>;
+
+            #[inline(always)]
+            unsafe fn from_foreign(value: T) -> Result<T, Self::Error> {
+                let value = match CopyMarshaler::<T>::from_foreign(value) {
+                    Ok(v) => v,
+                    Err(e) => match e {},
+                };
+                Ok(Self::check(value)?)
+            }
+        }
+
+        impl<T, $(const $c: $c_ty),*> ToForeign<T, T> for $name<T, $($c),*>
+        where
+            T: $($bound)+,
+        {
+            type Error = Box<dyn Error>;
+
+            #[inline(always)]
+            fn to_foreign(value: T) -> Result<T, Self::Error> {
+                let value = Self::check(value)?;
+                CopyMarshaler::<T>::to_foreign(value).map_err(|e: Infallible| match e {})
+            }
+        }
+
+        impl<T, $(const $c: $c_ty),*> ToForeign<Result<T, Box<dyn Error>>, T>
+            for $name<T, $($c),*>
+        where
+            T: $($bound)+,
+        {
+            type Error = Box<dyn Error>;
+
+            #[inline(always)]
+            fn to_foreign(result: Result<T, Box<dyn Error>>) -> Result<T, Self::Error> {
+                result.and_then(<Self as ToForeign<T, T>>::to_foreign)
+            }
+        }
+
+        impl<T, $(const $c: $c_ty),*> DropForeign<T> for $name<T, $($c),*>
+        where
+            T: $($bound)+,
+        {
+            #[inline(always)]
+            unsafe fn drop_foreign(_: T) {}
+        }
+    };
+}
+
+validating_marshaler!(RangeMarshaler<T, const MIN: i128, const MAX: i128>, Integer);
+validating_marshaler!(FiniteMarshaler<T>, Float);
+validating_marshaler!(NonZeroMarshaler<T>, Primitive);
+
+/// Lets `NonZeroMarshaler` take each integer's `NonZero` type as the plain integer.
+macro_rules! non_zero_marshaler {
+    ($($int:ty => $non_zero:ty),*) => {$(
+        impl FromForeign<$int, $non_zero> for NonZeroMarshaler<$int> {
+            type Error = Box<dyn Error>;
+
+            #[inline(always)]
+            unsafe fn from_foreign(value: $int) -> Result<$non_zero, Self::Error> {
+                Ok(<$non_zero>::new(Self::check(value)?).unwrap())
+            }
+        }
+
+        impl ToForeign<$non_zero, $int> for NonZeroMarshaler<$int> {
+            type Error = Box<dyn Error>;
+
+            #[inline(always)]
+            fn to_foreign(value: $non_zero) -> Result<$int, Self::Error> {
+                Ok(value.get())
+            }
+        }
+
+        impl ToForeign<Result<$non_zero, Box<dyn Error>>, $int> for NonZeroMarshaler<$int> {
+            type Error = Box<dyn Error>;
+
+            #[inline(always)]
+            fn to_foreign(result: Result<$non_zero, Box<dyn Error>>) -> Result<$int, Self::Error> {
+                result.map(<$non_zero>::get)
+            }
+        }
+    )*};
+}
+
+non_zero_marshaler!(
+    u8 => NonZeroU8,
+    i8 => NonZeroI8,
+    u16 => NonZeroU16,
+    i16 => NonZeroI16,
+    u32 => NonZeroU32,
+    i32 => NonZeroI32,
+    u64 => NonZeroU64,
+    i64 => NonZeroI64,
+    usize => NonZeroUsize,
+    isize => NonZeroIsize
+);
+
+/// Takes a `NonNull<T>` as a raw pointer, rejecting null:
+///
+///   - `NonNullMarshaler<u8>`: `NonNull<u8>` ↔ `*mut u8`
+///
+/// This is the default marshaler for `NonNull<T>`, which is only passed through as it is inside
+/// an `Option`.
+pub struct NonNullMarshaler<T>(PhantomData<T>);
+
+impl<T> InputType for NonNullMarshaler<T> {
+    type Foreign = *mut T;
+    type ForeignTraitObject = ();
+}
+
+impl<T> ReturnType for NonNullMarshaler<T> {
+    type Foreign = *mut T;
+    type ForeignTraitObject = ();
+
+    #[inline(always)]
+    fn foreign_default() -> *mut T {
+        std::ptr::null_mut()
+    }
+}
+
+impl<T> FromForeign<*mut T, NonNull<T>> for NonNullMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(ptr: *mut T) -> Result<NonNull<T>, Self::Error> {
+        match NonNull::new(ptr) {
+            Some(v) => Ok(v),
+            None => Err(null_ptr_error()),
+        }
+    }
+}
+
+impl<T> ToForeign<NonNull<T>, *mut T> for NonNullMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(ptr: NonNull<T>) -> Result<*mut T, Self::Error> {
+        Ok(ptr.as_ptr())
+    }
+}
+
+impl<T> ToForeign<Result<NonNull<T>, Box<dyn Error>>, *mut T> for NonNullMarshaler<T> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(result: Result<NonNull<T>, Box<dyn Error>>) -> Result<*mut T, Self::Error> {
+        result.map(NonNull::as_ptr)
+    }
+}
+
+impl<T> DropForeign<*mut T> for NonNullMarshaler<T> {
+    #[inline(always)]
+    unsafe fn drop_foreign(_: *mut T) {}
+}
+
+/// Takes an `extern "C" fn` as an `Option` of itself, rejecting null:
+///
+///   - `FnPtrMarshaler<extern "C" fn(u32)>`: `extern "C" fn(u32)` ↔ `Option<extern "C" fn(u32)>`
+///
+/// This is the default marshaler for `extern "C" fn`s, which are only passed through as they are
+/// inside an `Option`. `F` must be a function pointer type.
+pub struct FnPtrMarshaler<F>(PhantomData<F>);
+
+impl<F: Copy> InputType for FnPtrMarshaler<F> {
+    type Foreign = Option<F>;
+    type ForeignTraitObject = ();
+}
+
+impl<F: Copy> ReturnType for FnPtrMarshaler<F> {
+    type Foreign = Option<F>;
+    type ForeignTraitObject = ();
+
+    #[inline(always)]
+    fn foreign_default() -> Option<F> {
+        None
+    }
+}
+
+impl<F: Copy> FromForeign<Option<F>, F> for FnPtrMarshaler<F> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    unsafe fn from_foreign(f: Option<F>) -> Result<F, Self::Error> {
+        match f {
+            Some(f) => Ok(f),
+            None => Err(null_ptr_error()),
+        }
+    }
+}
+
+impl<F: Copy> ToForeign<F, Option<F>> for FnPtrMarshaler<F> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(f: F) -> Result<Option<F>, Self::Error> {
+        Ok(Some(f))
+    }
+}
+
+impl<F: Copy> ToForeign<Result<F, Box<dyn Error>>, Option<F>> for FnPtrMarshaler<F> {
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign(result: Result<F, Box<dyn Error>>) -> Result<Option<F>, Self::Error> {
+        result.map(Some)
+    }
+}
+
+impl<F: Copy> DropForeign<Option<F>> for FnPtrMarshaler<F> {
+    #[inline(always)]
+    unsafe fn drop_foreign(_: Option<F>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation() {
+        type Percent = RangeMarshaler<u8, 0, 100>;
+        assert_eq!(unsafe { Percent::from_foreign(100) }.unwrap(), 100);
+        let err = unsafe { Percent::from_foreign(150) }.unwrap_err();
+        assert_eq!(format!("{:?}", err), "150 is out of range 0..=100");
+
+        assert!(unsafe { RangeMarshaler::<i32, -1, 1>::from_foreign(-2) }.is_err());
+        assert!(unsafe { RangeMarshaler::<u64, 0, 1>::from_foreign(u64::MAX) }.is_err());
+        assert!(unsafe { RangeMarshaler::<usize, 0, 1>::from_foreign(1) }.is_ok());
+
+        assert_eq!(
+            unsafe { FiniteMarshaler::<f64>::from_foreign(1.5) }.unwrap(),
+            1.5
+        );
+        let err = unsafe { FiniteMarshaler::<f32>::from_foreign(f32::NAN) }.unwrap_err();
+        assert_eq!(format!("{:?}", err), "NaN is not finite");
+
+        let value: u32 = unsafe { NonZeroMarshaler::<u32>::from_foreign(7) }.unwrap();
+        assert_eq!(value, 7);
+        let value: Result<u32, _> = unsafe { NonZeroMarshaler::<u32>::from_foreign(0) };
+        assert!(value.is_err());
+        assert!(NonZeroMarshaler::<u32>::to_foreign(0u32).is_err());
+    }
+
+    #[test]
+    fn non_zero_types() {
+        let value: NonZeroU32 = unsafe { NonZeroMarshaler::<u32>::from_foreign(7) }.unwrap();
+        assert_eq!(value.get(), 7);
+        let value: Result<NonZeroU32, _> = unsafe { NonZeroMarshaler::<u32>::from_foreign(0) };
+        assert!(value.is_err());
+
+        let mut x = 1u8;
+        let ptr: NonNull<u8> =
+            unsafe { NonNullMarshaler::from_foreign(&mut x as *mut u8) }.unwrap();
+        assert_eq!(ptr.as_ptr(), &mut x as *mut u8);
+        let ptr: Result<NonNull<u8>, _> =
+            unsafe { NonNullMarshaler::from_foreign(std::ptr::null_mut::<u8>()) };
+        assert!(ptr.is_err());
+
+        extern "C" fn double(x: u32) -> u32 {
+            x * 2
+        }
+        type Double = extern "C" fn(u32) -> u32;
+        let f: Double =
+            unsafe { FnPtrMarshaler::<Double>::from_foreign(Some(double as Double)) }.unwrap();
+        assert_eq!(f(2), 4);
+        assert!(unsafe { FnPtrMarshaler::<Double>::from_foreign(None) }.is_err());
+    }
+}
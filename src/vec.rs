@@ -1,11 +1,39 @@
+use std::alloc::Layout;
 use std::convert::Infallible;
 use std::error::Error;
 use std::marker::PhantomData;
 
 use super::null_ptr_error;
-use super::{FromForeign, InputType, ReturnType, Slice, ToForeign};
+use super::{
+    DropForeign, FromForeign, InputType, OutBuffer, ReturnType, Slice, ToForeign, ToForeignBuffer,
+};
+
+/// Marshals an owned `Vec<T>` as a `Slice<T>` allocated by the cffi [`Allocator`](crate::Allocator).
+///
+/// The foreign buffer is always allocated with exactly `len` elements, so its length is also its
+/// capacity. A slice passed back through `from_foreign` or freed must therefore still have the
+/// length it was created with.
+///
+/// ## Freeing `Vec<T>`
+///
+/// Use the per-type free functions (`cffi_vec_free_u32` and friends, or your own exported with
+/// [`vec_free!`](crate::vec_free)), which also drop each element. For any other `T`,
+/// `cffi_vec_free` frees the buffer given the element's size and alignment.
 pub struct VecMarshaler<T>(PhantomData<T>);
 
+impl<T> VecMarshaler<T> {
+    /// Drops a `Vec<T>` previously returned by `to_foreign`. Null slices are ignored.
+    ///
+    /// # Safety
+    ///
+    /// `slice` must have been produced by `VecMarshaler::<T>::to_foreign` and not already freed.
+    pub unsafe fn free(slice: Slice<T>) {
+        if !slice.data.is_null() {
+            drop(crate::alloc::from_foreign_slice(slice));
+        }
+    }
+}
+
 impl<T> InputType for VecMarshaler<T> {
     type Foreign = Slice<T>;
     type ForeignTraitObject = ();
@@ -26,23 +54,36 @@ impl<T> ReturnType for VecMarshaler<T> {
 impl<T> ToForeign<Vec<T>, Slice<T>> for VecMarshaler<T> {
     type Error = Infallible;
 
-    fn to_foreign(mut vec: Vec<T>) -> Result<Slice<T>, Self::Error> {
-        vec.shrink_to_fit();
-        let len = vec.len();
-        let data = vec.as_mut_ptr();
-        std::mem::forget(vec);
-
-        // log::debug!("Vec len: {}", vec.len());
-        // let raw = Box::into_raw(vec.into_boxed_slice());
-        // log::debug!("Raw len: {}", unsafe { (*raw).len() });
-        // log::debug!("???: {}", super::vec_ref::VecRefMarshaler::from_foreign(raw).unwrap().len());
-
-        let raw = Slice { data, len };
+    fn to_foreign(vec: Vec<T>) -> Result<Slice<T>, Self::Error> {
+        let raw = crate::alloc::into_foreign_slice(vec);
         log::debug!("Ptr: {:?}", raw);
         Ok(raw)
     }
 }
 
+impl<T: Copy> ToForeignBuffer<Vec<T>> for VecMarshaler<T> {
+    type Element = T;
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign_buffer(vec: Vec<T>, buffer: &mut OutBuffer<T>) -> Result<(), Self::Error> {
+        buffer.write(&vec)
+    }
+}
+
+impl<T: Copy> ToForeignBuffer<Result<Vec<T>, Box<dyn Error>>> for VecMarshaler<T> {
+    type Element = T;
+    type Error = Box<dyn Error>;
+
+    #[inline(always)]
+    fn to_foreign_buffer(
+        result: Result<Vec<T>, Box<dyn Error>>,
+        buffer: &mut OutBuffer<T>,
+    ) -> Result<(), Self::Error> {
+        result.and_then(|vec| VecMarshaler::to_foreign_buffer(vec, buffer))
+    }
+}
+
 impl<T> FromForeign<Slice<T>, Vec<T>> for VecMarshaler<T> {
     type Error = Box<dyn Error>;
 
@@ -51,14 +92,120 @@ impl<T> FromForeign<Slice<T>, Vec<T>> for VecMarshaler<T> {
             return Err(null_ptr_error());
         }
 
-        // let ptr = unsafe { std::mem::transmute::<*const c_void, *mut [T]>(ptr) };
-        let vec = Vec::from_raw_parts(ptr.data, ptr.len, ptr.len);
+        Ok(crate::alloc::from_foreign_slice(ptr)?)
+    }
+}
 
-        Ok(vec)
+impl<T> DropForeign<Slice<T>> for VecMarshaler<T> {
+    #[inline(always)]
+    unsafe fn drop_foreign(slice: Slice<T>) {
+        VecMarshaler::free(slice)
     }
 }
 
+/// Frees a buffer previously returned by a [`VecMarshaler`] without dropping its elements.
+///
+/// `size` and `align` are those of the element type, i.e. `sizeof(T)` and `alignof(T)` in C.
+///
+/// # Safety
+///
+/// `slice` must have been produced by `VecMarshaler::to_foreign` for an element type with the
+/// given size and alignment, and not already freed.
 #[no_mangle]
-pub unsafe extern "C" fn cffi_vec_free(slice: Slice<libc::c_void>) {
-    Vec::from_raw_parts(slice.data, slice.len, slice.len);
+pub unsafe extern "C" fn cffi_vec_free(slice: Slice<libc::c_void>, size: usize, align: usize) {
+    let layout = match size
+        .checked_mul(slice.len)
+        .and_then(|bytes| Layout::from_size_align(bytes, align).ok())
+    {
+        Some(v) => v,
+        None => {
+            log::error!("cffi_vec_free: invalid layout for {:?}", slice);
+            return;
+        }
+    };
+
+    crate::alloc::free_foreign(slice.data, layout);
+}
+
+crate::vec_free!(cffi_vec_free_u8, u8);
+crate::vec_free!(cffi_vec_free_i8, i8);
+crate::vec_free!(cffi_vec_free_u16, u16);
+crate::vec_free!(cffi_vec_free_i16, i16);
+crate::vec_free!(cffi_vec_free_u32, u32);
+crate::vec_free!(cffi_vec_free_i32, i32);
+crate::vec_free!(cffi_vec_free_u64, u64);
+crate::vec_free!(cffi_vec_free_i64, i64);
+crate::vec_free!(cffi_vec_free_usize, usize);
+crate::vec_free!(cffi_vec_free_isize, isize);
+crate::vec_free!(cffi_vec_free_f32, f32);
+crate::vec_free!(cffi_vec_free_f64, f64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Foo {
+        id: u64,
+        name: String,
+    }
+
+    fn foos() -> Vec<Foo> {
+        (0..3)
+            .map(|id| Foo {
+                id,
+                name: format!("foo {}", id),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_struct() {
+        let slice = VecMarshaler::to_foreign(foos()).unwrap();
+        let vec: Vec<Foo> = unsafe { VecMarshaler::from_foreign(slice) }.unwrap();
+        assert_eq!(vec, foos());
+    }
+
+    #[test]
+    fn round_trip_empty() {
+        let slice = VecMarshaler::to_foreign(Vec::<u32>::new()).unwrap();
+        assert!(!slice.data.is_null());
+        let vec: Vec<u32> = unsafe { VecMarshaler::from_foreign(slice) }.unwrap();
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn free_with_layout() {
+        let slice = VecMarshaler::to_foreign(vec![1u32, 2, 3]).unwrap();
+        unsafe {
+            cffi_vec_free(
+                slice.cast(),
+                std::mem::size_of::<u32>(),
+                std::mem::align_of::<u32>(),
+            )
+        };
+    }
+
+    #[test]
+    fn free_typed() {
+        let slice = VecMarshaler::to_foreign(vec![1.0f64, 2.0]).unwrap();
+        unsafe { cffi_vec_free_f64(slice) };
+
+        let slice = VecMarshaler::to_foreign(foos()).unwrap();
+        unsafe { VecMarshaler::free(slice) };
+    }
+
+    #[test]
+    fn free_null() {
+        unsafe {
+            cffi_vec_free(Slice::default(), 1, 1);
+            cffi_vec_free_u32(Slice::default());
+        }
+    }
+
+    #[test]
+    fn free_string() {
+        let slice = crate::StringMarshaler::to_foreign("hello".to_string()).unwrap();
+        unsafe { crate::ffi::cffi_string_free(slice) };
+    }
 }
@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::marker::PhantomData;
+
+use super::null_ptr_error;
+use super::{DropForeign, FromForeign, InputType, ReturnType, Slice, ToForeign};
+
+/// Marshals a `Vec` element-wise, converting each item with the element marshaler `M`.
+///
+/// Where [`VecMarshaler`](crate::VecMarshaler) hands the `Vec<T>` buffer over as-is (and so only
+/// suits FFI-safe `T`), this builds a new `Slice` of each element's foreign representation:
+///
+///   - `VecOfMarshaler<StringMarshaler>`: `Vec<String>` → `Slice<Slice<u8>>`
+///   - `VecOfMarshaler<BoxMarshaler<T>>`: `Vec<T>` → `Slice<*const T>`
+///
+/// ## Freeing
+///
+/// The result must be freed with [`VecOfMarshaler::free`], which also frees each element using
+/// `M`'s [`DropForeign`] implementation. Export it for your element marshaler with
+/// [`vec_of_free!`](crate::vec_of_free); `cffi_vec_string_free` is provided for `Vec<String>`.
+pub struct VecOfMarshaler<M>(PhantomData<M>);
+
+impl<M> VecOfMarshaler<M> {
+    /// Frees a slice previously returned by `to_foreign`, including each element. Null slices
+    /// are ignored.
+    ///
+    /// # Safety
+    ///
+    /// `slice` must have been produced by `VecOfMarshaler::<M>::to_foreign` and not already freed.
+    pub unsafe fn free<F>(slice: Slice<F>)
+    where
+        M: DropForeign<F>,
+    {
+        if slice.data.is_null() {
+            return;
+        }
+
+        if let Ok(items) = crate::alloc::from_foreign_slice(slice) {
+            for item in items {
+                M::drop_foreign(item);
+            }
+        }
+    }
+}
+
+impl<M: InputType> InputType for VecOfMarshaler<M> {
+    type Foreign = Slice<M::Foreign>;
+    type ForeignTraitObject = ();
+}
+
+impl<M: ReturnType> ReturnType for VecOfMarshaler<M> {
+    type Foreign = Slice<M::Foreign>;
+    type ForeignTraitObject = ();
+
+    fn foreign_default() -> Self::Foreign {
+        Slice::default()
+    }
+}
+
+impl<M, L, F> ToForeign<Vec<L>, Slice<F>> for VecOfMarshaler<M>
+where
+    M: ToForeign<L, F> + DropForeign<F>,
+    M::Error: Into<Box<dyn Error>>,
+{
+    type Error = Box<dyn Error>;
+
+    fn to_foreign(vec: Vec<L>) -> Result<Slice<F>, Self::Error> {
+        let mut items = Vec::with_capacity(vec.len());
+
+        for item in vec {
+            match M::to_foreign(item) {
+                Ok(v) => items.push(v),
+                Err(e) => {
+                    for item in items {
+                        unsafe { M::drop_foreign(item) };
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(crate::alloc::into_foreign_slice(items))
+    }
+}
+
+impl<M, L, F> ToForeign<Result<Vec<L>, Box<dyn Error>>, Slice<F>> for VecOfMarshaler<M>
+where
+    M: ToForeign<L, F> + DropForeign<F>,
+    M::Error: Into<Box<dyn Error>>,
+{
+    type Error = Box<dyn Error>;
+
+    fn to_foreign(result: Result<Vec<L>, Box<dyn Error>>) -> Result<Slice<F>, Self::Error> {
+        result.and_then(VecOfMarshaler::<M>::to_foreign)
+    }
+}
+
+impl<M, L, F> FromForeign<Slice<F>, Vec<L>> for VecOfMarshaler<M>
+where
+    M: FromForeign<F, L> + DropForeign<F>,
+    M::Error: Into<Box<dyn Error>>,
+{
+    type Error = Box<dyn Error>;
+
+    unsafe fn from_foreign(slice: Slice<F>) -> Result<Vec<L>, Self::Error> {
+        if slice.data.is_null() {
+            return Err(null_ptr_error());
+        }
+
+        let mut items = crate::alloc::from_foreign_slice(slice)?.into_iter();
+        let mut vec = Vec::with_capacity(items.len());
+
+        for item in items.by_ref() {
+            match M::from_foreign(item) {
+                Ok(v) => vec.push(v),
+                Err(e) => {
+                    // The failed item has been consumed; release the ones not yet converted.
+                    for item in items {
+                        M::drop_foreign(item);
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(vec)
+    }
+}
+
+crate::vec_of_free!(cffi_vec_string_free, crate::StringMarshaler);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BoxMarshaler, StringMarshaler};
+
+    fn strings() -> Vec<String> {
+        vec!["one".into(), "two".into(), "".into()]
+    }
+
+    #[test]
+    fn round_trip_strings() {
+        let slice = VecOfMarshaler::<StringMarshaler>::to_foreign(strings()).unwrap();
+        assert_eq!(slice.len, 3);
+        let vec: Vec<String> =
+            unsafe { VecOfMarshaler::<StringMarshaler>::from_foreign(slice) }.unwrap();
+        assert_eq!(vec, strings());
+    }
+
+    #[test]
+    fn free_strings() {
+        let slice = VecOfMarshaler::<StringMarshaler>::to_foreign(strings()).unwrap();
+        unsafe { cffi_vec_string_free(slice) };
+    }
+
+    #[test]
+    fn free_handles() {
+        let slice = VecOfMarshaler::<BoxMarshaler<String>>::to_foreign(strings()).unwrap();
+        unsafe { VecOfMarshaler::<BoxMarshaler<String>>::free(slice) };
+    }
+
+    #[test]
+    fn invalid_element() {
+        let bytes = crate::VecMarshaler::to_foreign(vec![0xffu8]).unwrap();
+        let valid = StringMarshaler::to_foreign("valid".to_string()).unwrap();
+        let slice = crate::alloc::into_foreign_slice(vec![bytes, valid]);
+        let result: Result<Vec<String>, _> =
+            unsafe { VecOfMarshaler::<StringMarshaler>::from_foreign(slice) };
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::alloc::Layout;
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::sync::Once;
+
+use cffi::ffi::cffi_set_allocator;
+use cffi::{FromForeign, StringMarshaler, ToForeign, VecMarshaler};
+
+thread_local! {
+    /// The `(size, align)` of each allocation and free made on this thread.
+    static ALLOCS: RefCell<Vec<(usize, usize)>> = const { RefCell::new(vec![]) };
+    static FREES: RefCell<Vec<(usize, usize)>> = const { RefCell::new(vec![]) };
+}
+
+unsafe extern "C" fn counting_alloc(size: usize, align: usize) -> *mut c_void {
+    ALLOCS.with(|allocs| allocs.borrow_mut().push((size, align)));
+    std::alloc::alloc(Layout::from_size_align(size, align).unwrap()).cast()
+}
+
+unsafe extern "C" fn counting_free(ptr: *mut c_void, size: usize, align: usize) {
+    FREES.with(|frees| frees.borrow_mut().push((size, align)));
+    std::alloc::dealloc(ptr.cast(), Layout::from_size_align(size, align).unwrap());
+}
+
+fn setup() {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| {
+        assert_eq!(cffi_set_allocator(None, Some(counting_free)), 0);
+        assert_eq!(
+            cffi_set_allocator(Some(counting_alloc), Some(counting_free)),
+            1
+        );
+    });
+}
+
+fn allocs() -> Vec<(usize, usize)> {
+    ALLOCS.with(|allocs| allocs.borrow_mut().split_off(0))
+}
+
+fn frees() -> Vec<(usize, usize)> {
+    FREES.with(|frees| frees.borrow_mut().split_off(0))
+}
+
+#[test]
+fn allocator_is_fixed() {
+    setup();
+    assert_eq!(
+        cffi_set_allocator(Some(counting_alloc), Some(counting_free)),
+        0
+    );
+    assert!(cffi::set_allocator(cffi::Allocator::RUST).is_err());
+}
+
+#[test]
+fn round_trip() {
+    setup();
+
+    let slice = VecMarshaler::to_foreign(vec![1u32, 2, 3]).unwrap();
+    assert_eq!(allocs(), [(12, 4)]);
+    assert_eq!(slice.as_ref(), [1, 2, 3]);
+
+    let vec: Vec<u32> = unsafe { VecMarshaler::from_foreign(slice) }.unwrap();
+    assert_eq!(vec, [1, 2, 3]);
+    assert_eq!(frees(), [(12, 4)]);
+}
+
+#[test]
+fn free_function() {
+    setup();
+
+    let slice = StringMarshaler::to_foreign("allocated".to_string()).unwrap();
+    assert_eq!(allocs(), [(9, 1)]);
+
+    unsafe { cffi::ffi::cffi_string_free(slice) };
+    assert_eq!(frees(), [(9, 1)]);
+}
+
+#[test]
+fn zero_size() {
+    setup();
+
+    let slice = VecMarshaler::to_foreign(Vec::<u64>::new()).unwrap();
+    assert!(!slice.data.is_null());
+    assert_eq!(allocs(), [(1, 8)]);
+
+    let vec: Vec<u64> = unsafe { VecMarshaler::from_foreign(slice) }.unwrap();
+    assert!(vec.is_empty());
+    assert_eq!(frees(), [(1, 8)]);
+}
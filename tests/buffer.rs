@@ -0,0 +1,125 @@
+use std::cell::RefCell;
+use std::ptr;
+
+use cffi::{FromForeign, ToForeign};
+
+thread_local! {
+    static ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+extern "C" fn error(data: *const u8, len: usize) {
+    let message = unsafe { std::slice::from_raw_parts(data, len) };
+    let message = String::from_utf8_lossy(message).into_owned();
+    ERROR.with(|error| *error.borrow_mut() = Some(message));
+}
+
+fn take_error() -> Option<String> {
+    ERROR.with(|error| error.borrow_mut().take())
+}
+
+#[cffi::marshal(buffer)]
+pub fn greeting(name: String) -> String {
+    format!("hello {}", name)
+}
+
+pub struct Greeter {
+    greeting: String,
+}
+
+#[cffi::marshal(buffer)]
+impl Greeter {
+    #[marshal(cffi::BoxMarshaler::<Greeter>)]
+    pub fn new(greeting: String) -> Greeter {
+        Greeter { greeting }
+    }
+
+    pub fn greet(&self, name: String) -> String {
+        format!("{} {}", self.greeting, name)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.greeting.len() as u32
+    }
+}
+
+fn name(name: &str) -> cffi::Slice<u8> {
+    cffi::StringMarshaler::to_foreign(name.to_string()).unwrap()
+}
+
+#[test]
+fn exact_fit() {
+    let mut buf = [0u8; 11];
+    let mut len = 0;
+    greeting(
+        name("world"),
+        Some(error),
+        buf.as_mut_ptr(),
+        buf.len(),
+        &mut len,
+    );
+
+    assert_eq!(take_error(), None);
+    assert_eq!(len, 11);
+    assert_eq!(&buf, b"hello world");
+}
+
+#[test]
+fn too_small() {
+    let mut buf = [0u8; 4];
+    let mut len = 0;
+    greeting(
+        name("world"),
+        Some(error),
+        buf.as_mut_ptr(),
+        buf.len(),
+        &mut len,
+    );
+
+    let error = take_error().unwrap();
+    assert!(error.contains("required: 11"), "{}", error);
+    assert_eq!(len, 11);
+    assert_eq!(buf, [0; 4]);
+}
+
+#[test]
+fn length_query() {
+    let mut len = 0;
+    greeting(name("world"), Some(error), ptr::null_mut(), 0, &mut len);
+
+    assert!(take_error().is_some());
+    assert_eq!(len, 11);
+}
+
+#[test]
+fn impl_methods() {
+    let greeter = greeter_new(name("hi"), Some(error)) as *mut Greeter;
+    assert_eq!(greeter_size(greeter, Some(error)), 2);
+
+    let mut len = 0;
+    greeter_greet(
+        greeter,
+        name("world"),
+        Some(error),
+        ptr::null_mut(),
+        0,
+        &mut len,
+    );
+    assert!(take_error().is_some());
+    assert_eq!(len, 8);
+
+    let mut buf = vec![0u8; len];
+    greeter_greet(
+        greeter,
+        name("world"),
+        Some(error),
+        buf.as_mut_ptr(),
+        buf.len(),
+        &mut len,
+    );
+    assert_eq!(take_error(), None);
+    assert_eq!(buf, b"hi world");
+
+    let greeter: Box<Greeter> =
+        unsafe { cffi::BoxMarshaler::from_foreign(greeter as *const Greeter) }.unwrap();
+    drop(greeter);
+}
@@ -0,0 +1,101 @@
+use std::error::Error;
+use std::ffi::c_void;
+
+use cffi::ffi::cffi_alloc;
+use cffi::{FromForeign, Slice, StrMarshaler};
+
+#[cffi::foreign_trait]
+pub trait Store {
+    fn get(&self, key: &str) -> Result<String, Box<dyn Error>>;
+    fn count(&mut self) -> u32;
+}
+
+struct State {
+    count: u32,
+    released: bool,
+}
+
+/// Copies `bytes` into a buffer from the cffi allocator, as a C implementation would.
+unsafe fn allocated(bytes: &[u8]) -> Slice<u8> {
+    let data = cffi_alloc(bytes.len(), 1).cast::<u8>();
+    assert!(!data.is_null());
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), data, bytes.len());
+    Slice {
+        data,
+        len: bytes.len(),
+    }
+}
+
+unsafe extern "C" fn get(_userdata: *mut c_void, key: Slice<u8>) -> Slice<u8> {
+    let key: &str = StrMarshaler::from_foreign(key).unwrap();
+    match key {
+        // Not valid UTF-8, so it fails to marshal back into a `String`.
+        "invalid" => allocated(&[0xff]),
+        "" => allocated(b""),
+        key => allocated(key.to_uppercase().as_bytes()),
+    }
+}
+
+unsafe extern "C" fn count(userdata: *mut c_void) -> u32 {
+    let state = &mut *userdata.cast::<State>();
+    state.count += 1;
+    state.count
+}
+
+unsafe extern "C" fn release(userdata: *mut c_void) {
+    (*userdata.cast::<State>()).released = true;
+}
+
+fn vtable(state: &mut State) -> StoreVtable {
+    StoreVtable {
+        userdata: (state as *mut State).cast(),
+        get: Some(get),
+        count: Some(count),
+        release: Some(release),
+    }
+}
+
+#[test]
+fn calls_through_vtable() {
+    let mut state = State {
+        count: 0,
+        released: false,
+    };
+
+    let mut store: Box<dyn Store> =
+        unsafe { StoreMarshaler::from_foreign(vtable(&mut state)) }.unwrap();
+    assert_eq!(store.get("key").unwrap(), "KEY");
+    assert_eq!(store.get("").unwrap(), "");
+    assert_eq!(store.count(), 1);
+    assert_eq!(store.count(), 2);
+
+    drop(store);
+    assert!(state.released);
+}
+
+#[test]
+fn marshaling_failure() {
+    let mut state = State {
+        count: 0,
+        released: false,
+    };
+
+    let store: ForeignStore = unsafe { StoreMarshaler::from_foreign(vtable(&mut state)) }.unwrap();
+    assert!(store.get("invalid").is_err());
+}
+
+#[test]
+fn missing_method() {
+    let mut state = State {
+        count: 0,
+        released: false,
+    };
+
+    let vtable = StoreVtable {
+        count: None,
+        ..vtable(&mut state)
+    };
+    let result: Result<ForeignStore, _> = unsafe { StoreMarshaler::from_foreign(vtable) };
+    assert!(result.is_err());
+    assert!(state.released);
+}
@@ -0,0 +1,85 @@
+use cffi::{FromForeign, Slice, StringMarshaler, ToForeign};
+
+#[cffi::marshal]
+pub fn entries(prefix: String) -> impl Iterator<Item = String> {
+    (1..=2).map(move |i| format!("{}{}", prefix, i))
+}
+
+#[cffi::marshal]
+pub fn squares(count: u32) -> Box<dyn Iterator<Item = u32>> {
+    Box::new((1..=count).map(|i| i * i))
+}
+
+pub struct Directory {
+    names: Vec<String>,
+}
+
+#[cffi::marshal]
+impl Directory {
+    #[marshal(cffi::BoxMarshaler::<Directory>)]
+    pub fn new(name: String) -> Directory {
+        Directory {
+            names: vec![name.clone(), name.to_uppercase()],
+        }
+    }
+
+    // The cursor outlives the call, so the iterator owns a copy of the names.
+    pub fn names(&self) -> impl Iterator<Item = String> {
+        self.names.clone().into_iter()
+    }
+}
+
+#[test]
+fn strings() {
+    let prefix = StringMarshaler::to_foreign("entry".to_string()).unwrap();
+    let cursor = entries(prefix, None);
+    assert!(!cursor.is_null());
+
+    let mut items = vec![];
+    let mut out = Slice::default();
+    while entries_next(cursor, &mut out, None) == 1 {
+        let item = std::mem::take(&mut out);
+        let item: String = unsafe { StringMarshaler::from_foreign(item) }.unwrap();
+        items.push(item);
+    }
+
+    assert_eq!(items, ["entry1", "entry2"]);
+    entries_free(cursor);
+}
+
+#[test]
+fn passthrough_items() {
+    let cursor = squares(3, None);
+
+    let mut out = 0;
+    assert_eq!(squares_next(cursor, &mut out, None), 1);
+    assert_eq!(out, 1);
+    assert_eq!(squares_next(cursor, &mut out, None), 1);
+    assert_eq!(out, 4);
+
+    // Freed before it is exhausted.
+    squares_free(cursor);
+}
+
+#[test]
+fn impl_method() {
+    let name = StringMarshaler::to_foreign("dir".to_string()).unwrap();
+    let directory = directory_new(name, None) as *mut Directory;
+    let cursor = directory_names(directory, None);
+
+    // The cursor does not borrow the directory, so it may outlive it.
+    let directory: Box<Directory> =
+        unsafe { cffi::BoxMarshaler::from_foreign(directory as *const Directory) }.unwrap();
+    drop(directory);
+
+    let mut items = vec![];
+    let mut out = Slice::default();
+    while directory_names_next(cursor, &mut out, None) == 1 {
+        let item = std::mem::take(&mut out);
+        let item: String = unsafe { StringMarshaler::from_foreign(item) }.unwrap();
+        items.push(item);
+    }
+
+    assert_eq!(items, ["dir", "DIR"]);
+    directory_names_free(cursor);
+}
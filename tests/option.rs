@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use cffi::{FromForeign, Slice, StringMarshaler, ToForeign, VecMarshaler};
+
+#[cffi::marshal]
+pub fn greeting(name: Option<String>) -> String {
+    format!("hello {}", name.as_deref().unwrap_or("stranger"))
+}
+
+#[cffi::marshal]
+pub fn bytes(count: u32) -> Option<Vec<u8>> {
+    match count {
+        0 => None,
+        count => Some(vec![1; count as usize]),
+    }
+}
+
+#[cffi::marshal]
+pub fn has_path(path: Option<PathBuf>) -> bool {
+    path.is_some()
+}
+
+fn string(slice: Slice<u8>) -> String {
+    unsafe { StringMarshaler::from_foreign(slice) }.unwrap()
+}
+
+#[test]
+fn input() {
+    let name = StringMarshaler::to_foreign("world".to_string()).unwrap();
+    assert_eq!(string(greeting(name, None)), "hello world");
+    assert_eq!(string(greeting(Slice::default(), None)), "hello stranger");
+
+    assert_eq!(has_path(Slice::default(), None), 0);
+}
+
+#[test]
+fn output() {
+    let slice = bytes(2, None);
+    let vec: Vec<u8> = unsafe { VecMarshaler::from_foreign(slice) }.unwrap();
+    assert_eq!(vec, [1, 1]);
+
+    assert!(bytes(0, None).data.is_null());
+}
@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+use std::num::NonZeroU32;
+use std::ptr::{self, NonNull};
+
+use cffi::FromForeign;
+
+thread_local! {
+    static ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+extern "C" fn error(data: *const u8, len: usize) {
+    let message = unsafe { std::slice::from_raw_parts(data, len) };
+    let message = String::from_utf8_lossy(message).into_owned();
+    ERROR.with(|error| *error.borrow_mut() = Some(message));
+}
+
+fn take_error() -> Option<String> {
+    ERROR.with(|error| error.borrow_mut().take())
+}
+
+#[repr(C)]
+#[cffi::passthrough]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[cffi::marshal]
+pub fn length(point: Point) -> f32 {
+    (point.x * point.x + point.y * point.y).sqrt()
+}
+
+#[cffi::marshal]
+pub fn halve(value: NonZeroU32) -> u32 {
+    value.get() / 2
+}
+
+#[cffi::marshal]
+pub fn first(data: NonNull<u8>) -> u8 {
+    unsafe { *data.as_ptr() }
+}
+
+#[cffi::marshal]
+pub fn apply(f: extern "C" fn(u32) -> u32, value: u32) -> u32 {
+    f(value)
+}
+
+#[cffi::marshal]
+pub fn apply_or(f: Option<extern "C" fn(u32) -> u32>, value: u32) -> u32 {
+    f.map_or(value, |f| f(value))
+}
+
+extern "C" fn double(x: u32) -> u32 {
+    x * 2
+}
+
+#[test]
+fn declared_types() {
+    assert_eq!(length(Point { x: 3.0, y: 4.0 }), 5.0);
+}
+
+#[test]
+fn non_zero() {
+    assert_eq!(halve(8, Some(error)), 4);
+    assert_eq!(take_error(), None);
+
+    assert_eq!(halve(0, Some(error)), 0);
+    assert!(take_error().is_some());
+}
+
+#[test]
+fn non_null() {
+    let mut value = 7u8;
+    assert_eq!(first(&mut value, Some(error)), 7);
+    assert_eq!(take_error(), None);
+
+    first(ptr::null_mut(), Some(error));
+    assert!(take_error().unwrap().contains("null pointer"));
+}
+
+#[test]
+fn function_pointers() {
+    assert_eq!(apply(Some(double), 2, Some(error)), 4);
+    assert_eq!(take_error(), None);
+
+    apply(None, 2, Some(error));
+    assert!(take_error().is_some());
+
+    assert_eq!(apply_or(Some(double), 2), 4);
+    assert_eq!(apply_or(None, 2), 2);
+}
@@ -0,0 +1,26 @@
+use cffi::{FromForeign, StringMarshaler, ToForeign};
+
+#[cffi::marshal]
+pub fn describe(
+    #[marshal(split)] name: &str,
+    #[marshal(cffi::VecRefMarshaler::<u32>, split)] values: &[u32],
+) -> String {
+    format!("{}: {}", name, values.iter().sum::<u32>())
+}
+
+fn call(name: &str, values: &[u32]) -> String {
+    let result = describe(
+        name.as_ptr(),
+        name.len(),
+        values.as_ptr(),
+        values.len(),
+        None,
+    );
+    unsafe { StringMarshaler::from_foreign(result) }.unwrap()
+}
+
+#[test]
+fn split_params() {
+    assert_eq!(call("total", &[1, 2, 3]), "total: 6");
+    assert_eq!(call("", &[]), ": 0");
+}